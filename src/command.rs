@@ -0,0 +1,140 @@
+use crate::error::Error;
+use crate::server::api_key::ApiKeyFile;
+use crate::util::jwt::JsonValue;
+use getopts::Options;
+use std::time::SystemTime;
+
+///
+/// Run the command in the arguments, if any. Returns the exit code
+///
+/// The commands manage the files of the gatekeeper, without starting the server
+///
+pub fn run() -> Option<i32> {
+	let args: Vec<String> = std::env::args().collect();
+	let result = match args.get(1).map(|a| a.as_str()) {
+		Some("api-key") => api_key(&args[2..]),
+		_ => return None,
+	};
+	match result {
+		Ok(()) => Some(0),
+		Err(Error::SettingsShowHelpError) => Some(1),
+		Err(error) => {
+			eprintln!("Error: {:?}", error);
+			Some(1)
+		}
+	}
+}
+
+///
+/// Create, list and revoke the api keys
+///
+fn api_key(args: &[String]) -> Result<(), Error> {
+	let mut opts = Options::new();
+	opts.optflag("h", "help", "Show help");
+	opts.optopt("", "file", "FILE with the api keys", "FILE");
+	opts.optmulti("", "claim", "CLAIM of the userinfo. Ex: 'sub=ci'", "CLAIM");
+	opts.optmulti("", "role", "ROLE of the key", "ROLE");
+	opts.optopt("", "expires-in", "DAYS until the key expires", "DAYS");
+	opts.optmulti(
+		"",
+		"network",
+		"Only allow the key from the NETWORK (CIDR)",
+		"NETWORK",
+	);
+	let usage = "Usage: auth-gatekeeper api-key (create|list|revoke ID) --file FILE [options]";
+	let matches = match opts.parse(args) {
+		Ok(matches) => matches,
+		Err(error) => {
+			eprintln!("{}", error);
+			print!("{}", opts.usage(usage));
+			return Err(Error::SettingsShowHelpError);
+		}
+	};
+	let file = match matches.opt_str("file") {
+		Some(file) if !matches.opt_present("h") => file,
+		_ => {
+			print!("{}", opts.usage(usage));
+			return Err(Error::SettingsShowHelpError);
+		}
+	};
+
+	let mut key_file = ApiKeyFile::load(&file)?;
+	match (
+		matches.free.first().map(|a| a.as_str()),
+		matches.free.get(1),
+	) {
+		(Some("create"), None) => {
+			let mut claims = serde_json::Map::new();
+			for claim in matches.opt_strs("claim") {
+				let mut claim_split = claim.splitn(2, '=');
+				let name = claim_split.next().unwrap_or("").trim().to_owned();
+				let value = claim_split.next().unwrap_or("").trim().to_owned();
+				claims.insert(name, JsonValue::String(value));
+			}
+			if !claims.contains_key("sub") {
+				eprintln!("The sub claim is required. Ex: --claim sub=ci");
+				return Err(Error::SettingsError("Missing the sub claim"));
+			}
+			let expires_at = match matches.opt_str("expires-in") {
+				Some(days) => {
+					let days: u64 = days
+						.parse()
+						.map_err(|_| Error::SettingsError("Invalid expiration"))?;
+					Some(now() + days * 24 * 60 * 60)
+				}
+				None => None,
+			};
+			let random = ring::rand::SystemRandom::new();
+			let (entry, key) = key_file.create(
+				&random,
+				JsonValue::Object(claims),
+				matches.opt_strs("role"),
+				expires_at,
+				matches.opt_strs("network"),
+			)?;
+			key_file.save(&file)?;
+			eprintln!(
+				"Created the api key {}. It will not be shown again",
+				entry.id
+			);
+			println!("{}", key);
+		}
+		(Some("list"), None) => {
+			println!("ID\tEXPIRES\tROLES\tCLAIMS");
+			for entry in key_file.keys.iter() {
+				let expires = match entry.expires_at {
+					Some(expires_at) if expires_at <= now() => String::from("expired"),
+					Some(expires_at) => format!("in {} days", (expires_at - now()) / 86400),
+					None => String::from("never"),
+				};
+				println!(
+					"{}\t{}\t{}\t{}",
+					entry.id,
+					expires,
+					entry.roles.join(","),
+					entry.claims
+				);
+			}
+		}
+		(Some("revoke"), Some(id)) => {
+			if !key_file.revoke(id) {
+				eprintln!("Api key {} not found", id);
+				return Err(Error::SettingsError("Api key not found"));
+			}
+			key_file.save(&file)?;
+			eprintln!("Revoked the api key {}", id);
+		}
+		_ => {
+			print!("{}", opts.usage(usage));
+			return Err(Error::SettingsShowHelpError);
+		}
+	}
+	Ok(())
+}
+
+fn now() -> u64 {
+	SystemTime::now()
+		.duration_since(SystemTime::UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0)
+}
@@ -20,6 +20,12 @@ pub enum Error {
 	RequestJsonError(actix_web::client::JsonPayloadError),
 
 	ApiError,
+
+	ProviderStatus(u16),
+
+	LdapError(ldap3::LdapError),
+
+	BlockingCanceled,
 }
 
 impl std::error::Error for Error {}
@@ -42,6 +48,8 @@ impl std::fmt::Display for Error {
 			Error::CryptoRandomBytesError => String::from("Error creating the nonce"),
 
 			Error::SettingsConfigError(ref error) => format!("Config Error: {}", error.to_string()),
+			Error::ProviderStatus(status) => format!("Provider Error: status {}", status),
+			Error::LdapError(ref error) => format!("LDAP Error: {}", error),
 			_ => String::from("Error"),
 		};
 		write!(f, "{}", message)
@@ -61,6 +69,14 @@ impl From<jsonwebtoken::errors::Error> for Error {
 	}
 }
 
+/// Key parsing error
+impl From<openssl::error::ErrorStack> for Error {
+	fn from(error: openssl::error::ErrorStack) -> Error {
+		log::error!("OpenSSL error: {}", error);
+		Error::CryptoError
+	}
+}
+
 /// JSON serialize/dererialize error
 impl From<serde_json::Error> for Error {
 	fn from(error: serde_json::Error) -> Error {
@@ -91,3 +107,13 @@ impl From<url::ParseError> for Error {
 		Error::SettingsUrlParseError(error)
 	}
 }
+
+/// LDAP error, from the blocking calls
+impl From<actix_web::error::BlockingError<ldap3::LdapError>> for Error {
+	fn from(error: actix_web::error::BlockingError<ldap3::LdapError>) -> Error {
+		match error {
+			actix_web::error::BlockingError::Error(error) => Error::LdapError(error),
+			actix_web::error::BlockingError::Canceled => Error::BlockingCanceled,
+		}
+	}
+}
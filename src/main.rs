@@ -2,6 +2,7 @@
 extern crate bitflags;
 
 mod api;
+mod command;
 mod error;
 mod provider;
 mod server;
@@ -19,15 +20,39 @@ use actix_web::{http::Uri, App, HttpServer};
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
 	env_logger::init();
+	if let Some(code) = command::run() {
+		std::process::exit(code);
+	}
 	let random = util::crypto::Crypto::create_random();
+	let stats = server::stats::Stats::create();
+	let replay = util::replay::ReplayCache::create();
+	let basic_auth = server::basic_auth::BasicAuthState::create(&random)
+		.unwrap_or_else(|e| panic!("Could not create the basic authentication: {:?}", e));
 	let settings = settings::Settings::new(random.as_ref());
+	let jwt_keys = settings.jwt_key.as_deref().map(|files| {
+		let keys = util::jwt::JwtKeys::create(files)
+			.unwrap_or_else(|e| panic!("Invalid JWT keys: {:?}", e));
+		util::jwt::JwtKeys::watch(&keys);
+		keys
+	});
 	let listen = settings.listen.clone();
+	let tls_settings = settings.tls.clone();
 	let mut server = HttpServer::new(move || {
-		let handler = server::handler::Handler::new(random.clone(), settings.clone()).unwrap();
+		let handler = server::handler::Handler::new(
+			random.clone(),
+			stats.clone(),
+			replay.clone(),
+			basic_auth.clone(),
+			jwt_keys.clone(),
+			settings.clone(),
+		)
+		.unwrap();
 		App::new().configure(|cfg| handler.config(cfg).unwrap())
-	});
+	})
+	.on_connect(server::client_cert::on_connect);
 
 	// Check the urls to listen to
+	let mut tls: Option<server::tls::TlsPtr> = None;
 	let listen_list = listen.split_terminator(',');
 	for listen in listen_list {
 		let url = listen.parse::<Uri>();
@@ -41,6 +66,18 @@ async fn main() -> std::io::Result<()> {
 			let addr = format!("{}:{}", url.host().unwrap(), url.port_u16().unwrap_or(80));
 			log::info!("Listening on http://{}", addr);
 			server = server.bind(addr)?;
+		} else if scheme == "https" {
+			let addr = format!("{}:{}", url.host().unwrap(), url.port_u16().unwrap_or(443));
+			if tls.is_none() {
+				let created = server::tls::Tls::create(&tls_settings)
+					.unwrap_or_else(|e| panic!("Invalid TLS settings: {:?}", e));
+				server::tls::Tls::watch(&created);
+				tls = Some(created);
+			}
+			let acceptor = server::tls::Tls::acceptor(tls.as_ref().unwrap())
+				.unwrap_or_else(|e| panic!("Invalid TLS settings: {:?}", e));
+			log::info!("Listening on https://{}", addr);
+			server = server.bind_openssl(addr, acceptor)?;
 		} else if scheme == "unix" {
 			log::info!("Listening on unix:{}", url.path());
 			server = server.bind_uds(url.path())?;
@@ -0,0 +1,427 @@
+use super::base::{AccessToken, DeviceAuthorization, DeviceGrant, Provider, TokenSet, Userinfo};
+use super::id_token::IdTokenVerifier;
+use super::oauth2::{post_json, request_json};
+use super::oidc::{parse_url, ProviderOIDC, ProviderOIDCOptions};
+use crate::error::Error;
+use crate::settings::Settings;
+use crate::util::jwt::JsonValue;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// The userinfo of the grants is kept while the access token is valid, when it has no expiration
+const GRANT_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+/// The userinfo requested from Graph (Ex: tokens granted by another worker)
+const GRAPH_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+/// Claims copied from the tokens or the Graph userinfo
+const CLAIMS: [&str; 10] = [
+	"sub",
+	"oid",
+	"tid",
+	"name",
+	"given_name",
+	"family_name",
+	"preferred_username",
+	"email",
+	"picture",
+	"roles",
+];
+
+///
+/// Microsoft Entra ID (Azure AD) provider, using the v2.0 endpoints of the tenant
+///
+/// The claims come from the verified id_token of the grants. When the user has too many groups
+/// for the token (group overage), they are resolved using Graph and the access token.
+///
+pub struct ProviderAzure {
+	client: ProviderOIDC,
+	verifier: IdTokenVerifier,
+	client_id: String,
+	authority: String,
+	issuer: Option<String>,
+	tenant_id: Option<String>,
+	graph_url: Url,
+	cache: Mutex<HashMap<Vec<u8>, (JsonValue, Instant)>>,
+}
+
+impl ProviderAzure {
+	///
+	/// Create a new Azure provider, using the endpoints of the tenant by default
+	///
+	pub fn new(settings: &Settings) -> Result<Self, Error> {
+		let tenant = match settings.provider.tenant {
+			Some(ref tenant) if !tenant.is_empty() => tenant.clone(),
+			_ => return Err(Error::SettingsError("Missing the provider tenant")),
+		};
+		let mut graph_url = Url::parse(
+			settings
+				.provider
+				.api_url
+				.as_deref()
+				.unwrap_or("https://graph.microsoft.com"),
+		)?;
+		if !graph_url.path().ends_with('/') {
+			graph_url.set_path(&format!("{}/", graph_url.path()));
+		}
+		let mut settings = settings.clone();
+		let provider = &mut settings.provider;
+		let base_url = format!("https://login.microsoftonline.com/{}", tenant);
+		provider
+			.auth_url
+			.get_or_insert_with(|| format!("{}/oauth2/v2.0/authorize", base_url));
+		provider
+			.token_url
+			.get_or_insert_with(|| format!("{}/oauth2/v2.0/token", base_url));
+		provider
+			.device_auth_url
+			.get_or_insert_with(|| format!("{}/oauth2/v2.0/devicecode", base_url));
+		provider
+			.jwks_url
+			.get_or_insert_with(|| format!("{}/discovery/v2.0/keys", base_url));
+
+		let client = ProviderOIDC::new(
+			&settings,
+			ProviderOIDCOptions {
+				userinfo_from_access_token: false,
+				default_scope: "openid email profile offline_access User.Read",
+				auth_params: Vec::new(),
+			},
+		)?;
+		// The issuer has the tenant id, even when logging in with a domain or `organizations`
+		let authority = parse_url(&settings.provider.auth_url, "Missing the provider auth url")?
+			.origin()
+			.ascii_serialization();
+		let issuer = settings.provider.issuer.clone();
+		let verifier = IdTokenVerifier::new(
+			parse_url(&settings.provider.jwks_url, "Missing the provider jwks url")?,
+			issuer.iter().cloned().collect(),
+			settings.provider.client_id.clone(),
+		);
+		let tenant_id = Some(tenant.to_lowercase()).filter(|t| Self::is_tenant_id(t));
+		Ok(Self {
+			client,
+			verifier,
+			client_id: settings.provider.client_id.clone(),
+			authority,
+			issuer,
+			tenant_id,
+			graph_url,
+			cache: Mutex::new(HashMap::new()),
+		})
+	}
+	///
+	/// Check if the tenant is an id, and not a domain or `common`, `organizations` and `consumers`
+	///
+	fn is_tenant_id(tenant: &str) -> bool {
+		tenant.len() == 36 && tenant.chars().all(|c| c.is_ascii_hexdigit() || c == '-')
+	}
+	///
+	/// Check if the tenant of the claims is allowed
+	///
+	fn is_allowed_tenant(&self, claims: &JsonValue) -> bool {
+		match (claims.get("tid").and_then(|t| t.as_str()), &self.tenant_id) {
+			(Some(tid), Some(tenant_id)) => tid.eq_ignore_ascii_case(tenant_id),
+			(Some(_), None) => true,
+			(None, _) => false,
+		}
+	}
+	///
+	/// Check the tenant and the v2.0 issuer of the verified id_token
+	///
+	fn is_allowed_id_token(&self, claims: &JsonValue) -> bool {
+		if !self.is_allowed_tenant(claims) {
+			return false;
+		}
+		if self.issuer.is_some() {
+			// Already checked by the verifier
+			return true;
+		}
+		let issuer = format!(
+			"{}/{}/v2.0",
+			self.authority,
+			claims["tid"].as_str().unwrap_or("")
+		);
+		claims.get("iss").and_then(|iss| iss.as_str()) == Some(&issuer)
+	}
+	///
+	/// Check the tenant and the client of an access token accepted by Graph
+	///
+	fn is_allowed_access_token(&self, claims: &JsonValue) -> bool {
+		let client_id = claims
+			.get("appid")
+			.or_else(|| claims.get("azp"))
+			.and_then(|c| c.as_str());
+		self.is_allowed_tenant(claims) && client_id == Some(&self.client_id)
+	}
+	///
+	/// Check if the groups were left out of the token (`_claim_names` or `hasgroups`)
+	///
+	fn has_group_overage(claims: &JsonValue) -> bool {
+		let claim_names = claims.get("_claim_names").and_then(|n| n.get("groups"));
+		claim_names.is_some() || claims.get("hasgroups") == Some(&JsonValue::Bool(true))
+	}
+	///
+	/// Create the normalized claims, using the resolved groups or the ones of the token
+	///
+	fn create_claims(source: &JsonValue, groups: Option<Vec<JsonValue>>) -> JsonValue {
+		let mut claims = serde_json::Map::new();
+		for name in CLAIMS.iter() {
+			if let Some(value) = source.get(name).filter(|v| !v.is_null()) {
+				claims.insert((*name).to_owned(), value.clone());
+			}
+		}
+		if let Some(upn) = source.get("upn").filter(|upn| upn.is_string()) {
+			for name in ["preferred_username", "email"].iter() {
+				claims
+					.entry((*name).to_owned())
+					.or_insert_with(|| upn.clone());
+			}
+		}
+		let groups = groups.or_else(|| source.get("groups").and_then(|g| g.as_array()).cloned());
+		if let Some(groups) = groups {
+			claims.insert(String::from("groups"), JsonValue::Array(groups));
+		}
+		JsonValue::Object(claims)
+	}
+	///
+	/// Resolve the groups of the user using Graph
+	///
+	/// Returns None when the access token is not allowed to read them
+	///
+	async fn request_groups(&self, access_token: &str) -> Result<Option<Vec<JsonValue>>, Error> {
+		let url = self.graph_url.join("v1.0/me/getMemberObjects")?;
+		let body = serde_json::json!({ "securityEnabledOnly": false });
+		match post_json(&url, access_token, &body).await? {
+			Some(response) => Ok(response.get("value").and_then(|v| v.as_array()).cloned()),
+			None => {
+				log::warn!("Could not resolve the groups of the user using Graph");
+				Ok(None)
+			}
+		}
+	}
+	///
+	/// Key of the access token in the cache
+	///
+	fn cache_key(access_token: &str) -> Vec<u8> {
+		ring::digest::digest(&ring::digest::SHA256, access_token.as_bytes())
+			.as_ref()
+			.to_vec()
+	}
+	///
+	/// Keep the userinfo of the access token
+	///
+	fn cache_userinfo(&self, access_token: &str, data: JsonValue, ttl: Duration) {
+		let now = Instant::now();
+		let mut cache = self.cache.lock().unwrap();
+		cache.retain(|_, (_, expires_at)| now < *expires_at);
+		cache.insert(Self::cache_key(access_token), (data, now + ttl));
+	}
+	///
+	/// Verify the id_token of the grant, and keep the claims of the access token
+	///
+	/// Refreshed tokens may come without an id_token, then the userinfo is requested from Graph
+	///
+	async fn verify_token_set(
+		&self,
+		token_set: Option<TokenSet>,
+		require_id_token: bool,
+	) -> Result<Option<TokenSet>, Error> {
+		let mut token_set = match token_set {
+			Some(token_set) => token_set,
+			None => return Ok(None),
+		};
+		let raw_id_token = match token_set.raw_id_token {
+			Some(ref raw_id_token) => raw_id_token,
+			None if require_id_token => {
+				log::info!("Azure grant without an id_token");
+				return Ok(None);
+			}
+			None => return Ok(Some(token_set)),
+		};
+		let claims = match self.verifier.verify(raw_id_token).await? {
+			Some(claims) => claims,
+			None => return Ok(None),
+		};
+		if !self.is_allowed_id_token(&claims) {
+			log::info!("Azure id_token of {:?} is not allowed", claims.get("iss"));
+			return Ok(None);
+		}
+		let groups = if Self::has_group_overage(&claims) {
+			self.request_groups(&token_set.access_token).await?
+		} else {
+			None
+		};
+		let ttl = match token_set.expires_in {
+			Some(expires_in) => Duration::from_secs(expires_in.max(0) as u64),
+			None => GRANT_CACHE_TTL,
+		};
+		let data = Self::create_claims(&claims, groups);
+		self.cache_userinfo(&token_set.access_token, data, ttl);
+		token_set.id_token = Some(claims);
+		Ok(Some(token_set))
+	}
+	///
+	/// Get the userinfo of the grant, or request it from Graph
+	///
+	pub async fn userinfo(&self, access_token: &str) -> Result<Option<Userinfo>, Error> {
+		let key = Self::cache_key(access_token);
+		if let Some((data, expires_at)) = self.cache.lock().unwrap().get(&key) {
+			if Instant::now() < *expires_at {
+				return Ok(Some(Userinfo {
+					data: data.clone(),
+					expires_at: None,
+				}));
+			}
+		}
+
+		// The access token is for Graph, so its claims are trusted once Graph accepts it
+		let claims = match self.client.jwt_decode(access_token) {
+			Some(claims) if self.is_allowed_access_token(&claims) => claims,
+			_ => return Ok(None),
+		};
+		let url = self.graph_url.join("oidc/userinfo")?;
+		let mut source = match request_json(&url, access_token).await? {
+			Some(JsonValue::Object(source)) => source,
+			_ => return Ok(None),
+		};
+		for name in ["oid", "tid", "upn"].iter() {
+			if let Some(value) = claims.get(name) {
+				source.insert((*name).to_owned(), value.clone());
+			}
+		}
+		let groups = self.request_groups(access_token).await?;
+		let data = Self::create_claims(&JsonValue::Object(source), groups);
+		self.cache_userinfo(access_token, data.clone(), GRAPH_CACHE_TTL);
+		Ok(Some(Userinfo {
+			data,
+			expires_at: None,
+		}))
+	}
+	///
+	/// Peform an authorization_code grant
+	///
+	pub async fn grant_authorization_code(&self, code: &str) -> Result<Option<TokenSet>, Error> {
+		let token_set = self.client.grant_authorization_code(code).await?;
+		self.verify_token_set(token_set, true).await
+	}
+	///
+	/// Perform a password grant (ROPC), for the accounts without multi-factor authentication
+	///
+	pub async fn grant_password(
+		&self,
+		username: &str,
+		password: &str,
+	) -> Result<Option<TokenSet>, Error> {
+		let token_set = self.client.grant_password(username, password).await?;
+		self.verify_token_set(token_set, true).await
+	}
+	///
+	/// Peform a refresh_token grant
+	///
+	pub async fn grant_refresh_token(
+		&self,
+		refresh_token: &str,
+	) -> Result<Option<TokenSet>, Error> {
+		let token_set = self.client.grant_refresh_token(refresh_token).await?;
+		self.verify_token_set(token_set, false).await
+	}
+	///
+	/// Start a device authorization
+	///
+	pub async fn device_authorization(&self) -> Result<Option<DeviceAuthorization>, Error> {
+		self.client.device_authorization().await
+	}
+	///
+	/// Poll the device code, verifying the id_token once the user approves it
+	///
+	pub async fn grant_device_code(&self, device_code: &str) -> Result<DeviceGrant, Error> {
+		match self.client.grant_device_code(device_code).await? {
+			DeviceGrant::Granted(token_set) => {
+				match self.verify_token_set(Some(token_set), true).await? {
+					Some(token_set) => Ok(DeviceGrant::Granted(token_set)),
+					None => Ok(DeviceGrant::Error(String::from("access_denied"))),
+				}
+			}
+			grant => Ok(grant),
+		}
+	}
+	///
+	/// Azure uses the on-behalf-of flow instead of the token exchange
+	///
+	pub async fn grant_token_exchange(
+		&self,
+		_subject_token: &str,
+		_audience: &str,
+		_scope: Option<&str>,
+	) -> Result<Option<AccessToken>, Error> {
+		Ok(None)
+	}
+}
+
+impl Provider for ProviderAzure {
+	///
+	/// Get the Azure authorization url
+	///
+	fn get_authorization_url(&self, state: String) -> String {
+		self.client.get_authorization_url(state)
+	}
+	///
+	/// Get the logout url
+	///
+	fn get_logout_url(&self) -> String {
+		self.client.get_logout_url()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[test]
+	fn test_claims() {
+		let id_token = serde_json::json!({
+			"sub": "pairwise",
+			"oid": "00000000-0000-0000-0000-000000000001",
+			"tid": "00000000-0000-0000-0000-00000000000a",
+			"name": "Jane",
+			"preferred_username": "jane@example.com",
+			"groups": ["g1"],
+			"aud": "client",
+		});
+		assert!(!ProviderAzure::has_group_overage(&id_token));
+		let claims = ProviderAzure::create_claims(&id_token, None);
+		assert_eq!(
+			serde_json::json!({
+				"sub": "pairwise",
+				"oid": "00000000-0000-0000-0000-000000000001",
+				"tid": "00000000-0000-0000-0000-00000000000a",
+				"name": "Jane",
+				"preferred_username": "jane@example.com",
+				"groups": ["g1"],
+			}),
+			claims
+		);
+
+		let overage = serde_json::json!({
+			"sub": "pairwise",
+			"upn": "jane@example.com",
+			"_claim_names": { "groups": "src1" },
+			"_claim_sources": { "src1": { "endpoint": "https://graph.windows.net/" } },
+		});
+		assert!(ProviderAzure::has_group_overage(&overage));
+		assert!(ProviderAzure::has_group_overage(
+			&serde_json::json!({ "hasgroups": true })
+		));
+		let claims = ProviderAzure::create_claims(&overage, Some(vec![JsonValue::from("g2")]));
+		assert_eq!("jane@example.com", claims["preferred_username"]);
+		assert_eq!("jane@example.com", claims["email"]);
+		assert_eq!(serde_json::json!(["g2"]), claims["groups"]);
+		assert!(claims.get("_claim_names").is_none());
+
+		assert!(ProviderAzure::is_tenant_id(
+			"00000000-0000-0000-0000-00000000000a"
+		));
+		assert!(!ProviderAzure::is_tenant_id("organizations"));
+		assert!(!ProviderAzure::is_tenant_id("example.onmicrosoft.com"));
+	}
+}
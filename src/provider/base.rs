@@ -1,12 +1,50 @@
+use serde::Serialize;
+
 ///
 /// The token set
 ///
 #[derive(Debug)]
 pub struct TokenSet {
 	pub access_token: String,
-	pub refresh_token: String,
+	pub refresh_token: Option<String>,
 	pub expires_in: Option<i64>,
 	pub id_token: Option<serde_json::Value>,
+	pub raw_id_token: Option<String>,
+}
+
+///
+/// An access token without a refresh token (Ex: from a token exchange)
+///
+#[derive(Debug)]
+pub struct AccessToken {
+	pub access_token: String,
+	pub expires_in: Option<i64>,
+}
+
+///
+/// A device authorization (RFC 8628), where the user approves the device on the verification uri
+///
+#[derive(Debug, Serialize)]
+pub struct DeviceAuthorization {
+	pub device_code: String,
+	pub user_code: String,
+	pub verification_uri: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub verification_uri_complete: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub expires_in: Option<i64>,
+	pub interval: i64,
+}
+
+///
+/// The result of polling a device code
+///
+#[derive(Debug)]
+pub enum DeviceGrant {
+	/// The user approved the device
+	Granted(TokenSet),
+	/// The error of the provider. Ex: `authorization_pending`, `slow_down` or `access_denied`
+	Error(String),
 }
 
 ///
@@ -18,6 +56,38 @@ pub struct Userinfo {
 	pub expires_at: Option<std::time::SystemTime>,
 }
 
+impl Userinfo {
+	///
+	/// Get the roles of the user (keycloak realm roles, the plain roles claim or the groups),
+	/// each one once
+	///
+	pub fn roles(&self) -> Vec<&str> {
+		let mut roles: Vec<&str> = Vec::new();
+		let claims = [
+			self.data.get("realm_access").and_then(|v| v.get("roles")),
+			self.data.get("roles"),
+			self.data.get("groups"),
+		];
+		for claim in claims.iter() {
+			if let Some(serde_json::Value::Array(values)) = claim {
+				for role in values.iter().filter_map(|v| v.as_str()) {
+					if !roles.contains(&role) {
+						roles.push(role);
+					}
+				}
+			}
+		}
+		roles
+	}
+	///
+	/// Check if the user has at least one of the roles
+	///
+	pub fn has_any_role(&self, roles: &[&str]) -> bool {
+		let user_roles = self.roles();
+		roles.iter().any(|role| user_roles.contains(role))
+	}
+}
+
 pub trait Provider {
 	///
 	/// Get the authorization url
@@ -28,3 +98,39 @@ pub trait Provider {
 	///
 	fn get_logout_url(&self) -> String;
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[test]
+	fn test_roles() {
+		let userinfo = Userinfo {
+			data: serde_json::json!({
+				"realm_access": { "roles": ["admin"] },
+				"roles": ["user"],
+			}),
+			expires_at: None,
+		};
+		assert_eq!(vec!["admin", "user"], userinfo.roles());
+		assert!(userinfo.has_any_role(&["other", "user"]));
+		assert!(!userinfo.has_any_role(&["other"]));
+
+		let userinfo = Userinfo {
+			data: serde_json::json!({
+				"roles": ["user", "admin"],
+				"groups": ["staff", "admin"],
+			}),
+			expires_at: None,
+		};
+		assert_eq!(vec!["user", "admin", "staff"], userinfo.roles());
+		assert!(userinfo.has_any_role(&["staff"]));
+
+		let userinfo = Userinfo {
+			data: serde_json::json!({ "groups": ["staff"] }),
+			expires_at: None,
+		};
+		assert_eq!(vec!["staff"], userinfo.roles());
+		assert!(userinfo.has_any_role(&["other", "staff"]));
+		assert!(!userinfo.has_any_role(&["admin"]));
+	}
+}
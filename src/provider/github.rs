@@ -0,0 +1,290 @@
+use super::base::{AccessToken, DeviceAuthorization, DeviceGrant, Provider, TokenSet, Userinfo};
+use super::oauth2::request_json;
+use super::oidc::{parse_list, ProviderOIDC, ProviderOIDCOptions};
+use crate::error::Error;
+use crate::settings::Settings;
+use crate::util::jwt::JsonValue;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// The userinfo takes a few api requests, so it is cached to respect the rate limits
+const USERINFO_CACHE_TTL: Duration = Duration::from_secs(30);
+
+///
+/// GitHub provider, checking the organization and team memberships
+///
+/// The organizations are in the `groups` claim as `org`, and the teams as `org/team`
+///
+pub struct ProviderGitHub {
+	client: ProviderOIDC,
+	api_url: Url,
+	allowed_orgs: Vec<String>,
+	allowed_teams: Vec<String>,
+	cache: Mutex<HashMap<Vec<u8>, (JsonValue, Instant)>>,
+}
+
+impl ProviderGitHub {
+	///
+	/// Create a new GitHub provider. The urls default to github.com
+	///
+	pub fn new(settings: &Settings) -> Result<Self, Error> {
+		let mut api_url = Url::parse(
+			settings
+				.provider
+				.api_url
+				.as_deref()
+				.unwrap_or("https://api.github.com"),
+		)?;
+		// Keep the path of GitHub Enterprise (/api/v3) when joining the endpoints
+		if !api_url.path().ends_with('/') {
+			api_url.set_path(&format!("{}/", api_url.path()));
+		}
+		let mut settings = settings.clone();
+		let provider = &mut settings.provider;
+		provider
+			.auth_url
+			.get_or_insert_with(|| String::from("https://github.com/login/oauth/authorize"));
+		provider
+			.token_url
+			.get_or_insert_with(|| String::from("https://github.com/login/oauth/access_token"));
+		provider
+			.device_auth_url
+			.get_or_insert_with(|| String::from("https://github.com/login/device/code"));
+		provider
+			.userinfo_url
+			.get_or_insert_with(|| api_url.join("user").unwrap().to_string());
+		let client = ProviderOIDC::new(
+			&settings,
+			ProviderOIDCOptions {
+				userinfo_from_access_token: false,
+				default_scope: "read:user user:email read:org",
+				auth_params: Vec::new(),
+			},
+		)?;
+		Ok(Self {
+			client,
+			api_url,
+			allowed_orgs: parse_list(&settings.provider.allowed_orgs),
+			allowed_teams: parse_list(&settings.provider.allowed_teams),
+			cache: Mutex::new(HashMap::new()),
+		})
+	}
+	///
+	/// Check if the user is a member of the allowed organizations or teams, when set
+	///
+	pub fn is_allowed(&self, userinfo: &Userinfo) -> bool {
+		if self.allowed_orgs.is_empty() && self.allowed_teams.is_empty() {
+			return true;
+		}
+		let groups = match userinfo.data.get("groups").and_then(|g| g.as_array()) {
+			Some(groups) => groups,
+			None => return false,
+		};
+		groups
+			.iter()
+			.filter_map(|g| g.as_str())
+			.map(|g| g.to_lowercase())
+			.any(|g| self.allowed_orgs.contains(&g) || self.allowed_teams.contains(&g))
+	}
+	///
+	/// Request the user, the verified emails, the organizations and the teams
+	///
+	pub async fn userinfo(&self, access_token: &str) -> Result<Option<Userinfo>, Error> {
+		let key = ring::digest::digest(&ring::digest::SHA256, access_token.as_bytes())
+			.as_ref()
+			.to_vec();
+		let now = Instant::now();
+		if let Some((data, expires_at)) = self.cache.lock().unwrap().get(&key) {
+			if now < *expires_at {
+				return Ok(Some(Userinfo {
+					data: data.clone(),
+					expires_at: None,
+				}));
+			}
+		}
+
+		let user = match self.request("user", access_token).await? {
+			Some(user) => user,
+			None => return Ok(None),
+		};
+		// Missing scopes only hide the emails, organizations or teams
+		let emails = self.request_list("user/emails", access_token).await?;
+		let orgs = self.request_list("user/orgs", access_token).await?;
+		let teams = self.request_list("user/teams", access_token).await?;
+		let data = Self::create_claims(&user, &emails, &orgs, &teams);
+
+		let mut cache = self.cache.lock().unwrap();
+		cache.retain(|_, (_, expires_at)| now < *expires_at);
+		cache.insert(key, (data.clone(), now + USERINFO_CACHE_TTL));
+		Ok(Some(Userinfo {
+			data,
+			expires_at: None,
+		}))
+	}
+	///
+	/// Create the claims from the api responses
+	///
+	fn create_claims(
+		user: &JsonValue,
+		emails: &[JsonValue],
+		orgs: &[JsonValue],
+		teams: &[JsonValue],
+	) -> JsonValue {
+		let login = user["login"].as_str().unwrap_or("");
+		let sub = match user["id"] {
+			JsonValue::Number(ref id) => id.to_string(),
+			_ => login.to_owned(),
+		};
+		let verified: Vec<&JsonValue> = emails
+			.iter()
+			.filter(|e| e["verified"].as_bool().unwrap_or(false))
+			.collect();
+		let email = verified
+			.iter()
+			.find(|e| e["primary"].as_bool().unwrap_or(false))
+			.or_else(|| verified.first())
+			.and_then(|e| e["email"].as_str());
+
+		let mut groups: Vec<String> = orgs
+			.iter()
+			.filter_map(|o| o["login"].as_str().map(|o| o.to_owned()))
+			.collect();
+		for team in teams {
+			let org = team["organization"]["login"].as_str();
+			let slug = team["slug"].as_str();
+			if let (Some(org), Some(slug)) = (org, slug) {
+				if !groups.iter().any(|g| g == org) {
+					groups.push(org.to_owned());
+				}
+				groups.push(format!("{}/{}", org, slug));
+			}
+		}
+
+		let mut claims = serde_json::json!({
+			"sub": sub,
+			"preferred_username": login,
+			"name": user["name"].as_str().unwrap_or(login),
+			"groups": groups,
+		});
+		if let Some(email) = email {
+			claims["email"] = JsonValue::from(email);
+			claims["email_verified"] = JsonValue::Bool(true);
+		}
+		if let Some(picture) = user["avatar_url"].as_str() {
+			claims["picture"] = JsonValue::from(picture);
+		}
+		claims
+	}
+	///
+	/// Send a request to the api
+	///
+	async fn request(&self, path: &str, access_token: &str) -> Result<Option<JsonValue>, Error> {
+		let url = self.api_url.join(path)?;
+		request_json(&url, access_token).await
+	}
+	///
+	/// Send a request to the api for a list. Only the first 100 items are used
+	///
+	async fn request_list(&self, path: &str, access_token: &str) -> Result<Vec<JsonValue>, Error> {
+		let path = format!("{}?per_page=100", path);
+		match self.request(&path, access_token).await? {
+			Some(JsonValue::Array(items)) => Ok(items),
+			_ => Ok(Vec::new()),
+		}
+	}
+	///
+	/// Peform an authorization_code grant
+	///
+	pub async fn grant_authorization_code(&self, code: &str) -> Result<Option<TokenSet>, Error> {
+		self.client.grant_authorization_code(code).await
+	}
+	///
+	/// GitHub does not support the password grant
+	///
+	pub async fn grant_password(
+		&self,
+		_username: &str,
+		_password: &str,
+	) -> Result<Option<TokenSet>, Error> {
+		Ok(None)
+	}
+	///
+	/// Peform a refresh_token grant, for the GitHub apps with expiring tokens
+	///
+	pub async fn grant_refresh_token(
+		&self,
+		refresh_token: &str,
+	) -> Result<Option<TokenSet>, Error> {
+		self.client.grant_refresh_token(refresh_token).await
+	}
+	///
+	/// Start a device authorization
+	///
+	pub async fn device_authorization(&self) -> Result<Option<DeviceAuthorization>, Error> {
+		self.client.device_authorization().await
+	}
+	///
+	/// Poll the device code
+	///
+	pub async fn grant_device_code(&self, device_code: &str) -> Result<DeviceGrant, Error> {
+		self.client.grant_device_code(device_code).await
+	}
+	///
+	/// GitHub does not support the token exchange
+	///
+	pub async fn grant_token_exchange(
+		&self,
+		_subject_token: &str,
+		_audience: &str,
+		_scope: Option<&str>,
+	) -> Result<Option<AccessToken>, Error> {
+		Ok(None)
+	}
+}
+
+impl Provider for ProviderGitHub {
+	///
+	/// Get the GitHub authorization url
+	///
+	fn get_authorization_url(&self, state: String) -> String {
+		self.client.get_authorization_url(state)
+	}
+	///
+	/// Get the logout url. GitHub has no logout endpoint
+	///
+	fn get_logout_url(&self) -> String {
+		self.client.get_logout_url()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[test]
+	fn test_claims() {
+		let user = serde_json::json!({ "id": 1, "login": "octocat", "name": null });
+		let emails = vec![
+			serde_json::json!({ "email": "unverified@example.com", "primary": true, "verified": false }),
+			serde_json::json!({ "email": "octocat@example.com", "primary": false, "verified": true }),
+		];
+		let orgs = vec![serde_json::json!({ "login": "github" })];
+		let teams = vec![
+			serde_json::json!({ "slug": "admins", "organization": { "login": "github" } }),
+			serde_json::json!({ "slug": "devs", "organization": { "login": "other" } }),
+		];
+		let claims = ProviderGitHub::create_claims(&user, &emails, &orgs, &teams);
+		assert_eq!(
+			serde_json::json!({
+				"sub": "1",
+				"preferred_username": "octocat",
+				"name": "octocat",
+				"email": "octocat@example.com",
+				"email_verified": true,
+				"groups": ["github", "github/admins", "other", "other/devs"],
+			}),
+			claims
+		);
+	}
+}
@@ -0,0 +1,207 @@
+use super::base::{AccessToken, DeviceAuthorization, DeviceGrant, Provider, TokenSet, Userinfo};
+use super::id_token::IdTokenVerifier;
+use super::oidc::{parse_list, parse_url, ProviderOIDC, ProviderOIDCOptions};
+use crate::error::Error;
+use crate::settings::Settings;
+
+///
+/// Google provider, restricting the users to the Google Workspace domains
+///
+/// The `hd` claim of the verified id_token is checked on login, and the one of the userinfo
+/// on every request.
+///
+pub struct ProviderGoogle {
+	client: ProviderOIDC,
+	verifier: IdTokenVerifier,
+	allowed_domains: Vec<String>,
+}
+
+impl ProviderGoogle {
+	///
+	/// Create a new Google provider, using the Google endpoints by default
+	///
+	pub fn new(settings: &Settings) -> Result<Self, Error> {
+		let mut settings = settings.clone();
+		let provider = &mut settings.provider;
+		provider
+			.auth_url
+			.get_or_insert_with(|| String::from("https://accounts.google.com/o/oauth2/v2/auth"));
+		provider
+			.token_url
+			.get_or_insert_with(|| String::from("https://oauth2.googleapis.com/token"));
+		provider
+			.device_auth_url
+			.get_or_insert_with(|| String::from("https://oauth2.googleapis.com/device/code"));
+		provider.userinfo_url.get_or_insert_with(|| {
+			String::from("https://openidconnect.googleapis.com/v1/userinfo")
+		});
+		provider
+			.jwks_url
+			.get_or_insert_with(|| String::from("https://www.googleapis.com/oauth2/v3/certs"));
+
+		let allowed_domains = parse_list(&settings.provider.allowed_domains);
+		// The login page only filters one domain, or any Workspace domain with `*`
+		let mut auth_params = vec![("access_type", String::from("offline"))];
+		match allowed_domains.len() {
+			0 => {}
+			1 => auth_params.push(("hd", allowed_domains[0].clone())),
+			_ => auth_params.push(("hd", String::from("*"))),
+		}
+		let client = ProviderOIDC::new(
+			&settings,
+			ProviderOIDCOptions {
+				userinfo_from_access_token: false,
+				default_scope: "openid email profile",
+				auth_params,
+			},
+		)?;
+		let issuers = match settings.provider.issuer {
+			Some(ref issuer) => vec![issuer.clone()],
+			None => vec![
+				String::from("https://accounts.google.com"),
+				String::from("accounts.google.com"),
+			],
+		};
+		let verifier = IdTokenVerifier::new(
+			parse_url(&settings.provider.jwks_url, "Missing the provider jwks url")?,
+			issuers,
+			settings.provider.client_id.clone(),
+		);
+		Ok(Self {
+			client,
+			verifier,
+			allowed_domains,
+		})
+	}
+	///
+	/// Check if the hosted domain of the user is allowed, when set
+	///
+	pub fn is_allowed(&self, userinfo: &Userinfo) -> bool {
+		self.is_allowed_domain(userinfo.data.get("hd").and_then(|hd| hd.as_str()))
+	}
+	///
+	/// Check if the hosted domain is allowed. Gmail accounts have no domain
+	///
+	fn is_allowed_domain(&self, hd: Option<&str>) -> bool {
+		if self.allowed_domains.is_empty() {
+			return true;
+		}
+		match hd {
+			Some(hd) => self.allowed_domains.contains(&hd.to_lowercase()),
+			None => false,
+		}
+	}
+	///
+	/// Verify the id_token of the grant, and its hosted domain
+	///
+	/// Refreshed tokens may come without an id_token, as the userinfo is checked anyway
+	///
+	async fn verify_token_set(
+		&self,
+		token_set: Option<TokenSet>,
+		require_id_token: bool,
+	) -> Result<Option<TokenSet>, Error> {
+		let mut token_set = match token_set {
+			Some(token_set) => token_set,
+			None => return Ok(None),
+		};
+		let raw_id_token = match token_set.raw_id_token {
+			Some(ref raw_id_token) => raw_id_token,
+			None if require_id_token => {
+				log::info!("Google grant without an id_token");
+				return Ok(None);
+			}
+			None => return Ok(Some(token_set)),
+		};
+		let claims = match self.verifier.verify(raw_id_token).await? {
+			Some(claims) => claims,
+			None => return Ok(None),
+		};
+		let hd = claims.get("hd").and_then(|hd| hd.as_str());
+		if !self.is_allowed_domain(hd) {
+			log::info!("Google domain {:?} is not allowed", hd);
+			return Ok(None);
+		}
+		token_set.id_token = Some(claims);
+		Ok(Some(token_set))
+	}
+	///
+	/// Request the userinfo
+	///
+	pub async fn userinfo(&self, access_token: &str) -> Result<Option<Userinfo>, Error> {
+		self.client.userinfo(access_token).await
+	}
+	///
+	/// Peform an authorization_code grant
+	///
+	pub async fn grant_authorization_code(&self, code: &str) -> Result<Option<TokenSet>, Error> {
+		let token_set = self.client.grant_authorization_code(code).await?;
+		self.verify_token_set(token_set, true).await
+	}
+	///
+	/// Google does not support the password grant
+	///
+	pub async fn grant_password(
+		&self,
+		_username: &str,
+		_password: &str,
+	) -> Result<Option<TokenSet>, Error> {
+		Ok(None)
+	}
+	///
+	/// Peform a refresh_token grant
+	///
+	pub async fn grant_refresh_token(
+		&self,
+		refresh_token: &str,
+	) -> Result<Option<TokenSet>, Error> {
+		let token_set = self.client.grant_refresh_token(refresh_token).await?;
+		self.verify_token_set(token_set, false).await
+	}
+	///
+	/// Start a device authorization
+	///
+	pub async fn device_authorization(&self) -> Result<Option<DeviceAuthorization>, Error> {
+		self.client.device_authorization().await
+	}
+	///
+	/// Poll the device code, verifying the id_token once the user approves it
+	///
+	pub async fn grant_device_code(&self, device_code: &str) -> Result<DeviceGrant, Error> {
+		match self.client.grant_device_code(device_code).await? {
+			DeviceGrant::Granted(token_set) => {
+				match self.verify_token_set(Some(token_set), true).await? {
+					Some(token_set) => Ok(DeviceGrant::Granted(token_set)),
+					None => Ok(DeviceGrant::Error(String::from("access_denied"))),
+				}
+			}
+			grant => Ok(grant),
+		}
+	}
+	///
+	/// Google does not support the token exchange
+	///
+	pub async fn grant_token_exchange(
+		&self,
+		_subject_token: &str,
+		_audience: &str,
+		_scope: Option<&str>,
+	) -> Result<Option<AccessToken>, Error> {
+		Ok(None)
+	}
+}
+
+impl Provider for ProviderGoogle {
+	///
+	/// Get the Google authorization url
+	///
+	fn get_authorization_url(&self, state: String) -> String {
+		self.client.get_authorization_url(state)
+	}
+	///
+	/// Get the logout url
+	///
+	fn get_logout_url(&self) -> String {
+		self.client.get_logout_url()
+	}
+}
@@ -0,0 +1,178 @@
+use crate::error::Error;
+use crate::util::jwt::JsonValue;
+use actix_web::client::ClientBuilder;
+use jsonwebtoken::jwk::{AlgorithmParameters, Jwk};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// How long the keys of the provider are used before fetching them again
+const KEYS_TTL: Duration = Duration::from_secs(60 * 60);
+/// Unknown key ids fetch the keys again, but not more often than this
+const KEYS_MIN_INTERVAL: Duration = Duration::from_secs(60);
+
+///
+/// Keys fetched from the jwks url
+///
+struct ProviderKeys {
+	keys: Vec<Jwk>,
+	fetched_at: Instant,
+}
+
+///
+/// Verify the id_tokens using the published keys of the provider
+///
+pub struct IdTokenVerifier {
+	jwks_url: Url,
+	issuers: Vec<String>,
+	audience: String,
+	keys: Mutex<Option<ProviderKeys>>,
+}
+
+impl IdTokenVerifier {
+	///
+	/// Create a verifier for the id_tokens of the issuers, sent to the audience (the client id)
+	///
+	/// Without issuers, the issuer must be checked by the provider (Ex: multi-tenant Azure)
+	///
+	pub fn new(jwks_url: Url, issuers: Vec<String>, audience: String) -> Self {
+		Self {
+			jwks_url,
+			issuers,
+			audience,
+			keys: Mutex::new(None),
+		}
+	}
+	///
+	/// Verify the signature, the issuer, the audience and the expiration of the id_token
+	///
+	/// Returns the claims, or None if the id_token is not valid
+	///
+	pub async fn verify(&self, id_token: &str) -> Result<Option<JsonValue>, Error> {
+		let header = match jsonwebtoken::decode_header(id_token) {
+			Ok(header) => header,
+			Err(_) => return Ok(None),
+		};
+		let kid = match header.kid {
+			Some(ref kid) => kid,
+			None => return Ok(None),
+		};
+		let jwk = match self.get_key(kid).await? {
+			Some(jwk) => jwk,
+			None => {
+				log::info!("Unknown id_token key {}", kid);
+				return Ok(None);
+			}
+		};
+		let key = jsonwebtoken::DecodingKey::from_jwk(&jwk)?;
+		let mut validation = jsonwebtoken::Validation::new(header.alg);
+		if !self.issuers.is_empty() {
+			validation.set_issuer(&self.issuers);
+		}
+		validation.set_audience(&[&self.audience]);
+		match jsonwebtoken::decode::<JsonValue>(id_token, &key, &validation) {
+			Ok(decoded) => Ok(Some(decoded.claims)),
+			Err(error) => {
+				log::info!("Invalid id_token: {}", error);
+				Ok(None)
+			}
+		}
+	}
+	///
+	/// Get the key by its id, fetching the keys when they are old or the id is unknown
+	///
+	async fn get_key(&self, kid: &str) -> Result<Option<Jwk>, Error> {
+		{
+			let keys = self.keys.lock().unwrap();
+			if let Some(ref keys) = *keys {
+				let elapsed = keys.fetched_at.elapsed();
+				let jwk = keys
+					.keys
+					.iter()
+					.find(|k| k.common.key_id.as_deref() == Some(kid));
+				if elapsed < KEYS_TTL && jwk.is_some() {
+					return Ok(jwk.cloned());
+				}
+				if elapsed < KEYS_MIN_INTERVAL {
+					return Ok(None);
+				}
+			}
+		}
+
+		let keys = self.fetch_keys().await?;
+		let jwk = keys
+			.iter()
+			.find(|k| k.common.key_id.as_deref() == Some(kid))
+			.cloned();
+		*self.keys.lock().unwrap() = Some(ProviderKeys {
+			keys,
+			fetched_at: Instant::now(),
+		});
+		Ok(jwk)
+	}
+	///
+	/// Fetch the keys. Symmetric and unknown keys are ignored
+	///
+	async fn fetch_keys(&self) -> Result<Vec<Jwk>, Error> {
+		let client = ClientBuilder::new().timeout(std::time::Duration::new(30, 0)).finish();
+		let mut res = client.get(self.jwks_url.as_str()).send().await?;
+		if !res.status().is_success() {
+			log::error!("Could not fetch the keys of {}", self.jwks_url);
+			return Err(Error::ProviderStatus(res.status().as_u16()));
+		}
+		let body = res.json::<JsonValue>().limit(1024 * 1024).await?;
+		let keys = match body.get("keys") {
+			Some(JsonValue::Array(keys)) => keys,
+			_ => return Ok(Vec::new()),
+		};
+		Ok(keys
+			.iter()
+			.filter_map(|key| serde_json::from_value::<Jwk>(key.clone()).ok())
+			.filter(|key| !matches!(key.algorithm, AlgorithmParameters::OctetKey(_)))
+			.collect())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::util::jwk::Jwk as KeyPair;
+	#[test]
+	fn test_verify() {
+		let rsa = openssl::rsa::Rsa::generate(2048).unwrap();
+		let key = KeyPair::from_pem(&rsa.private_key_to_pem().unwrap()).unwrap();
+		let verifier = IdTokenVerifier::new(
+			Url::parse("http://127.0.0.1:1/keys").unwrap(),
+			vec![String::from("https://issuer")],
+			String::from("client"),
+		);
+		*verifier.keys.lock().unwrap() = Some(ProviderKeys {
+			keys: vec![serde_json::from_value(key.public.clone()).unwrap()],
+			fetched_at: Instant::now(),
+		});
+
+		let mut header = jsonwebtoken::Header::new(key.algorithm);
+		header.kid = Some(key.kid.clone());
+		let encoding_key = key.encoding_key.as_ref().unwrap();
+		let now = jsonwebtoken::get_current_timestamp();
+		let sign =
+			|claims: JsonValue| jsonwebtoken::encode(&header, &claims, encoding_key).unwrap();
+		let valid = sign(serde_json::json!({
+			"iss": "https://issuer", "aud": "client", "exp": now + 60, "hd": "example.com",
+		}));
+		let other_audience = sign(serde_json::json!({
+			"iss": "https://issuer", "aud": "other", "exp": now + 60,
+		}));
+		let expired = sign(serde_json::json!({
+			"iss": "https://issuer", "aud": "client", "exp": now - 120,
+		}));
+
+		actix_rt::System::new("test").block_on(async move {
+			let claims = verifier.verify(&valid).await.unwrap().unwrap();
+			assert_eq!("example.com", claims["hd"]);
+			assert!(verifier.verify(&other_audience).await.unwrap().is_none());
+			assert!(verifier.verify(&expired).await.unwrap().is_none());
+			assert!(verifier.verify("invalid").await.unwrap().is_none());
+		});
+	}
+}
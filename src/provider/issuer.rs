@@ -0,0 +1,209 @@
+use super::base::{TokenSet, Userinfo};
+use crate::error::Error;
+use crate::util::jwt::JsonValue;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Issuer of the sessions
+const ISSUER: &str = "auth-gatekeeper";
+/// Seconds of the sessions, unless set
+pub const DEFAULT_SESSION_TTL: u64 = 8 * 60 * 60;
+/// Seconds of the access tokens of the sessions that are refreshed
+const REFRESHED_ACCESS_TOKEN_TTL: u64 = 5 * 60;
+/// Registered claims of the token, which are not part of the userinfo
+const REGISTERED_CLAIMS: [&str; 4] = ["iss", "aud", "iat", "exp"];
+/// Type of the refresh tokens, so they are not accepted as access tokens
+const REFRESH_TOKEN_TYPE: &str = "refresh";
+
+type Claims = serde_json::Map<String, JsonValue>;
+
+///
+/// Sessions issued by the gatekeeper, for the providers without tokens (Ex: ldap)
+///
+/// The access token is a JWT with the claims of the user, signed using a key derived from the
+/// secret. Without a refresh token, the user must login again once it expires. With a refresh
+/// token, the access token is short and the session lasts while it is refreshed.
+///
+pub struct SessionIssuer {
+	encoding_key: EncodingKey,
+	decoding_key: DecodingKey,
+	audience: String,
+	ttl: u64,
+}
+
+impl SessionIssuer {
+	///
+	/// Create the issuer using the secret of the gatekeeper. The audience identifies the
+	/// provider, so its tokens are not accepted by the others
+	///
+	pub fn new(secret: &str, audience: String, ttl: u64) -> Self {
+		let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes());
+		let secret = ring::hmac::sign(&key, b"auth-gatekeeper session");
+		Self {
+			encoding_key: EncodingKey::from_secret(secret.as_ref()),
+			decoding_key: DecodingKey::from_secret(secret.as_ref()),
+			audience,
+			ttl,
+		}
+	}
+	///
+	/// Issue the token set of a session with the claims
+	///
+	pub fn issue(&self, claims: JsonValue) -> Result<TokenSet, Error> {
+		Ok(TokenSet {
+			access_token: self.encode(to_claims(claims), self.ttl)?,
+			refresh_token: None,
+			expires_in: Some(self.ttl as i64),
+			id_token: None,
+			raw_id_token: None,
+		})
+	}
+	///
+	/// Issue the token set of a session that is refreshed, with the claims
+	///
+	/// The refresh token has the subject and its version, which the provider checks before
+	/// issuing the next tokens (Ex: the user must exist, with the same password)
+	///
+	pub fn issue_refreshable(
+		&self,
+		claims: JsonValue,
+		subject: &str,
+		version: &str,
+	) -> Result<TokenSet, Error> {
+		// Short sessions keep the access token shorter than the refresh token, so they are refreshed
+		let access_ttl = REFRESHED_ACCESS_TOKEN_TTL.min(self.ttl / 2).max(1);
+		let mut refresh_claims = Claims::new();
+		refresh_claims.insert(String::from("typ"), JsonValue::from(REFRESH_TOKEN_TYPE));
+		refresh_claims.insert(String::from("sub"), JsonValue::from(subject));
+		refresh_claims.insert(String::from("ver"), JsonValue::from(version));
+		Ok(TokenSet {
+			access_token: self.encode(to_claims(claims), access_ttl)?,
+			refresh_token: Some(self.encode(refresh_claims, self.ttl)?),
+			expires_in: Some(access_ttl as i64),
+			id_token: None,
+			raw_id_token: None,
+		})
+	}
+	///
+	/// Get the userinfo of the session, or None when the token is not valid or expired
+	///
+	pub fn userinfo(&self, access_token: &str) -> Option<Userinfo> {
+		let mut claims = self.decode(access_token)?;
+		if claims.contains_key("typ") {
+			return None;
+		}
+		let expires_at = claims
+			.get("exp")
+			.and_then(|exp| exp.as_u64())
+			.map(|exp| UNIX_EPOCH + Duration::from_secs(exp));
+		for claim in REGISTERED_CLAIMS.iter() {
+			claims.remove(*claim);
+		}
+		Some(Userinfo {
+			data: JsonValue::Object(claims),
+			expires_at,
+		})
+	}
+	///
+	/// Get the subject and the version of the refresh token, or None when it is not valid
+	///
+	pub fn refresh(&self, refresh_token: &str) -> Option<(String, String)> {
+		let claims = self.decode(refresh_token)?;
+		if claims.get("typ").and_then(|t| t.as_str()) != Some(REFRESH_TOKEN_TYPE) {
+			return None;
+		}
+		let subject = claims.get("sub")?.as_str()?;
+		let version = claims.get("ver")?.as_str()?;
+		Some((subject.to_owned(), version.to_owned()))
+	}
+	///
+	/// Sign the claims, adding the registered claims
+	///
+	fn encode(&self, mut claims: Claims, ttl: u64) -> Result<String, Error> {
+		let now = jsonwebtoken::get_current_timestamp();
+		claims.insert(String::from("iss"), JsonValue::from(ISSUER));
+		claims.insert(String::from("aud"), JsonValue::from(self.audience.clone()));
+		claims.insert(String::from("iat"), JsonValue::from(now));
+		claims.insert(String::from("exp"), JsonValue::from(now + ttl));
+		Ok(jsonwebtoken::encode(
+			&Header::new(Algorithm::HS256),
+			&claims,
+			&self.encoding_key,
+		)?)
+	}
+	///
+	/// Verify the token, and get its claims
+	///
+	fn decode(&self, token: &str) -> Option<Claims> {
+		let mut validation = Validation::new(Algorithm::HS256);
+		validation.set_issuer(&[ISSUER]);
+		validation.set_audience(&[&self.audience]);
+		validation.leeway = 0;
+		let claims = jsonwebtoken::decode::<JsonValue>(token, &self.decoding_key, &validation)
+			.ok()?
+			.claims;
+		match claims {
+			JsonValue::Object(claims) => Some(claims),
+			_ => None,
+		}
+	}
+}
+
+///
+/// Get the claims of the json value
+///
+fn to_claims(value: JsonValue) -> Claims {
+	match value {
+		JsonValue::Object(claims) => claims,
+		_ => Claims::new(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[test]
+	fn test_issue() {
+		let issuer = SessionIssuer::new("secret", String::from("ldap://one"), 60);
+		let token_set = issuer
+			.issue(serde_json::json!({ "sub": "alice", "groups": ["admins"] }))
+			.unwrap();
+		assert!(token_set.refresh_token.is_none());
+		let userinfo = issuer.userinfo(&token_set.access_token).unwrap();
+		assert_eq!(
+			serde_json::json!({ "sub": "alice", "groups": ["admins"] }),
+			userinfo.data
+		);
+		assert!(userinfo.expires_at.is_some());
+
+		let other = SessionIssuer::new("secret", String::from("ldap://two"), 60);
+		assert!(other.userinfo(&token_set.access_token).is_none());
+		let other = SessionIssuer::new("other", String::from("ldap://one"), 60);
+		assert!(other.userinfo(&token_set.access_token).is_none());
+		assert!(issuer.userinfo("invalid").is_none());
+	}
+	#[test]
+	fn test_issue_refreshable() {
+		let issuer = SessionIssuer::new("secret", String::from("static:users"), 3600);
+		let token_set = issuer
+			.issue_refreshable(serde_json::json!({ "sub": "alice" }), "alice", "v1")
+			.unwrap();
+		assert_eq!(Some(300), token_set.expires_in);
+		let refresh_token = token_set.refresh_token.unwrap();
+		assert_eq!(
+			Some((String::from("alice"), String::from("v1"))),
+			issuer.refresh(&refresh_token)
+		);
+		assert!(issuer.refresh(&token_set.access_token).is_none());
+		assert!(issuer.userinfo(&refresh_token).is_none());
+		let short = SessionIssuer::new("secret", String::from("static:users"), 60);
+		let short_token_set = short
+			.issue_refreshable(serde_json::json!({ "sub": "alice" }), "alice", "v1")
+			.unwrap();
+		assert_eq!(Some(30), short_token_set.expires_in);
+		assert_eq!(
+			"alice",
+			issuer.userinfo(&token_set.access_token).unwrap().data["sub"]
+		);
+	}
+}
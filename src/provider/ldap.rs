@@ -0,0 +1,303 @@
+use super::base::{AccessToken, Provider, TokenSet, Userinfo};
+use super::issuer::{SessionIssuer, DEFAULT_SESSION_TTL};
+use super::oauth2::ClaimMapping;
+use crate::error::Error;
+use crate::settings::Settings;
+use crate::util::jwt::JsonValue;
+use actix_web::web;
+use ldap3::{ldap_escape, LdapConn, LdapConnSettings, LdapError, Scope, SearchEntry};
+use std::time::Duration;
+
+/// Timeout of the connection and the operations
+const LDAP_TIMEOUT: Duration = Duration::from_secs(30);
+/// Claims of the users, unless set
+const DEFAULT_CLAIMS: &str =
+	"sub=uid|sAMAccountName|dn,email=mail,name=displayName|cn,preferred_username=uid|sAMAccountName";
+
+///
+/// The directory where the users are searched and bound
+///
+#[derive(Clone)]
+struct LdapDirectory {
+	url: String,
+	bind_dn: Option<String>,
+	bind_password: String,
+	base_dn: String,
+	user_filter: String,
+}
+
+///
+/// LDAP provider, authenticating the users with a search and a bind
+///
+/// There are no tokens, so the gatekeeper issues its own sessions with the claims of the user.
+/// Only the password logins are supported.
+///
+pub struct ProviderLdap {
+	directory: LdapDirectory,
+	claims: Vec<ClaimMapping>,
+	issuer: SessionIssuer,
+	logout_url: String,
+}
+
+impl LdapDirectory {
+	///
+	/// Search the user, and bind as the user to check the password
+	///
+	/// Returns the attributes of the user, or None when the user is not found or the password
+	/// is invalid
+	///
+	fn authenticate(&self, username: &str, password: &str) -> Result<Option<JsonValue>, LdapError> {
+		let settings = LdapConnSettings::new().set_conn_timeout(LDAP_TIMEOUT);
+		let mut ldap = LdapConn::with_settings(settings, &self.url)?;
+		ldap.with_timeout(LDAP_TIMEOUT);
+		if let Some(ref bind_dn) = self.bind_dn {
+			ldap.simple_bind(bind_dn, &self.bind_password)?.success()?;
+		}
+		let filter = self
+			.user_filter
+			.replace("{username}", &ldap_escape(username));
+		let (entries, _) = ldap
+			.search(
+				&self.base_dn,
+				Scope::Subtree,
+				&filter,
+				vec!["*", "memberOf"],
+			)?
+			.success()?;
+		// The filter must match a single user, so a wildcard does not login as another user
+		let mut entries: Vec<SearchEntry> = entries
+			.into_iter()
+			.filter(|entry| !entry.is_ref())
+			.map(SearchEntry::construct)
+			.collect();
+		if entries.len() != 1 {
+			log::info!("LDAP user '{}' matched {} entries", username, entries.len());
+			ldap.unbind()?;
+			return Ok(None);
+		}
+		let entry = entries.remove(0);
+		let result = ldap.simple_bind(&entry.dn, password)?;
+		ldap.unbind()?;
+		if result.rc != 0 {
+			log::info!("LDAP bind of '{}' failed: {}", entry.dn, result);
+			return Ok(None);
+		}
+		Ok(Some(entry_attributes(entry)))
+	}
+}
+
+impl ProviderLdap {
+	///
+	/// Create a new LDAP provider
+	///
+	pub fn new(settings: &Settings) -> Result<Self, Error> {
+		let provider = &settings.provider;
+		let url = provider
+			.ldap_url
+			.clone()
+			.ok_or(Error::SettingsError("Missing the provider ldap url"))?;
+		let base_dn = provider
+			.ldap_base_dn
+			.clone()
+			.ok_or(Error::SettingsError("Missing the provider ldap base dn"))?;
+		let user_filter = provider
+			.ldap_user_filter
+			.clone()
+			.unwrap_or_else(|| String::from("(uid={username})"));
+		if !user_filter.contains("{username}") {
+			return Err(Error::SettingsError(
+				"The provider ldap user filter must have {username}",
+			));
+		}
+		let claims =
+			ClaimMapping::parse_list(provider.claims.as_deref().unwrap_or(DEFAULT_CLAIMS))?;
+		let issuer = SessionIssuer::new(
+			&settings.secret,
+			format!("{}/{}", url, base_dn),
+			provider.session_ttl.unwrap_or(DEFAULT_SESSION_TTL),
+		);
+		let logout_url = if provider.logout_redirect_url.is_empty() {
+			String::from("/")
+		} else {
+			provider.logout_redirect_url.clone()
+		};
+		Ok(Self {
+			directory: LdapDirectory {
+				url,
+				bind_dn: provider.ldap_bind_dn.clone(),
+				bind_password: provider.ldap_bind_password.clone().unwrap_or_default(),
+				base_dn,
+				user_filter,
+			},
+			claims,
+			issuer,
+			logout_url,
+		})
+	}
+	///
+	/// Get the userinfo of the session issued by the gatekeeper
+	///
+	pub async fn userinfo(&self, access_token: &str) -> Result<Option<Userinfo>, Error> {
+		Ok(self.issuer.userinfo(access_token))
+	}
+	///
+	/// There is no authorization server, so there are no codes
+	///
+	pub async fn grant_authorization_code(&self, _code: &str) -> Result<Option<TokenSet>, Error> {
+		Ok(None)
+	}
+	///
+	/// Authenticate the user in the directory, and issue a session with the claims
+	///
+	pub async fn grant_password(
+		&self,
+		username: &str,
+		password: &str,
+	) -> Result<Option<TokenSet>, Error> {
+		// An empty password is an unauthenticated bind, which most servers accept
+		if username.is_empty() || password.is_empty() {
+			return Ok(None);
+		}
+		let directory = self.directory.clone();
+		let username = username.to_owned();
+		let password = password.to_owned();
+		let attributes = web::block(move || directory.authenticate(&username, &password)).await?;
+		let attributes = match attributes {
+			Some(attributes) => attributes,
+			None => return Ok(None),
+		};
+		let mut claims = ClaimMapping::map(&self.claims, &attributes);
+		if let Some(groups) = attributes.get("groups") {
+			claims.insert(String::from("groups"), groups.clone());
+		}
+		Ok(Some(self.issuer.issue(JsonValue::Object(claims))?))
+	}
+	///
+	/// The sessions are not refreshed, the user must login again
+	///
+	pub async fn grant_refresh_token(
+		&self,
+		_refresh_token: &str,
+	) -> Result<Option<TokenSet>, Error> {
+		Ok(None)
+	}
+	///
+	/// There is no token exchange
+	///
+	pub async fn grant_token_exchange(
+		&self,
+		_subject_token: &str,
+		_audience: &str,
+		_scope: Option<&str>,
+	) -> Result<Option<AccessToken>, Error> {
+		Ok(None)
+	}
+}
+
+impl Provider for ProviderLdap {
+	///
+	/// There is no authorization server, the login page asks for the password
+	///
+	fn get_authorization_url(&self, _state: String) -> String {
+		String::from("/login")
+	}
+	///
+	/// Get the logout url
+	///
+	fn get_logout_url(&self) -> String {
+		self.logout_url.clone()
+	}
+}
+
+///
+/// Get the attributes of the entry as json. Attributes with many values become arrays
+///
+/// The groups in `memberOf` become the `groups`, using the common name of each group
+///
+fn entry_attributes(entry: SearchEntry) -> JsonValue {
+	let mut attributes = serde_json::Map::new();
+	attributes.insert(String::from("dn"), JsonValue::String(entry.dn));
+	for (name, mut values) in entry.attrs {
+		if name.eq_ignore_ascii_case("memberOf") {
+			let groups = values.iter().map(|dn| group_name(dn)).collect();
+			attributes.insert(String::from("groups"), JsonValue::Array(groups));
+		} else if values.len() == 1 {
+			attributes.insert(name, JsonValue::String(values.remove(0)));
+		} else {
+			attributes.insert(name, JsonValue::from(values));
+		}
+	}
+	JsonValue::Object(attributes)
+}
+
+///
+/// Get the common name of the group DN. Ex: `cn=admins,ou=groups,dc=example,dc=com` is `admins`
+///
+/// Groups without a common name keep the DN
+///
+fn group_name(dn: &str) -> JsonValue {
+	let mut name = String::new();
+	let mut escaped = false;
+	for c in dn.chars() {
+		if escaped {
+			name.push(c);
+			escaped = false;
+		} else if c == '\\' {
+			escaped = true;
+		} else if c == ',' {
+			break;
+		} else {
+			name.push(c);
+		}
+	}
+	match name.split_once('=') {
+		Some((attribute, value)) if attribute.trim().eq_ignore_ascii_case("cn") => {
+			JsonValue::from(value.trim())
+		}
+		_ => JsonValue::from(dn),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::HashMap;
+	#[test]
+	fn test_entry_attributes() {
+		let mut attrs = HashMap::new();
+		attrs.insert(String::from("uid"), vec![String::from("alice")]);
+		attrs.insert(
+			String::from("mail"),
+			vec![
+				String::from("alice@example.com"),
+				String::from("a@example.com"),
+			],
+		);
+		attrs.insert(
+			String::from("memberOf"),
+			vec![
+				String::from("cn=admins,ou=groups,dc=example,dc=com"),
+				String::from("CN=Sales\\, East,OU=Groups,DC=example,DC=com"),
+				String::from("ou=staff,dc=example,dc=com"),
+			],
+		);
+		let attributes = entry_attributes(SearchEntry {
+			dn: String::from("uid=alice,ou=people,dc=example,dc=com"),
+			attrs,
+			bin_attrs: HashMap::new(),
+		});
+		assert_eq!("uid=alice,ou=people,dc=example,dc=com", attributes["dn"]);
+		assert_eq!("alice", attributes["uid"]);
+		assert_eq!("a@example.com", attributes["mail"][1]);
+		assert_eq!(
+			serde_json::json!(["admins", "Sales, East", "ou=staff,dc=example,dc=com"]),
+			attributes["groups"]
+		);
+
+		let mappings = ClaimMapping::parse_list(DEFAULT_CLAIMS).unwrap();
+		let claims = ClaimMapping::map(&mappings, &attributes);
+		assert_eq!("alice", claims["sub"]);
+		assert_eq!("alice", claims["preferred_username"]);
+		assert!(claims.get("name").is_none());
+	}
+}
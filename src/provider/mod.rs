@@ -1,12 +1,45 @@
+mod azure;
 mod base;
+mod github;
+mod google;
+mod id_token;
+mod issuer;
+mod ldap;
+mod oauth2;
 mod oidc;
+mod saml;
+mod static_users;
 use crate::error::Error;
 use crate::settings::Settings;
+use crate::util::replay::ReplayCachePtr;
+pub use azure::ProviderAzure;
 pub use base::*;
+pub use github::ProviderGitHub;
+pub use google::ProviderGoogle;
+pub use ldap::ProviderLdap;
+pub use oauth2::ProviderOAuth2;
 pub use oidc::{ProviderOIDC, ProviderOIDCOptions};
+pub use saml::ProviderSaml;
+pub use static_users::ProviderStatic;
+
+///
+/// The providers by name. The first one is the default
+///
+/// A single provider has no name, so the sessions do not record it
+///
+pub struct Providers {
+	providers: Vec<(String, ProviderBox)>,
+}
 
 pub enum ProviderBox {
 	OIDC(ProviderOIDC),
+	OAuth2(ProviderOAuth2),
+	GitHub(ProviderGitHub),
+	Google(ProviderGoogle),
+	Azure(Box<ProviderAzure>),
+	Ldap(ProviderLdap),
+	Static(ProviderStatic),
+	Saml(ProviderSaml),
 }
 
 impl ProviderBox {
@@ -16,6 +49,13 @@ impl ProviderBox {
 	pub fn get_authorization_url(&self, state: String) -> String {
 		match self {
 			ProviderBox::OIDC(provider) => provider.get_authorization_url(state),
+			ProviderBox::OAuth2(provider) => provider.get_authorization_url(state),
+			ProviderBox::GitHub(provider) => provider.get_authorization_url(state),
+			ProviderBox::Google(provider) => provider.get_authorization_url(state),
+			ProviderBox::Azure(provider) => provider.get_authorization_url(state),
+			ProviderBox::Ldap(provider) => provider.get_authorization_url(state),
+			ProviderBox::Static(provider) => provider.get_authorization_url(state),
+			ProviderBox::Saml(provider) => provider.get_authorization_url(state),
 		}
 	}
 	///
@@ -24,6 +64,44 @@ impl ProviderBox {
 	pub fn get_logout_url(&self) -> String {
 		match self {
 			ProviderBox::OIDC(provider) => provider.get_logout_url(),
+			ProviderBox::OAuth2(provider) => provider.get_logout_url(),
+			ProviderBox::GitHub(provider) => provider.get_logout_url(),
+			ProviderBox::Google(provider) => provider.get_logout_url(),
+			ProviderBox::Azure(provider) => provider.get_logout_url(),
+			ProviderBox::Ldap(provider) => provider.get_logout_url(),
+			ProviderBox::Static(provider) => provider.get_logout_url(),
+			ProviderBox::Saml(provider) => provider.get_logout_url(),
+		}
+	}
+	///
+	/// Check if the provider only accepts passwords, so the login page asks for them
+	///
+	pub fn is_password_only(&self) -> bool {
+		matches!(self, ProviderBox::Ldap(_) | ProviderBox::Static(_))
+	}
+	///
+	/// Check if the provider is SAML, so the responses are posted to the callback by the IdP
+	///
+	pub fn is_saml(&self) -> bool {
+		matches!(self, ProviderBox::Saml(_))
+	}
+	///
+	/// Get the metadata of the SAML service provider
+	///
+	pub fn saml_metadata(&self) -> Option<String> {
+		match self {
+			ProviderBox::Saml(provider) => Some(provider.metadata()),
+			_ => None,
+		}
+	}
+	///
+	/// Check if the provider allows the user (Ex: the GitHub organizations or the Google domains)
+	///
+	pub fn is_allowed(&self, userinfo: &Userinfo) -> bool {
+		match self {
+			ProviderBox::GitHub(provider) => provider.is_allowed(userinfo),
+			ProviderBox::Google(provider) => provider.is_allowed(userinfo),
+			_ => true,
 		}
 	}
 	///
@@ -32,6 +110,13 @@ impl ProviderBox {
 	pub async fn userinfo(&self, access_token: &str) -> Result<Option<Userinfo>, Error> {
 		match self {
 			ProviderBox::OIDC(provider) => provider.userinfo(access_token).await,
+			ProviderBox::OAuth2(provider) => provider.userinfo(access_token).await,
+			ProviderBox::GitHub(provider) => provider.userinfo(access_token).await,
+			ProviderBox::Google(provider) => provider.userinfo(access_token).await,
+			ProviderBox::Azure(provider) => provider.userinfo(access_token).await,
+			ProviderBox::Ldap(provider) => provider.userinfo(access_token).await,
+			ProviderBox::Static(provider) => provider.userinfo(access_token).await,
+			ProviderBox::Saml(provider) => provider.userinfo(access_token).await,
 		}
 	}
 	///
@@ -40,6 +125,13 @@ impl ProviderBox {
 	pub async fn grant_authorization_code(&self, code: &str) -> Result<Option<TokenSet>, Error> {
 		match self {
 			ProviderBox::OIDC(provider) => provider.grant_authorization_code(code).await,
+			ProviderBox::OAuth2(provider) => provider.grant_authorization_code(code).await,
+			ProviderBox::GitHub(provider) => provider.grant_authorization_code(code).await,
+			ProviderBox::Google(provider) => provider.grant_authorization_code(code).await,
+			ProviderBox::Azure(provider) => provider.grant_authorization_code(code).await,
+			ProviderBox::Ldap(provider) => provider.grant_authorization_code(code).await,
+			ProviderBox::Static(provider) => provider.grant_authorization_code(code).await,
+			ProviderBox::Saml(provider) => provider.grant_authorization_code(code).await,
 		}
 	}
 	///
@@ -52,6 +144,13 @@ impl ProviderBox {
 	) -> Result<Option<TokenSet>, Error> {
 		match self {
 			ProviderBox::OIDC(provider) => provider.grant_password(username, password).await,
+			ProviderBox::OAuth2(provider) => provider.grant_password(username, password).await,
+			ProviderBox::GitHub(provider) => provider.grant_password(username, password).await,
+			ProviderBox::Google(provider) => provider.grant_password(username, password).await,
+			ProviderBox::Azure(provider) => provider.grant_password(username, password).await,
+			ProviderBox::Ldap(provider) => provider.grant_password(username, password).await,
+			ProviderBox::Static(provider) => provider.grant_password(username, password).await,
+			ProviderBox::Saml(provider) => provider.grant_password(username, password).await,
 		}
 	}
 	///
@@ -63,16 +162,172 @@ impl ProviderBox {
 	) -> Result<Option<TokenSet>, Error> {
 		match self {
 			ProviderBox::OIDC(provider) => provider.grant_refresh_token(refresh_token).await,
+			ProviderBox::OAuth2(provider) => provider.grant_refresh_token(refresh_token).await,
+			ProviderBox::GitHub(provider) => provider.grant_refresh_token(refresh_token).await,
+			ProviderBox::Google(provider) => provider.grant_refresh_token(refresh_token).await,
+			ProviderBox::Azure(provider) => provider.grant_refresh_token(refresh_token).await,
+			ProviderBox::Ldap(provider) => provider.grant_refresh_token(refresh_token).await,
+			ProviderBox::Static(provider) => provider.grant_refresh_token(refresh_token).await,
+			ProviderBox::Saml(provider) => provider.grant_refresh_token(refresh_token).await,
+		}
+	}
+	///
+	/// Perform a token exchange request for another audience
+	///
+	pub async fn grant_token_exchange(
+		&self,
+		subject_token: &str,
+		audience: &str,
+		scope: Option<&str>,
+	) -> Result<Option<AccessToken>, Error> {
+		match self {
+			ProviderBox::OIDC(provider) => {
+				provider
+					.grant_token_exchange(subject_token, audience, scope)
+					.await
+			}
+			ProviderBox::OAuth2(provider) => {
+				provider
+					.grant_token_exchange(subject_token, audience, scope)
+					.await
+			}
+			ProviderBox::GitHub(provider) => {
+				provider
+					.grant_token_exchange(subject_token, audience, scope)
+					.await
+			}
+			ProviderBox::Google(provider) => {
+				provider
+					.grant_token_exchange(subject_token, audience, scope)
+					.await
+			}
+			ProviderBox::Azure(provider) => {
+				provider
+					.grant_token_exchange(subject_token, audience, scope)
+					.await
+			}
+			ProviderBox::Ldap(provider) => {
+				provider
+					.grant_token_exchange(subject_token, audience, scope)
+					.await
+			}
+			ProviderBox::Static(provider) => {
+				provider
+					.grant_token_exchange(subject_token, audience, scope)
+					.await
+			}
+			ProviderBox::Saml(provider) => {
+				provider
+					.grant_token_exchange(subject_token, audience, scope)
+					.await
+			}
+		}
+	}
+	///
+	/// Start a device authorization for the clients without a browser. None when the provider
+	/// does not support it
+	///
+	pub async fn device_authorization(&self) -> Result<Option<DeviceAuthorization>, Error> {
+		match self {
+			ProviderBox::OIDC(provider) => provider.device_authorization().await,
+			ProviderBox::OAuth2(provider) => provider.device_authorization().await,
+			ProviderBox::GitHub(provider) => provider.device_authorization().await,
+			ProviderBox::Google(provider) => provider.device_authorization().await,
+			ProviderBox::Azure(provider) => provider.device_authorization().await,
+			_ => Ok(None),
 		}
 	}
+	///
+	/// Perform a grant_type: device_code request
+	///
+	pub async fn grant_device_code(&self, device_code: &str) -> Result<DeviceGrant, Error> {
+		match self {
+			ProviderBox::OIDC(provider) => provider.grant_device_code(device_code).await,
+			ProviderBox::OAuth2(provider) => provider.grant_device_code(device_code).await,
+			ProviderBox::GitHub(provider) => provider.grant_device_code(device_code).await,
+			ProviderBox::Google(provider) => provider.grant_device_code(device_code).await,
+			ProviderBox::Azure(provider) => provider.grant_device_code(device_code).await,
+			_ => Ok(DeviceGrant::Error(String::from("unsupported_grant_type"))),
+		}
+	}
+}
+
+impl Providers {
+	///
+	/// Get the provider by name, or the default provider without a name
+	///
+	/// Without named providers, the name is ignored
+	///
+	pub fn get(&self, name: Option<&str>) -> Option<&ProviderBox> {
+		self.select(name).map(|(_, provider)| provider)
+	}
+	///
+	/// Select the provider by name, or the default provider without a name
+	///
+	/// Returns the name to record in the session, which is None without named providers
+	///
+	pub fn select(&self, name: Option<&str>) -> Option<(Option<String>, &ProviderBox)> {
+		if !self.is_named() {
+			return Some((None, self.get_default()));
+		}
+		let (name, provider) = match name {
+			Some(name) => self.providers.iter().find(|(n, _)| n == name)?,
+			None => &self.providers[0],
+		};
+		Some((Some(name.clone()), provider))
+	}
+	///
+	/// Get the default provider
+	///
+	pub fn get_default(&self) -> &ProviderBox {
+		&self.providers[0].1
+	}
+	///
+	/// Check if there are named providers to choose
+	///
+	pub fn is_named(&self) -> bool {
+		!self.providers[0].0.is_empty()
+	}
+	///
+	/// Get the names of the providers
+	///
+	pub fn names(&self) -> Vec<&str> {
+		self.providers.iter().map(|(n, _)| n.as_str()).collect()
+	}
+}
+
+///
+/// Create the named providers, or the single provider of the settings
+///
+pub fn create_providers(settings: &Settings, replay: &ReplayCachePtr) -> Result<Providers, Error> {
+	let names = oidc::parse_list(&settings.providers);
+	if names.is_empty() {
+		return Ok(Providers {
+			providers: vec![(String::new(), create_provider(settings, replay)?)],
+		});
+	}
+	let mut providers = Vec::with_capacity(names.len());
+	for name in names {
+		let mut settings = settings.clone();
+		settings.provider = settings
+			.named_providers
+			.remove(&name)
+			.ok_or(Error::SettingsError(
+				"Missing the settings of a named provider",
+			))?;
+		providers.push((name, create_provider(&settings, replay)?));
+	}
+	Ok(Providers { providers })
 }
 
-pub fn create_provider(settings: &Settings) -> Result<ProviderBox, Error> {
+pub fn create_provider(settings: &Settings, replay: &ReplayCachePtr) -> Result<ProviderBox, Error> {
 	if settings.provider.provider == "keycloak" || settings.provider.provider == "fusionauth" {
 		let provider = ProviderOIDC::new(
 			&settings,
 			ProviderOIDCOptions {
 				userinfo_from_access_token: true,
+				default_scope: "openid email profile offline_access",
+				auth_params: Vec::new(),
 			},
 		)?;
 		Ok(ProviderBox::OIDC(provider))
@@ -81,9 +336,28 @@ pub fn create_provider(settings: &Settings) -> Result<ProviderBox, Error> {
 			&settings,
 			ProviderOIDCOptions {
 				userinfo_from_access_token: false,
+				default_scope: "openid email profile offline_access",
+				auth_params: Vec::new(),
 			},
 		)?;
 		Ok(ProviderBox::OIDC(provider))
+	} else if settings.provider.provider == "oauth2" {
+		Ok(ProviderBox::OAuth2(ProviderOAuth2::new(settings)?))
+	} else if settings.provider.provider == "github" {
+		Ok(ProviderBox::GitHub(ProviderGitHub::new(settings)?))
+	} else if settings.provider.provider == "google" {
+		Ok(ProviderBox::Google(ProviderGoogle::new(settings)?))
+	} else if settings.provider.provider == "azure" {
+		Ok(ProviderBox::Azure(Box::new(ProviderAzure::new(settings)?)))
+	} else if settings.provider.provider == "ldap" {
+		Ok(ProviderBox::Ldap(ProviderLdap::new(settings)?))
+	} else if settings.provider.provider == "static" {
+		Ok(ProviderBox::Static(ProviderStatic::new(settings)?))
+	} else if settings.provider.provider == "saml" {
+		Ok(ProviderBox::Saml(ProviderSaml::new(
+			settings,
+			replay.clone(),
+		)?))
 	} else {
 		Err(Error::SettingsError("Invalid provider"))
 	}
@@ -0,0 +1,362 @@
+use super::base::{AccessToken, DeviceAuthorization, DeviceGrant, Provider, TokenSet, Userinfo};
+use super::oidc::{parse_url, ProviderOIDC, ProviderOIDCOptions};
+use crate::error::Error;
+use crate::settings::Settings;
+use crate::util::jwt::JsonValue;
+use actix_web::client::ClientBuilder;
+use url::Url;
+
+///
+/// A segment of the path of a claim
+///
+enum ClaimPathSegment {
+	/// Field of an object
+	Field(String),
+	/// Item of an array
+	Index(usize),
+	/// First item of an array where the field has the value. Ex: `emails[primary=true]`
+	Find(String, String, JsonValue),
+}
+
+///
+/// A claim of the userinfo, with the paths to try in order
+///
+pub(super) struct ClaimMapping {
+	name: String,
+	paths: Vec<Vec<ClaimPathSegment>>,
+}
+
+///
+/// Generic OAuth2 provider, for services without OpenID Connect
+///
+/// The tokens are handled like OpenID Connect, but the userinfo is built from a chain of
+/// requests and the claims are mapped from its response.
+///
+pub struct ProviderOAuth2 {
+	client: ProviderOIDC,
+	userinfo_url: Url,
+	userinfo_requests: Vec<(String, Url)>,
+	claims: Vec<ClaimMapping>,
+}
+
+impl ClaimPathSegment {
+	///
+	/// Parse the segments of a path like `emails[primary=true].email`
+	///
+	fn parse_path(path: &str) -> Result<Vec<Self>, Error> {
+		let mut segments = Vec::new();
+		for segment in path.split('.') {
+			let (field, filter) = match segment.find('[') {
+				Some(index) if segment.ends_with(']') => (
+					&segment[..index],
+					Some(&segment[index + 1..segment.len() - 1]),
+				),
+				Some(_) => return Err(Error::SettingsError("Invalid provider claim path")),
+				None => (segment, None),
+			};
+			if let Some(filter) = filter {
+				let (name, value) = filter
+					.split_once('=')
+					.ok_or(Error::SettingsError("Invalid provider claim filter"))?;
+				// Values like `true` or `1` are compared as json, other values as strings
+				let value = serde_json::from_str(value)
+					.unwrap_or_else(|_| JsonValue::String(value.to_owned()));
+				segments.push(ClaimPathSegment::Find(
+					field.to_owned(),
+					name.to_owned(),
+					value,
+				));
+			} else if let Ok(index) = field.parse() {
+				segments.push(ClaimPathSegment::Index(index));
+			} else if !field.is_empty() {
+				segments.push(ClaimPathSegment::Field(field.to_owned()));
+			} else {
+				return Err(Error::SettingsError("Invalid provider claim path"));
+			}
+		}
+		Ok(segments)
+	}
+	///
+	/// Get the value of the path
+	///
+	fn get<'a>(path: &[Self], value: &'a JsonValue) -> Option<&'a JsonValue> {
+		let mut value = value;
+		for segment in path {
+			value = match segment {
+				ClaimPathSegment::Field(field) => value.get(field)?,
+				ClaimPathSegment::Index(index) => value.get(index)?,
+				ClaimPathSegment::Find(field, name, expected) => value
+					.get(field)?
+					.as_array()?
+					.iter()
+					.find(|item| item.get(name) == Some(expected))?,
+			};
+		}
+		Some(value).filter(|v| !v.is_null())
+	}
+}
+
+impl ClaimMapping {
+	///
+	/// Parse the comma delimited mappings like `sub=id,name=name|login`
+	///
+	pub(super) fn parse_list(value: &str) -> Result<Vec<Self>, Error> {
+		let mut mappings = Vec::new();
+		for mapping in value.split(',').filter(|m| !m.trim().is_empty()) {
+			let (name, paths) = mapping
+				.split_once('=')
+				.ok_or(Error::SettingsError("Invalid provider claim"))?;
+			let paths = paths
+				.split('|')
+				.map(|p| ClaimPathSegment::parse_path(p.trim()))
+				.collect::<Result<Vec<_>, _>>()?;
+			mappings.push(Self {
+				name: name.trim().to_owned(),
+				paths,
+			});
+		}
+		Ok(mappings)
+	}
+	///
+	/// Get the mapped claims of the userinfo, using the first path found
+	///
+	/// The `sub` claim is always a string, as some providers use numeric ids
+	///
+	pub(super) fn map(
+		mappings: &[Self],
+		userinfo: &JsonValue,
+	) -> serde_json::Map<String, JsonValue> {
+		let mut claims = serde_json::Map::new();
+		for mapping in mappings {
+			let value = mapping
+				.paths
+				.iter()
+				.find_map(|path| ClaimPathSegment::get(path, userinfo));
+			let value = match value {
+				Some(JsonValue::Number(number)) if mapping.name == "sub" => {
+					JsonValue::String(number.to_string())
+				}
+				Some(value) => value.clone(),
+				None => continue,
+			};
+			claims.insert(mapping.name.clone(), value);
+		}
+		claims
+	}
+	///
+	/// Add the mapped claims to the userinfo
+	///
+	fn apply(mappings: &[Self], userinfo: JsonValue) -> JsonValue {
+		let claims = Self::map(mappings, &userinfo);
+		let mut userinfo = match userinfo {
+			JsonValue::Object(userinfo) => userinfo,
+			_ => serde_json::Map::new(),
+		};
+		userinfo.extend(claims);
+		JsonValue::Object(userinfo)
+	}
+}
+
+impl ProviderOAuth2 {
+	///
+	/// Create a new OAuth2 provider
+	///
+	pub fn new(settings: &Settings) -> Result<Self, Error> {
+		let client = ProviderOIDC::new(
+			settings,
+			ProviderOIDCOptions {
+				userinfo_from_access_token: false,
+				default_scope: "",
+				auth_params: Vec::new(),
+			},
+		)?;
+		let mut userinfo_requests = Vec::new();
+		if let Some(ref value) = settings.provider.userinfo_requests {
+			for request in value.split(',').filter(|r| !r.trim().is_empty()) {
+				let (name, url) = request
+					.split_once('=')
+					.ok_or(Error::SettingsError("Invalid provider userinfo request"))?;
+				userinfo_requests.push((name.trim().to_owned(), Url::parse(url.trim())?));
+			}
+		}
+		let claims = match settings.provider.claims {
+			Some(ref value) => ClaimMapping::parse_list(value)?,
+			None => ClaimMapping::parse_list("sub=sub|id,email=email,name=name")?,
+		};
+		Ok(Self {
+			client,
+			userinfo_url: parse_url(
+				&settings.provider.userinfo_url,
+				"Missing the provider userinfo url",
+			)?,
+			userinfo_requests,
+			claims,
+		})
+	}
+	///
+	/// Request the userinfo, and the other requests of the chain
+	///
+	/// The responses of the other requests are added to the userinfo using their names
+	///
+	pub async fn userinfo(&self, access_token: &str) -> Result<Option<Userinfo>, Error> {
+		let mut data = match request_json(&self.userinfo_url, access_token).await? {
+			Some(JsonValue::Object(data)) => data,
+			_ => return Ok(None),
+		};
+		for (name, url) in self.userinfo_requests.iter() {
+			let response = match request_json(url, access_token).await? {
+				Some(response) => response,
+				None => return Ok(None),
+			};
+			data.insert(name.clone(), response);
+		}
+		Ok(Some(Userinfo {
+			data: ClaimMapping::apply(&self.claims, JsonValue::Object(data)),
+			expires_at: None,
+		}))
+	}
+	///
+	/// Peform an authorization_code grant
+	///
+	pub async fn grant_authorization_code(&self, code: &str) -> Result<Option<TokenSet>, Error> {
+		self.client.grant_authorization_code(code).await
+	}
+	///
+	/// Perform a password grant
+	///
+	pub async fn grant_password(
+		&self,
+		username: &str,
+		password: &str,
+	) -> Result<Option<TokenSet>, Error> {
+		self.client.grant_password(username, password).await
+	}
+	///
+	/// Peform a refresh_token grant
+	///
+	pub async fn grant_refresh_token(
+		&self,
+		refresh_token: &str,
+	) -> Result<Option<TokenSet>, Error> {
+		self.client.grant_refresh_token(refresh_token).await
+	}
+	///
+	/// Start a device authorization
+	///
+	pub async fn device_authorization(&self) -> Result<Option<DeviceAuthorization>, Error> {
+		self.client.device_authorization().await
+	}
+	///
+	/// Poll the device code
+	///
+	pub async fn grant_device_code(&self, device_code: &str) -> Result<DeviceGrant, Error> {
+		self.client.grant_device_code(device_code).await
+	}
+	///
+	/// Perform a token exchange (RFC 8693) for an access token of another audience
+	///
+	pub async fn grant_token_exchange(
+		&self,
+		subject_token: &str,
+		audience: &str,
+		scope: Option<&str>,
+	) -> Result<Option<AccessToken>, Error> {
+		self.client
+			.grant_token_exchange(subject_token, audience, scope)
+			.await
+	}
+}
+
+impl Provider for ProviderOAuth2 {
+	///
+	/// Get the OAuth2 authorization url
+	///
+	fn get_authorization_url(&self, state: String) -> String {
+		self.client.get_authorization_url(state)
+	}
+	///
+	/// Get the logout url
+	///
+	fn get_logout_url(&self) -> String {
+		self.client.get_logout_url()
+	}
+}
+
+///
+/// Send a request using the access token. Returns None when the token is not accepted
+///
+pub async fn request_json(url: &Url, access_token: &str) -> Result<Option<JsonValue>, Error> {
+	send_json_request(url, access_token, None).await
+}
+
+///
+/// Post the json body using the access token. Returns None when the token is not accepted
+///
+pub async fn post_json(
+	url: &Url,
+	access_token: &str,
+	body: &JsonValue,
+) -> Result<Option<JsonValue>, Error> {
+	send_json_request(url, access_token, Some(body)).await
+}
+
+///
+/// Send a request to the api, posting the body when set
+///
+async fn send_json_request(
+	url: &Url,
+	access_token: &str,
+	body: Option<&JsonValue>,
+) -> Result<Option<JsonValue>, Error> {
+	let client = ClientBuilder::new().timeout(std::time::Duration::new(30, 0)).finish();
+	let request = match body {
+		Some(_) => client.post(url.as_str()),
+		None => client.get(url.as_str()),
+	}
+	.header("authorization", format!("Bearer {}", access_token))
+	.header("accept", "application/json");
+	let mut res = match body {
+		Some(body) => request.send_json(body),
+		None => request.send(),
+	}
+	.await?;
+	let status = res.status();
+	if status == 400 || status == 401 || status == 403 {
+		return Ok(None);
+	}
+	if !status.is_success() {
+		log::error!("Provider request {} failed with {}", url, status);
+		return Err(Error::ProviderStatus(status.as_u16()));
+	}
+	let body = res.json::<JsonValue>().limit(1024 * 1024).await?;
+	Ok(Some(body))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[test]
+	fn test_claims() {
+		let mappings = ClaimMapping::parse_list(
+			"sub=id,email=email|emails[primary=true].email,name=name|login,first=emails.0.email",
+		)
+		.unwrap();
+		let userinfo = serde_json::json!({
+			"id": 42,
+			"login": "octocat",
+			"name": null,
+			"email": null,
+			"emails": [
+				{ "email": "other@example.com", "primary": false },
+				{ "email": "octocat@example.com", "primary": true },
+			],
+		});
+		let claims = ClaimMapping::apply(&mappings, userinfo);
+		assert_eq!("42", claims["sub"]);
+		assert_eq!("octocat@example.com", claims["email"]);
+		assert_eq!("octocat", claims["name"]);
+		assert_eq!("other@example.com", claims["first"]);
+		assert!(ClaimMapping::parse_list("sub").is_err());
+		assert!(ClaimMapping::parse_list("sub=emails[primary]").is_err());
+	}
+}
@@ -1,4 +1,4 @@
-use super::base::{Provider, TokenSet, Userinfo};
+use super::base::{AccessToken, DeviceAuthorization, DeviceGrant, Provider, TokenSet, Userinfo};
 use crate::error::Error;
 use crate::settings::Settings;
 use crate::util::jwt::JsonValue;
@@ -6,8 +6,13 @@ use actix_web::{client::ClientBuilder, ResponseError};
 use std::time::SystemTime;
 use url::Url;
 
+/// Seconds between the polls of a device code, when the provider does not tell
+const DEFAULT_DEVICE_INTERVAL: i64 = 5;
+
 pub struct ProviderOIDCOptions {
 	pub userinfo_from_access_token: bool,
+	pub default_scope: &'static str,
+	pub auth_params: Vec<(&'static str, String)>,
 }
 
 pub struct ProviderOIDC {
@@ -16,7 +21,8 @@ pub struct ProviderOIDC {
 	scope: String,
 	auth_url: Url,
 	token_url: Url,
-	userinfo_url: Url,
+	device_auth_url: Option<Url>,
+	userinfo_url: Option<Url>,
 	end_session_url: Option<Url>,
 	callback_url: Url,
 	logout_redirect_url: Url,
@@ -28,11 +34,29 @@ impl ProviderOIDC {
 	/// Create a new OpenID Connect provider
 	///
 	pub fn new(settings: &Settings, options: ProviderOIDCOptions) -> Result<Self, Error> {
-		let auth_url = Url::parse(&settings.provider.auth_url)?;
-		let token_url = Url::parse(&settings.provider.token_url)?;
-		let userinfo_url = Url::parse(&settings.provider.userinfo_url)?;
+		if settings.provider.client_id.is_empty() {
+			return Err(Error::SettingsError("Missing the provider client id"));
+		}
+		let auth_url = parse_url(&settings.provider.auth_url, "Missing the provider auth url")?;
+		let token_url = parse_url(
+			&settings.provider.token_url,
+			"Missing the provider token url",
+		)?;
+		// Keycloak reads the userinfo from the access token
+		let userinfo_url = if options.userinfo_from_access_token {
+			None
+		} else {
+			Some(parse_url(
+				&settings.provider.userinfo_url,
+				"Missing the provider userinfo url",
+			)?)
+		};
 		let callback_url = Url::parse(&settings.provider.callback_url)?;
 		let logout_redirect_url = Url::parse(&settings.provider.logout_redirect_url)?;
+		let device_auth_url = match settings.provider.device_auth_url {
+			Some(ref url) => Some(url.parse::<Url>()?),
+			None => None,
+		};
 		let end_session_url = if let Some(ref url) = &settings.provider.end_session_url {
 			Some(url.parse::<Url>()?)
 		} else {
@@ -46,9 +70,10 @@ impl ProviderOIDC {
 				.provider
 				.scope
 				.clone()
-				.unwrap_or_else(|| String::from("openid email profile offline_access")),
+				.unwrap_or_else(|| String::from(options.default_scope)),
 			auth_url: auth_url,
 			token_url: token_url,
+			device_auth_url,
 			userinfo_url: userinfo_url,
 			end_session_url: end_session_url,
 			callback_url: callback_url,
@@ -77,9 +102,14 @@ impl ProviderOIDC {
 		Some(id_token.clone())
 	}
 
-	/// Decode a JWT sent by keycloak
-	fn jwt_decode(&self, jwt: &str) -> Option<JsonValue> {
-		let decoded = jsonwebtoken::dangerous_insecure_decode::<JsonValue>(jwt);
+	/// Decode a JWT sent by keycloak, or a token already accepted by an api
+	pub fn jwt_decode(&self, jwt: &str) -> Option<JsonValue> {
+		let mut validation = jsonwebtoken::Validation::default();
+		validation.insecure_disable_signature_validation();
+		validation.validate_exp = false;
+		validation.required_spec_claims.clear();
+		let key = jsonwebtoken::DecodingKey::from_secret(&[]);
+		let decoded = jsonwebtoken::decode::<JsonValue>(jwt, &key, &validation);
 		if decoded.is_err() {
 			return None;
 		}
@@ -88,30 +118,55 @@ impl ProviderOIDC {
 	}
 
 	///
-	/// Grant a token
+	/// Post a form to the token endpoint
 	///
-	async fn grant<T: serde::Serialize + ?Sized>(
+	async fn request_token<T: serde::Serialize + ?Sized>(
 		&self,
 		form: &T,
-	) -> Result<Option<TokenSet>, Error> {
+	) -> Result<serde_json::Value, Error> {
+		Self::post_form(&self.token_url, form).await
+	}
+
+	///
+	/// Post a form to an endpoint of the provider, and read the json
+	///
+	async fn post_form<T: serde::Serialize + ?Sized>(
+		url: &Url,
+		form: &T,
+	) -> Result<serde_json::Value, Error> {
 		let client = ClientBuilder::new().timeout(std::time::Duration::new(30, 0)).finish();
 		let mut res = client
-			.post(self.token_url.as_str())
+			.post(url.as_str())
+			.header("accept", "application/json")
 			.send_form(&form)
 			.await?;
 		let body = res.json::<serde_json::Value>().await?;
+		Ok(body)
+	}
 
-		let access_token = body["access_token"].as_str();
-		let refresh_token = body["refresh_token"].as_str();
-		if access_token.is_none() || refresh_token.is_none() {
-			return Ok(None);
-		}
-		Ok(Some(TokenSet {
-			access_token: access_token.unwrap().to_owned(),
-			refresh_token: refresh_token.unwrap().to_owned(),
+	///
+	/// Grant a token. The refresh token is optional, as some providers never expire the tokens
+	///
+	async fn grant<T: serde::Serialize + ?Sized>(
+		&self,
+		form: &T,
+	) -> Result<Option<TokenSet>, Error> {
+		let body = self.request_token(form).await?;
+		Ok(self.get_token_set(&body))
+	}
+
+	///
+	/// Get the token set from the response of the token endpoint
+	///
+	fn get_token_set(&self, body: &serde_json::Value) -> Option<TokenSet> {
+		let access_token = body["access_token"].as_str()?;
+		Some(TokenSet {
+			access_token: access_token.to_owned(),
+			refresh_token: body["refresh_token"].as_str().map(|t| t.to_owned()),
 			expires_in: body["expires_in"].as_i64(),
-			id_token: self.get_id_token(&body),
-		}))
+			id_token: self.get_id_token(body),
+			raw_id_token: body["id_token"].as_str().map(|t| t.to_owned()),
+		})
 	}
 
 	///
@@ -127,9 +182,13 @@ impl ProviderOIDC {
 	/// Request the userinfo using openid client
 	///
 	async fn get_userinfo_from_oidc(&self, access_token: &str) -> Result<Option<Userinfo>, Error> {
+		let userinfo_url = match self.userinfo_url {
+			Some(ref userinfo_url) => userinfo_url,
+			None => return Ok(None),
+		};
 		let client = ClientBuilder::new().timeout(std::time::Duration::new(30, 0)).finish();
 		let res = client
-			.get(self.userinfo_url.as_str())
+			.get(userinfo_url.as_str())
 			.header("authorization", format!("Bearer {}", access_token))
 			.send();
 
@@ -220,6 +279,131 @@ impl ProviderOIDC {
 		];
 		self.grant(&params).await
 	}
+	///
+	/// Start a device authorization (RFC 8628), when the provider has the endpoint
+	///
+	pub async fn device_authorization(&self) -> Result<Option<DeviceAuthorization>, Error> {
+		let device_auth_url = match self.device_auth_url {
+			Some(ref url) => url,
+			None => return Ok(None),
+		};
+		let params = [
+			("client_id", &self.client_id),
+			("client_secret", &self.client_secret),
+			("scope", &self.scope),
+		];
+		let body = Self::post_form(device_auth_url, &params).await?;
+		let authorization = parse_device_authorization(&body);
+		if authorization.is_none() {
+			log::warn!(
+				"Device authorization denied by the provider: {}",
+				body["error"].as_str().unwrap_or("invalid response")
+			);
+		}
+		Ok(authorization)
+	}
+	///
+	/// Poll the device code. The errors of the provider are returned until the user approves it
+	///
+	pub async fn grant_device_code(&self, device_code: &str) -> Result<DeviceGrant, Error> {
+		let params = [
+			("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+			("client_id", &self.client_id),
+			("client_secret", &self.client_secret),
+			("device_code", device_code),
+		];
+		let body = self.request_token(&params).await?;
+		if let Some(error) = body["error"].as_str() {
+			return Ok(DeviceGrant::Error(error.to_owned()));
+		}
+		match self.get_token_set(&body) {
+			Some(token_set) => Ok(DeviceGrant::Granted(token_set)),
+			None => Ok(DeviceGrant::Error(String::from("invalid_grant"))),
+		}
+	}
+	///
+	/// Perform a token exchange (RFC 8693) for an access token of another audience
+	///
+	pub async fn grant_token_exchange(
+		&self,
+		subject_token: &str,
+		audience: &str,
+		scope: Option<&str>,
+	) -> Result<Option<AccessToken>, Error> {
+		let mut params = vec![
+			(
+				"grant_type",
+				"urn:ietf:params:oauth:grant-type:token-exchange",
+			),
+			("client_id", &self.client_id),
+			("client_secret", &self.client_secret),
+			("subject_token", subject_token),
+			(
+				"subject_token_type",
+				"urn:ietf:params:oauth:token-type:access_token",
+			),
+			(
+				"requested_token_type",
+				"urn:ietf:params:oauth:token-type:access_token",
+			),
+			("audience", audience),
+		];
+		if let Some(scope) = scope {
+			params.push(("scope", scope));
+		}
+		let body = self.request_token(&params).await?;
+		let access_token = body["access_token"].as_str();
+		if access_token.is_none() {
+			return Ok(None);
+		}
+		Ok(Some(AccessToken {
+			access_token: access_token.unwrap().to_owned(),
+			expires_in: body["expires_in"].as_i64(),
+		}))
+	}
+}
+
+///
+/// Parse the response of the device authorization endpoint. Google names the uri
+/// `verification_url`
+///
+fn parse_device_authorization(body: &serde_json::Value) -> Option<DeviceAuthorization> {
+	let verification_uri = body["verification_uri"]
+		.as_str()
+		.or_else(|| body["verification_url"].as_str())?;
+	Some(DeviceAuthorization {
+		device_code: body["device_code"].as_str()?.to_owned(),
+		user_code: body["user_code"].as_str()?.to_owned(),
+		verification_uri: verification_uri.to_owned(),
+		verification_uri_complete: body["verification_uri_complete"]
+			.as_str()
+			.map(|u| u.to_owned()),
+		expires_in: body["expires_in"].as_i64(),
+		interval: body["interval"].as_i64().unwrap_or(DEFAULT_DEVICE_INTERVAL),
+	})
+}
+
+///
+/// Parse an url of the provider, which is required
+///
+pub fn parse_url(value: &Option<String>, error: &'static str) -> Result<Url, Error> {
+	match value {
+		Some(ref value) => Ok(Url::parse(value)?),
+		None => Err(Error::SettingsError(error)),
+	}
+}
+
+///
+/// Parse a comma delimited list, in lowercase
+///
+pub fn parse_list(value: &Option<String>) -> Vec<String> {
+	value
+		.as_deref()
+		.unwrap_or("")
+		.split(',')
+		.map(|v| v.trim().to_lowercase())
+		.filter(|v| !v.is_empty())
+		.collect()
 }
 
 impl Provider for ProviderOIDC {
@@ -232,9 +416,14 @@ impl Provider for ProviderOIDC {
 			let mut query = auth_url.query_pairs_mut();
 			query
 				.append_pair("response_type", "code")
-				.append_pair("scope", &self.scope)
 				.append_pair("client_id", &self.client_id)
 				.append_pair("redirect_uri", self.callback_url.as_str());
+			if !self.scope.is_empty() {
+				query.append_pair("scope", &self.scope);
+			}
+			for (name, value) in self.options.auth_params.iter() {
+				query.append_pair(name, value);
+			}
 			if !state.is_empty() {
 				query.append_pair("state", &state);
 			}
@@ -260,3 +449,44 @@ impl Provider for ProviderOIDC {
 		end_session_url.to_string()
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[test]
+	fn test_parse_device_authorization() {
+		let authorization = parse_device_authorization(&serde_json::json!({
+			"device_code": "device",
+			"user_code": "ABCD-EFGH",
+			"verification_url": "https://www.google.com/device",
+			"expires_in": 1800,
+		}))
+		.unwrap();
+		assert_eq!("device", authorization.device_code);
+		assert_eq!(
+			"https://www.google.com/device",
+			authorization.verification_uri
+		);
+		assert_eq!(Some(1800), authorization.expires_in);
+		assert_eq!(DEFAULT_DEVICE_INTERVAL, authorization.interval);
+
+		let authorization = parse_device_authorization(&serde_json::json!({
+			"device_code": "device",
+			"user_code": "ABCD-EFGH",
+			"verification_uri": "https://idp.example.com/device",
+			"verification_uri_complete": "https://idp.example.com/device?user_code=ABCD-EFGH",
+			"interval": 10,
+		}))
+		.unwrap();
+		assert_eq!(
+			"https://idp.example.com/device",
+			authorization.verification_uri
+		);
+		assert!(authorization.verification_uri_complete.is_some());
+		assert_eq!(10, authorization.interval);
+
+		assert!(
+			parse_device_authorization(&serde_json::json!({ "error": "invalid_client" })).is_none()
+		);
+	}
+}
@@ -0,0 +1,584 @@
+use super::base::{AccessToken, Provider, TokenSet, Userinfo};
+use super::issuer::{SessionIssuer, DEFAULT_SESSION_TTL};
+use crate::error::Error;
+use crate::settings::Settings;
+use crate::util::jwt::JsonValue;
+use crate::util::replay::ReplayCachePtr;
+use crate::util::xmldsig::{self, child, text_of, DSIG_NS};
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use openssl::pkey::{PKey, Public};
+use openssl::x509::X509;
+use ring::rand::SecureRandom;
+use roxmltree::{Document, Node};
+use std::io::Write;
+use url::Url;
+
+const PROTOCOL_NS: &str = "urn:oasis:names:tc:SAML:2.0:protocol";
+const ASSERTION_NS: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+const STATUS_SUCCESS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+const BEARER: &str = "urn:oasis:names:tc:SAML:2.0:cm:bearer";
+const POST_BINDING: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST";
+/// Seconds of tolerance between the clocks of the gatekeeper and the IdP
+const CLOCK_SKEW: i64 = 60;
+/// Claims of the users, unless set. The sources are the names of the attributes
+const DEFAULT_CLAIMS: &str = "email=email|mail|http://schemas.xmlsoap.org/ws/2005/05/identity/claims/emailaddress|urn:oid:0.9.2342.19200300.100.1.3,\
+	name=name|displayName|http://schemas.xmlsoap.org/ws/2005/05/identity/claims/name|urn:oid:2.16.840.1.113730.3.1.241,\
+	groups=groups|memberOf|http://schemas.microsoft.com/ws/2008/06/identity/claims/groups|http://schemas.microsoft.com/ws/2008/06/identity/claims/role";
+
+///
+/// The checks of the responses of the IdP
+///
+struct ServiceProvider {
+	entity_id: String,
+	acs_url: String,
+	idp_entity_id: Option<String>,
+	keys: Vec<PKey<Public>>,
+	/// The assertions already used, so they are not accepted again
+	used: ReplayCachePtr,
+}
+
+///
+/// The user of a valid assertion
+///
+#[derive(Debug)]
+struct SamlUser {
+	name_id: String,
+	attributes: serde_json::Map<String, JsonValue>,
+}
+
+///
+/// SAML 2.0 provider, as a service provider of an IdP (Ex: ADFS or Okta)
+///
+/// The login redirects to the IdP with an AuthnRequest, and the IdP posts the signed response to
+/// the callback. There are no tokens, so the gatekeeper issues its own sessions with the claims
+/// mapped from the attributes of the assertion.
+///
+pub struct ProviderSaml {
+	sp: ServiceProvider,
+	sso_url: Url,
+	claims: Vec<(String, Vec<String>)>,
+	issuer: SessionIssuer,
+	logout_url: String,
+}
+
+impl ServiceProvider {
+	///
+	/// Validate the response, returning the user of the assertion or the reason it is not valid
+	///
+	/// The assertion, or the response with it, must be signed by the IdP. The conditions, the
+	/// audience and the bearer confirmation are checked at the time, and each assertion is only
+	/// accepted once
+	///
+	fn validate(&self, xml: &str, now: i64) -> Result<SamlUser, &'static str> {
+		let document = Document::parse(xml).map_err(|_| "Invalid xml")?;
+		let response = document.root_element();
+		if !response.has_tag_name((PROTOCOL_NS, "Response")) {
+			return Err("Not a response");
+		}
+		if let Some(destination) = response.attribute("Destination") {
+			if destination != self.acs_url {
+				return Err("The response is for another destination");
+			}
+		}
+		let status = child(response, PROTOCOL_NS, "Status")
+			.and_then(|status| child(status, PROTOCOL_NS, "StatusCode"))
+			.and_then(|code| code.attribute("Value"));
+		if status != Some(STATUS_SUCCESS) {
+			return Err("The response is not successful");
+		}
+		if child(response, ASSERTION_NS, "EncryptedAssertion").is_some() {
+			return Err("Encrypted assertions are not supported");
+		}
+		let mut assertions = response
+			.children()
+			.filter(|node| node.has_tag_name((ASSERTION_NS, "Assertion")));
+		let assertion = match (assertions.next(), assertions.next()) {
+			(Some(assertion), None) => assertion,
+			_ => return Err("The response must have a single assertion"),
+		};
+
+		// The claims are only read from the signed assertion
+		let signed = if child(assertion, DSIG_NS, "Signature").is_some() {
+			assertion
+		} else {
+			response
+		};
+		xmldsig::verify_enveloped(signed, &self.keys)?;
+
+		if let Some(ref idp_entity_id) = self.idp_entity_id {
+			let issuer = child(assertion, ASSERTION_NS, "Issuer").map(text_of);
+			if issuer.as_deref().map(|issuer| issuer.trim()) != Some(idp_entity_id.as_str()) {
+				return Err("The assertion is from another issuer");
+			}
+		}
+		self.validate_conditions(assertion, now)?;
+		let subject = child(assertion, ASSERTION_NS, "Subject").ok_or("Missing the subject")?;
+		let confirmed_until = self.validate_confirmation(subject, now)?;
+		let name_id = child(subject, ASSERTION_NS, "NameID")
+			.map(text_of)
+			.map(|name_id| name_id.trim().to_owned())
+			.filter(|name_id| !name_id.is_empty())
+			.ok_or("Missing the name id")?;
+		// The assertion is recorded until the confirmation expires, so it is not posted again
+		let id = assertion
+			.attribute("ID")
+			.ok_or("Missing the assertion id")?;
+		if !self.used.record(id, confirmed_until + CLOCK_SKEW, now) {
+			return Err("The assertion was already used");
+		}
+
+		let mut attributes = serde_json::Map::new();
+		for statement in assertion
+			.children()
+			.filter(|node| node.has_tag_name((ASSERTION_NS, "AttributeStatement")))
+		{
+			for attribute in statement
+				.children()
+				.filter(|node| node.has_tag_name((ASSERTION_NS, "Attribute")))
+			{
+				let name = match attribute.attribute("Name") {
+					Some(name) => name,
+					None => continue,
+				};
+				let values: Vec<JsonValue> = attribute
+					.children()
+					.filter(|node| node.has_tag_name((ASSERTION_NS, "AttributeValue")))
+					.map(|value| JsonValue::from(text_of(value)))
+					.collect();
+				attributes.insert(name.to_owned(), JsonValue::from(values));
+			}
+		}
+		Ok(SamlUser {
+			name_id,
+			attributes,
+		})
+	}
+	///
+	/// Check the time of the assertion, and that the audience is the service provider
+	///
+	fn validate_conditions(&self, assertion: Node, now: i64) -> Result<(), &'static str> {
+		let conditions =
+			child(assertion, ASSERTION_NS, "Conditions").ok_or("Missing the conditions")?;
+		if let Some(not_before) = conditions.attribute("NotBefore") {
+			if parse_instant(not_before).ok_or("Invalid NotBefore")? > now + CLOCK_SKEW {
+				return Err("The assertion is not valid yet");
+			}
+		}
+		if let Some(not_on_or_after) = conditions.attribute("NotOnOrAfter") {
+			if parse_instant(not_on_or_after).ok_or("Invalid NotOnOrAfter")? <= now - CLOCK_SKEW {
+				return Err("The assertion expired");
+			}
+		}
+		let restrictions: Vec<Node> = conditions
+			.children()
+			.filter(|node| node.has_tag_name((ASSERTION_NS, "AudienceRestriction")))
+			.collect();
+		let allowed = !restrictions.is_empty()
+			&& restrictions.iter().all(|restriction| {
+				restriction
+					.children()
+					.filter(|node| node.has_tag_name((ASSERTION_NS, "Audience")))
+					.any(|audience| text_of(audience).trim() == self.entity_id)
+			});
+		if !allowed {
+			return Err("The assertion is for another audience");
+		}
+		Ok(())
+	}
+	///
+	/// Check the bearer confirmation of the subject, which must be for the callback, returning
+	/// the time it expires
+	///
+	fn validate_confirmation(&self, subject: Node, now: i64) -> Result<i64, &'static str> {
+		subject
+			.children()
+			.filter(|node| node.has_tag_name((ASSERTION_NS, "SubjectConfirmation")))
+			.filter(|confirmation| confirmation.attribute("Method") == Some(BEARER))
+			.filter_map(|confirmation| child(confirmation, ASSERTION_NS, "SubjectConfirmationData"))
+			.filter(|data| data.attribute("Recipient") == Some(self.acs_url.as_str()))
+			.filter_map(|data| data.attribute("NotOnOrAfter").and_then(parse_instant))
+			.filter(|not_on_or_after| *not_on_or_after > now - CLOCK_SKEW)
+			.max()
+			.ok_or("The subject is not confirmed")
+	}
+}
+
+impl ProviderSaml {
+	///
+	/// Create a new SAML provider
+	///
+	pub fn new(settings: &Settings, used: ReplayCachePtr) -> Result<Self, Error> {
+		let provider = &settings.provider;
+		let sso_url = Url::parse(
+			provider
+				.auth_url
+				.as_deref()
+				.ok_or(Error::SettingsError("Missing the provider auth url"))?,
+		)?;
+		if provider.callback_url.is_empty() {
+			return Err(Error::SettingsError("Missing the provider callback url"));
+		}
+		let acs_url = Url::parse(&provider.callback_url)?;
+		// The entity id defaults to the url of the metadata
+		let entity_id = if provider.client_id.is_empty() {
+			acs_url.join("/auth/saml/metadata")?.to_string()
+		} else {
+			provider.client_id.clone()
+		};
+		let certificate_file =
+			provider
+				.saml_idp_certificate
+				.as_deref()
+				.ok_or(Error::SettingsError(
+					"Missing the provider saml idp certificate",
+				))?;
+		let pem = std::fs::read(certificate_file).map_err(|error| {
+			log::error!(
+				"Could not read the SAML certificate {}: {}",
+				certificate_file,
+				error
+			);
+			Error::SettingsError("Could not read the provider saml idp certificate")
+		})?;
+		let keys = X509::stack_from_pem(&pem)?
+			.iter()
+			.map(|certificate| certificate.public_key())
+			.collect::<Result<Vec<_>, _>>()?;
+		if keys.is_empty() {
+			return Err(Error::SettingsError(
+				"Missing the provider saml idp certificate",
+			));
+		}
+		let claims = parse_claims(provider.claims.as_deref().unwrap_or(DEFAULT_CLAIMS))?;
+		let issuer = SessionIssuer::new(
+			&settings.secret,
+			format!("saml:{}", sso_url),
+			provider.session_ttl.unwrap_or(DEFAULT_SESSION_TTL),
+		);
+		let logout_url = if provider.logout_redirect_url.is_empty() {
+			String::from("/")
+		} else {
+			provider.logout_redirect_url.clone()
+		};
+		Ok(Self {
+			sp: ServiceProvider {
+				entity_id,
+				acs_url: acs_url.to_string(),
+				idp_entity_id: provider.issuer.clone(),
+				keys,
+				used,
+			},
+			sso_url,
+			claims,
+			issuer,
+			logout_url,
+		})
+	}
+	///
+	/// Get the metadata of the service provider, to register it in the IdP
+	///
+	pub fn metadata(&self) -> String {
+		format!(
+			"<?xml version=\"1.0\"?>\n<md:EntityDescriptor xmlns:md=\"urn:oasis:names:tc:SAML:2.0:metadata\" entityID=\"{}\"><md:SPSSODescriptor AuthnRequestsSigned=\"false\" WantAssertionsSigned=\"true\" protocolSupportEnumeration=\"{}\"><md:AssertionConsumerService Binding=\"{}\" Location=\"{}\" index=\"0\" isDefault=\"true\"/></md:SPSSODescriptor></md:EntityDescriptor>\n",
+			escape(&self.sp.entity_id),
+			PROTOCOL_NS,
+			POST_BINDING,
+			escape(&self.sp.acs_url),
+		)
+	}
+	///
+	/// Get the userinfo of the session issued by the gatekeeper
+	///
+	pub async fn userinfo(&self, access_token: &str) -> Result<Option<Userinfo>, Error> {
+		Ok(self.issuer.userinfo(access_token))
+	}
+	///
+	/// Validate the response posted by the IdP (`SAMLResponse`), and issue a session with the
+	/// claims of the assertion
+	///
+	pub async fn grant_authorization_code(&self, code: &str) -> Result<Option<TokenSet>, Error> {
+		let response = base64::decode(code.split_whitespace().collect::<String>())
+			.ok()
+			.and_then(|response| String::from_utf8(response).ok());
+		let response = match response {
+			Some(response) => response,
+			None => {
+				log::info!("Invalid SAML response: not base64 encoded");
+				return Ok(None);
+			}
+		};
+		let now = jsonwebtoken::get_current_timestamp() as i64;
+		let user = match self.sp.validate(&response, now) {
+			Ok(user) => user,
+			Err(reason) => {
+				log::info!("Invalid SAML response: {}", reason);
+				return Ok(None);
+			}
+		};
+		let claims = map_claims(&self.claims, &user);
+		Ok(Some(self.issuer.issue(JsonValue::Object(claims))?))
+	}
+	///
+	/// There are no passwords, the users login in the IdP
+	///
+	pub async fn grant_password(
+		&self,
+		_username: &str,
+		_password: &str,
+	) -> Result<Option<TokenSet>, Error> {
+		Ok(None)
+	}
+	///
+	/// The sessions are not refreshed, the user must login again
+	///
+	pub async fn grant_refresh_token(
+		&self,
+		_refresh_token: &str,
+	) -> Result<Option<TokenSet>, Error> {
+		Ok(None)
+	}
+	///
+	/// There is no token exchange
+	///
+	pub async fn grant_token_exchange(
+		&self,
+		_subject_token: &str,
+		_audience: &str,
+		_scope: Option<&str>,
+	) -> Result<Option<AccessToken>, Error> {
+		Ok(None)
+	}
+	///
+	/// Create the AuthnRequest, asking the IdP to post the response to the callback
+	///
+	fn authn_request(&self) -> String {
+		let mut id = [0u8; 16];
+		let _ = ring::rand::SystemRandom::new().fill(&mut id);
+		let id: String = id.iter().map(|byte| format!("{:02x}", byte)).collect();
+		let now = time::OffsetDateTime::now_utc().format("%Y-%m-%dT%H:%M:%SZ");
+		format!(
+			"<samlp:AuthnRequest xmlns:samlp=\"{}\" xmlns:saml=\"{}\" ID=\"_{}\" Version=\"2.0\" IssueInstant=\"{}\" Destination=\"{}\" AssertionConsumerServiceURL=\"{}\" ProtocolBinding=\"{}\"><saml:Issuer>{}</saml:Issuer></samlp:AuthnRequest>",
+			PROTOCOL_NS,
+			ASSERTION_NS,
+			id,
+			now,
+			escape(self.sso_url.as_str()),
+			escape(&self.sp.acs_url),
+			POST_BINDING,
+			escape(&self.sp.entity_id),
+		)
+	}
+}
+
+impl Provider for ProviderSaml {
+	///
+	/// Get the url of the IdP with the AuthnRequest (HTTP-Redirect binding)
+	///
+	fn get_authorization_url(&self, state: String) -> String {
+		let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+		let request = encoder
+			.write_all(self.authn_request().as_bytes())
+			.and_then(|_| encoder.finish())
+			.map(base64::encode)
+			.unwrap_or_default();
+		let mut sso_url = self.sso_url.clone();
+		{
+			let mut query = sso_url.query_pairs_mut();
+			query.append_pair("SAMLRequest", &request);
+			if !state.is_empty() {
+				query.append_pair("RelayState", &state);
+			}
+		}
+		sso_url.to_string()
+	}
+	///
+	/// Get the logout url
+	///
+	fn get_logout_url(&self) -> String {
+		self.logout_url.clone()
+	}
+}
+
+///
+/// Parse the claims, as a comma delimited list of `CLAIM=ATTRIBUTE|ATTRIBUTE`
+///
+/// The attributes are names, which may be urls, so they have no paths like the oauth2 claims
+///
+fn parse_claims(value: &str) -> Result<Vec<(String, Vec<String>)>, Error> {
+	value
+		.split(',')
+		.map(|claim| claim.trim())
+		.filter(|claim| !claim.is_empty())
+		.map(|claim| {
+			let (name, sources) = claim
+				.split_once('=')
+				.ok_or(Error::SettingsError("Invalid provider claim"))?;
+			let sources = sources
+				.split('|')
+				.map(|source| source.trim().to_owned())
+				.filter(|source| !source.is_empty())
+				.collect();
+			Ok((name.trim().to_owned(), sources))
+		})
+		.collect()
+}
+
+///
+/// Map the user to the claims. The `sub` is the name id, unless it is mapped
+///
+/// Attributes with many values are arrays, and the `groups` and `roles` are always arrays
+///
+fn map_claims(
+	claims: &[(String, Vec<String>)],
+	user: &SamlUser,
+) -> serde_json::Map<String, JsonValue> {
+	let mut mapped = serde_json::Map::new();
+	mapped.insert(String::from("sub"), JsonValue::from(user.name_id.clone()));
+	for (name, sources) in claims {
+		let values = sources
+			.iter()
+			.find_map(|source| user.attributes.get(source))
+			.and_then(|values| values.as_array());
+		let mut values = match values {
+			Some(values) => values.clone(),
+			None => continue,
+		};
+		let value = if values.len() == 1 && name != "groups" && name != "roles" {
+			values.remove(0)
+		} else {
+			JsonValue::Array(values)
+		};
+		mapped.insert(name.clone(), value);
+	}
+	mapped
+}
+
+///
+/// Parse a time of SAML (Ex: `2024-01-01T12:00:00.123Z`) as seconds since the epoch
+///
+fn parse_instant(value: &str) -> Option<i64> {
+	let (date_time, rest) = (value.get(..19)?, &value[19..]);
+	let rest = rest.strip_suffix('Z').unwrap_or(rest);
+	if let Some(fraction) = rest.strip_prefix('.') {
+		if fraction.is_empty() || !fraction.chars().all(|c| c.is_ascii_digit()) {
+			return None;
+		}
+	} else if !rest.is_empty() {
+		return None;
+	}
+	let date_time = time::PrimitiveDateTime::parse(date_time, "%Y-%m-%dT%H:%M:%S").ok()?;
+	Some(date_time.assume_utc().unix_timestamp())
+}
+
+///
+/// Escape the value in the xml
+///
+fn escape(value: &str) -> String {
+	value
+		.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::util::replay::ReplayCache;
+	use openssl::rsa::Rsa;
+
+	const RESPONSE: &str = r##"<samlp:Response xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol" ID="r1" Version="2.0" Destination="https://app.example.com/auth/callback"><samlp:Status><samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:Success"/></samlp:Status><saml:Assertion xmlns:saml="urn:oasis:names:tc:SAML:2.0:assertion" ID="a1" Version="2.0"><saml:Issuer>https://idp.example.com</saml:Issuer><ds:Signature xmlns:ds="http://www.w3.org/2000/09/xmldsig#"><ds:SignedInfo><ds:CanonicalizationMethod Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"/><ds:SignatureMethod Algorithm="http://www.w3.org/2001/04/xmldsig-more#rsa-sha256"/><ds:Reference URI="#a1"><ds:Transforms><ds:Transform Algorithm="http://www.w3.org/2000/09/xmldsig#enveloped-signature"/><ds:Transform Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"/></ds:Transforms><ds:DigestMethod Algorithm="http://www.w3.org/2001/04/xmlenc#sha256"/><ds:DigestValue>DIGEST</ds:DigestValue></ds:Reference></ds:SignedInfo><ds:SignatureValue>SIGNATURE</ds:SignatureValue></ds:Signature><saml:Subject><saml:NameID>alice@example.com</saml:NameID><saml:SubjectConfirmation Method="urn:oasis:names:tc:SAML:2.0:cm:bearer"><saml:SubjectConfirmationData NotOnOrAfter="2024-01-01T12:05:00Z" Recipient="https://app.example.com/auth/callback"/></saml:SubjectConfirmation></saml:Subject><saml:Conditions NotBefore="2024-01-01T11:59:00.000Z" NotOnOrAfter="2024-01-01T12:05:00Z"><saml:AudienceRestriction><saml:Audience>https://app.example.com</saml:Audience></saml:AudienceRestriction></saml:Conditions><saml:AttributeStatement><saml:Attribute Name="http://schemas.xmlsoap.org/ws/2005/05/identity/claims/name"><saml:AttributeValue>Alice</saml:AttributeValue></saml:Attribute><saml:Attribute Name="http://schemas.microsoft.com/ws/2008/06/identity/claims/role"><saml:AttributeValue>admins</saml:AttributeValue></saml:Attribute><saml:Attribute Name="mail"><saml:AttributeValue>alice@example.com</saml:AttributeValue><saml:AttributeValue>a@example.com</saml:AttributeValue></saml:Attribute></saml:AttributeStatement></saml:Assertion></samlp:Response>"##;
+
+	#[test]
+	fn test_validate() {
+		let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+		let sp = ServiceProvider {
+			entity_id: String::from("https://app.example.com"),
+			acs_url: String::from("https://app.example.com/auth/callback"),
+			idp_entity_id: Some(String::from("https://idp.example.com")),
+			keys: vec![PKey::public_key_from_der(&key.public_key_to_der().unwrap()).unwrap()],
+			used: ReplayCache::create(),
+		};
+		let now = parse_instant("2024-01-01T12:00:00Z").unwrap();
+		let response = xmldsig::sign_enveloped(RESPONSE, &key);
+		let user = sp.validate(&response, now).unwrap();
+		assert_eq!("alice@example.com", user.name_id);
+		assert_eq!(
+			"The assertion was already used",
+			sp.validate(&response, now + 10).unwrap_err()
+		);
+		let unconfirmed = xmldsig::sign_enveloped(
+			&RESPONSE.replace(" Recipient=\"https://app.example.com/auth/callback\"", ""),
+			&key,
+		);
+		assert_eq!(
+			"The subject is not confirmed",
+			sp.validate(&unconfirmed, now).unwrap_err()
+		);
+		let claims = map_claims(&parse_claims(DEFAULT_CLAIMS).unwrap(), &user);
+		assert_eq!(
+			serde_json::json!({
+				"sub": "alice@example.com",
+				"email": ["alice@example.com", "a@example.com"],
+				"name": "Alice",
+				"groups": ["admins"],
+			}),
+			JsonValue::Object(claims)
+		);
+
+		let expired = parse_instant("2024-01-01T12:06:01Z").unwrap();
+		assert_eq!(
+			"The assertion expired",
+			sp.validate(&response, expired).unwrap_err()
+		);
+		let other = ServiceProvider {
+			entity_id: String::from("https://other.example.com"),
+			..sp
+		};
+		assert_eq!(
+			"The assertion is for another audience",
+			other.validate(&response, now).unwrap_err()
+		);
+		let sp = ServiceProvider {
+			entity_id: String::from("https://app.example.com"),
+			..other
+		};
+		assert_eq!(
+			"The digest of the signed element does not match",
+			sp.validate(&response.replace(">Alice<", ">Admin<"), now)
+				.unwrap_err()
+		);
+		// An unsigned assertion next to the signed one is not accepted
+		let start = response.find("<saml:Assertion").unwrap();
+		let wrapped = format!(
+			"{}<saml:Assertion xmlns:saml=\"urn:oasis:names:tc:SAML:2.0:assertion\" ID=\"a2\"/>{}",
+			&response[..start],
+			&response[start..]
+		);
+		assert_eq!(
+			"The response must have a single assertion",
+			sp.validate(&wrapped, now).unwrap_err()
+		);
+		assert_eq!(
+			"The response is not successful",
+			sp.validate(
+				&response.replace(":status:Success", ":status:Requester"),
+				now
+			)
+			.unwrap_err()
+		);
+	}
+
+	#[test]
+	fn test_parse_instant() {
+		assert_eq!(Some(1704110400), parse_instant("2024-01-01T12:00:00Z"));
+		assert_eq!(
+			Some(1704110400),
+			parse_instant("2024-01-01T12:00:00.1234567Z")
+		);
+		assert_eq!(Some(1704110400), parse_instant("2024-01-01T12:00:00"));
+		assert_eq!(None, parse_instant("2024-01-01T12:00:00+03:00"));
+		assert_eq!(None, parse_instant("2024-01-01"));
+	}
+}
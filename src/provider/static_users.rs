@@ -0,0 +1,450 @@
+use super::base::{AccessToken, Provider, TokenSet, Userinfo};
+use super::issuer::{SessionIssuer, DEFAULT_SESSION_TTL};
+use crate::error::Error;
+use crate::settings::Settings;
+use crate::util::jwt::JsonValue;
+use actix_web::web;
+use argon2::{PasswordHasher, PasswordVerifier};
+use ring::rand::SecureRandom;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the users file is checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+/// Hash checked for the unknown users of an empty file
+const UNKNOWN_USER_HASH: &str = "$2b$10$NA3gMXYkyJpJoigFxtka3.JFqdZJ00XSgOlvwUiFeD4BfXXP5f4eS";
+
+type Claims = serde_json::Map<String, JsonValue>;
+
+///
+/// A user of the file
+///
+#[derive(Clone)]
+struct StaticUser {
+	hash: String,
+	roles: Vec<String>,
+	claims: Claims,
+}
+
+///
+/// The users loaded from the file
+///
+/// The unknown users are checked against the decoy hash, which uses the scheme and the cost
+/// of most users, so they take as long as the known ones
+///
+struct LoadedUsers {
+	users: HashMap<String, StaticUser>,
+	decoy_hash: String,
+	modified: Option<SystemTime>,
+	checked_at: Instant,
+}
+
+///
+/// Static provider, with the users of a file like htpasswd
+///
+/// The gatekeeper issues its own sessions, which are refreshed while the user is in the file
+/// with the same password. The changes of the file are used without a restart.
+///
+pub struct ProviderStatic {
+	file: String,
+	loaded: Mutex<LoadedUsers>,
+	issuer: SessionIssuer,
+	logout_url: String,
+}
+
+impl ProviderStatic {
+	///
+	/// Create a new static provider
+	///
+	pub fn new(settings: &Settings) -> Result<Self, Error> {
+		let provider = &settings.provider;
+		let file = provider
+			.users_file
+			.clone()
+			.ok_or(Error::SettingsError("Missing the provider users file"))?;
+		let (users, decoy_hash) = load_users(&file)?;
+		let loaded = LoadedUsers {
+			users,
+			decoy_hash,
+			modified: get_modified(&file),
+			checked_at: Instant::now(),
+		};
+		let issuer = SessionIssuer::new(
+			&settings.secret,
+			format!("static:{}", file),
+			provider.session_ttl.unwrap_or(DEFAULT_SESSION_TTL),
+		);
+		let logout_url = if provider.logout_redirect_url.is_empty() {
+			String::from("/")
+		} else {
+			provider.logout_redirect_url.clone()
+		};
+		Ok(Self {
+			file,
+			loaded: Mutex::new(loaded),
+			issuer,
+			logout_url,
+		})
+	}
+	///
+	/// Get the userinfo of the session issued by the gatekeeper
+	///
+	pub async fn userinfo(&self, access_token: &str) -> Result<Option<Userinfo>, Error> {
+		Ok(self.issuer.userinfo(access_token))
+	}
+	///
+	/// There is no authorization server, so there are no codes
+	///
+	pub async fn grant_authorization_code(&self, _code: &str) -> Result<Option<TokenSet>, Error> {
+		Ok(None)
+	}
+	///
+	/// Check the password of the user, and issue a session with the claims
+	///
+	pub async fn grant_password(
+		&self,
+		username: &str,
+		password: &str,
+	) -> Result<Option<TokenSet>, Error> {
+		let (user, decoy_hash) = self.get_user(username).await;
+		let hash = match user {
+			Some(ref user) => user.hash.clone(),
+			None => decoy_hash,
+		};
+		let password = password.to_owned();
+		let valid = web::block(move || Ok::<_, ()>(verify_password(&password, &hash)))
+			.await
+			.map_err(|_| Error::BlockingCanceled)?;
+		match user {
+			Some(ref user) if valid => Ok(Some(self.issue(username, user)?)),
+			_ => {
+				log::info!("Invalid password of the static user '{}'", username);
+				Ok(None)
+			}
+		}
+	}
+	///
+	/// Issue a new session, while the user is in the file with the same password
+	///
+	pub async fn grant_refresh_token(
+		&self,
+		refresh_token: &str,
+	) -> Result<Option<TokenSet>, Error> {
+		let (username, version) = match self.issuer.refresh(refresh_token) {
+			Some(refresh) => refresh,
+			None => return Ok(None),
+		};
+		match self.get_user(&username).await.0 {
+			Some(ref user) if hash_version(&user.hash) == version => {
+				Ok(Some(self.issue(&username, user)?))
+			}
+			_ => {
+				log::info!("The session of the static user '{}' ended", username);
+				Ok(None)
+			}
+		}
+	}
+	///
+	/// There is no token exchange
+	///
+	pub async fn grant_token_exchange(
+		&self,
+		_subject_token: &str,
+		_audience: &str,
+		_scope: Option<&str>,
+	) -> Result<Option<AccessToken>, Error> {
+		Ok(None)
+	}
+	///
+	/// Issue the tokens of the user. The claims are `sub`, `preferred_username`, the ones of the
+	/// file and the `roles`
+	///
+	fn issue(&self, username: &str, user: &StaticUser) -> Result<TokenSet, Error> {
+		let mut claims = Claims::new();
+		claims.insert(String::from("sub"), JsonValue::from(username));
+		claims.insert(
+			String::from("preferred_username"),
+			JsonValue::from(username),
+		);
+		claims.extend(user.claims.clone());
+		if !user.roles.is_empty() {
+			claims.insert(String::from("roles"), JsonValue::from(user.roles.clone()));
+		}
+		self.issuer.issue_refreshable(
+			JsonValue::Object(claims),
+			username,
+			&hash_version(&user.hash),
+		)
+	}
+	///
+	/// Get the user and the decoy hash, reloading the file when it changes
+	///
+	async fn get_user(&self, username: &str) -> (Option<StaticUser>, String) {
+		self.reload().await;
+		let loaded = self.loaded.lock().unwrap();
+		(
+			loaded.users.get(username).cloned(),
+			loaded.decoy_hash.clone(),
+		)
+	}
+	///
+	/// Reload the file when it changes. The file is read on the blocking pool, without holding
+	/// the lock of the users
+	///
+	async fn reload(&self) {
+		let previous = {
+			let mut loaded = self.loaded.lock().unwrap();
+			if loaded.checked_at.elapsed() < RELOAD_INTERVAL {
+				return;
+			}
+			loaded.checked_at = Instant::now();
+			loaded.modified
+		};
+		let file = self.file.clone();
+		let result = web::block(move || {
+			let modified = get_modified(&file);
+			if modified == previous {
+				return Ok(None);
+			}
+			// The errors are not Send, so only their description leaves the blocking pool
+			load_users(&file)
+				.map(|(users, decoy_hash)| Some((users, decoy_hash, modified)))
+				.map_err(|error| format!("{:?}", error))
+		})
+		.await;
+		match result {
+			Ok(Some((users, decoy_hash, modified))) => {
+				log::info!("Static users reloaded");
+				let mut loaded = self.loaded.lock().unwrap();
+				loaded.users = users;
+				loaded.decoy_hash = decoy_hash;
+				loaded.modified = modified;
+			}
+			Ok(None) => {}
+			Err(error) => log::error!("Error reloading the static users: {:?}", error),
+		}
+	}
+}
+
+impl Provider for ProviderStatic {
+	///
+	/// There is no authorization server, the login page asks for the password
+	///
+	fn get_authorization_url(&self, _state: String) -> String {
+		String::from("/login")
+	}
+	///
+	/// Get the logout url
+	///
+	fn get_logout_url(&self) -> String {
+		self.logout_url.clone()
+	}
+}
+
+///
+/// Load the users file, and create the decoy hash for the unknown users
+///
+fn load_users(file: &str) -> Result<(HashMap<String, StaticUser>, String), Error> {
+	let content = std::fs::read_to_string(file).map_err(|error| {
+		log::error!("Could not read the users file {}: {}", file, error);
+		Error::SettingsError("Could not read the users file")
+	})?;
+	let users = parse_users(&content)?;
+	let decoy_hash = create_decoy_hash(&users)?;
+	Ok((users, decoy_hash))
+}
+
+///
+/// Parse the users. Each line is `USERNAME:HASH[:ROLES[:CLAIMS]]`, with a bcrypt or argon2
+/// hash, comma delimited roles and a json object with the claims
+///
+fn parse_users(content: &str) -> Result<HashMap<String, StaticUser>, Error> {
+	let mut users = HashMap::new();
+	for (index, line) in content.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		let invalid = || {
+			log::error!("Invalid user in the line {} of the users file", index + 1);
+			Error::SettingsError("Invalid user in the users file")
+		};
+		let mut parts = line.splitn(4, ':');
+		let (username, hash) = match (parts.next(), parts.next()) {
+			(Some(username), Some(hash)) if !username.is_empty() && is_supported_hash(hash) => {
+				(username, hash)
+			}
+			_ => return Err(invalid()),
+		};
+		let roles = parts
+			.next()
+			.unwrap_or("")
+			.split(',')
+			.map(|r| r.trim())
+			.filter(|r| !r.is_empty())
+			.map(|r| r.to_owned())
+			.collect();
+		let claims = match parts.next().map(|c| c.trim()) {
+			Some(claims) if !claims.is_empty() => match serde_json::from_str(claims) {
+				Ok(JsonValue::Object(claims)) => claims,
+				_ => return Err(invalid()),
+			},
+			_ => Claims::new(),
+		};
+		users.insert(
+			username.to_owned(),
+			StaticUser {
+				hash: hash.to_owned(),
+				roles,
+				claims,
+			},
+		);
+	}
+	Ok(users)
+}
+
+///
+/// Check if the hash is bcrypt (`htpasswd -B`) or argon2
+///
+fn is_supported_hash(hash: &str) -> bool {
+	["$2a$", "$2b$", "$2y$", "$argon2"]
+		.iter()
+		.any(|prefix| hash.starts_with(prefix))
+}
+
+///
+/// Verify the password using the hash
+///
+fn verify_password(password: &str, hash: &str) -> bool {
+	if hash.starts_with("$argon2") {
+		match argon2::PasswordHash::new(hash) {
+			Ok(hash) => argon2::Argon2::default()
+				.verify_password(password.as_bytes(), &hash)
+				.is_ok(),
+			Err(_) => false,
+		}
+	} else {
+		bcrypt::verify(password, hash).unwrap_or(false)
+	}
+}
+
+///
+/// Get the scheme and the cost of a hash. Ex: `$2b$10` or `$argon2id$v=19$m=19456,t=2,p=1`
+///
+fn hash_params(hash: &str) -> &str {
+	let fields = if hash.starts_with("$argon2") { 3 } else { 2 };
+	match hash.match_indices('$').nth(fields) {
+		Some((index, _)) => &hash[..index],
+		None => hash,
+	}
+}
+
+///
+/// Hash a random password using the scheme and the cost of most users
+///
+fn create_decoy_hash(users: &HashMap<String, StaticUser>) -> Result<String, Error> {
+	let mut counts: HashMap<&str, (usize, &str)> = HashMap::new();
+	for user in users.values() {
+		let count = counts
+			.entry(hash_params(&user.hash))
+			.or_insert((0, &user.hash));
+		count.0 += 1;
+	}
+	let hash = match counts
+		.into_iter()
+		.max_by_key(|(params, (count, _))| (*count, *params))
+	{
+		Some((_, (_, hash))) => hash,
+		None => return Ok(String::from(UNKNOWN_USER_HASH)),
+	};
+
+	let mut password = [0u8; 16];
+	ring::rand::SystemRandom::new()
+		.fill(&mut password)
+		.map_err(|_| Error::CryptoRandomBytesError)?;
+	let invalid = || Error::SettingsError("Invalid hash in the users file");
+	if hash.starts_with("$argon2") {
+		let parsed = argon2::PasswordHash::new(hash).map_err(|_| invalid())?;
+		let algorithm = argon2::Algorithm::try_from(parsed.algorithm).map_err(|_| invalid())?;
+		let version =
+			argon2::Version::try_from(parsed.version.unwrap_or(0x13)).map_err(|_| invalid())?;
+		let params = argon2::Params::try_from(&parsed).map_err(|_| invalid())?;
+		let salt =
+			argon2::password_hash::SaltString::encode_b64(&password).map_err(|_| invalid())?;
+		let decoy = argon2::Argon2::new(algorithm, version, params)
+			.hash_password(&password, &salt)
+			.map_err(|_| invalid())?;
+		Ok(decoy.to_string())
+	} else {
+		let cost = hash
+			.get(4..6)
+			.and_then(|cost| cost.parse().ok())
+			.ok_or_else(invalid)?;
+		bcrypt::hash(password, cost).map_err(|_| invalid())
+	}
+}
+
+///
+/// Version of the password in the refresh tokens, so changing it ends the sessions
+///
+fn hash_version(hash: &str) -> String {
+	let digest = ring::digest::digest(&ring::digest::SHA256, hash.as_bytes());
+	base64::encode_config(&digest.as_ref()[..12], base64::URL_SAFE_NO_PAD)
+}
+
+fn get_modified(file: &str) -> Option<SystemTime> {
+	std::fs::metadata(file).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[test]
+	fn test_users() {
+		let bcrypt_hash = bcrypt::hash("secret", 4).unwrap();
+		let salt = argon2::password_hash::SaltString::encode_b64(b"saltsalt").unwrap();
+		let argon2_hash = argon2::Argon2::default()
+			.hash_password(b"other", &salt)
+			.unwrap()
+			.to_string();
+		let content = format!(
+			"# Users\nalice:{}:admin, dev:{{\"email\":\"alice@example.com\"}}\n\nbob:{}\n",
+			bcrypt_hash, argon2_hash
+		);
+		let users = parse_users(&content).unwrap();
+		let alice = &users["alice"];
+		assert_eq!(vec!["admin", "dev"], alice.roles);
+		assert_eq!("alice@example.com", alice.claims["email"]);
+		assert!(verify_password("secret", &alice.hash));
+		assert!(!verify_password("other", &alice.hash));
+		let bob = &users["bob"];
+		assert!(bob.roles.is_empty());
+		assert!(verify_password("other", &bob.hash));
+		assert!(!verify_password("secret", &bob.hash));
+		assert!(!bcrypt::verify("secret", UNKNOWN_USER_HASH).unwrap());
+		assert_eq!("$2b$04", hash_params(&alice.hash));
+		assert_eq!("$argon2id$v=19$m=19456,t=2,p=1", hash_params(&bob.hash));
+
+		// The decoy uses the scheme and the cost of most users
+		let content = format!("{}carol:{}\n", content, bcrypt::hash("other", 4).unwrap());
+		let users = parse_users(&content).unwrap();
+		let decoy_hash = create_decoy_hash(&users).unwrap();
+		assert_eq!("$2b$04", hash_params(&decoy_hash));
+		assert!(!verify_password("secret", &decoy_hash));
+		let users = parse_users(&format!("bob:{}", argon2_hash)).unwrap();
+		assert_eq!(
+			"$argon2id$v=19$m=19456,t=2,p=1",
+			hash_params(&create_decoy_hash(&users).unwrap())
+		);
+		assert_eq!(
+			UNKNOWN_USER_HASH,
+			create_decoy_hash(&HashMap::new()).unwrap()
+		);
+
+		assert!(parse_users("alice:$apr1$salt$hash").is_err());
+		assert!(parse_users(&format!("alice:{}:admin:[]", bcrypt_hash)).is_err());
+		assert!(parse_users(&format!(":{}", bcrypt_hash)).is_err());
+	}
+}
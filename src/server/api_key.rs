@@ -0,0 +1,366 @@
+use super::trust::{Network, TrustedProxies};
+use crate::error::Error;
+use crate::settings::Settings;
+use crate::util::jwt::JsonValue;
+use actix_web::{
+	http::{header, HeaderName},
+	HttpRequest,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Prefix of the keys, so they can be found by secret scanners
+const KEY_PREFIX: &str = "agk";
+/// How often the key file is checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+///
+/// An api key, as saved in the key file. Only the hash of the key is kept
+///
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ApiKeyEntry {
+	pub id: String,
+	pub hash: String,
+	pub claims: JsonValue,
+	#[serde(default)]
+	pub roles: Vec<String>,
+	pub created_at: u64,
+	pub expires_at: Option<u64>,
+	#[serde(default)]
+	pub networks: Vec<String>,
+}
+
+///
+/// The key file
+///
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ApiKeyFile {
+	pub keys: Vec<ApiKeyEntry>,
+}
+
+///
+/// The keys loaded from the file
+///
+struct LoadedKeys {
+	keys: Vec<(ApiKeyEntry, Vec<Network>)>,
+	modified: Option<SystemTime>,
+	checked_at: Instant,
+}
+
+///
+/// Api keys for the service accounts
+///
+pub struct ApiKeys {
+	file: Option<String>,
+	header: Option<HeaderName>,
+	loaded: Mutex<LoadedKeys>,
+}
+
+impl ApiKeyFile {
+	///
+	/// Load the key file. A missing file has no keys
+	///
+	pub fn load(path: &str) -> Result<Self, Error> {
+		match std::fs::read(path) {
+			Ok(content) => Ok(serde_json::from_slice(&content)?),
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+			Err(error) => {
+				log::error!("Could not read the api key file {}: {}", path, error);
+				Err(Error::SettingsError("Could not read the api key file"))
+			}
+		}
+	}
+	///
+	/// Save the key file, replacing it at once so the gatekeeper never reads half of it
+	///
+	pub fn save(&self, path: &str) -> Result<(), Error> {
+		let content = serde_json::to_vec_pretty(self)?;
+		let temp_path = format!("{}.tmp", path);
+		let write = || -> std::io::Result<()> {
+			let mut options = std::fs::OpenOptions::new();
+			options.write(true).create(true).truncate(true);
+			#[cfg(unix)]
+			std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+			std::io::Write::write_all(&mut options.open(&temp_path)?, &content)?;
+			std::fs::rename(&temp_path, path)
+		};
+		write().map_err(|error| {
+			log::error!("Could not write the api key file {}: {}", path, error);
+			Error::SettingsError("Could not write the api key file")
+		})
+	}
+	///
+	/// Create a new key. Returns the key, which is not saved anywhere
+	///
+	pub fn create(
+		&mut self,
+		random: &dyn ring::rand::SecureRandom,
+		claims: JsonValue,
+		roles: Vec<String>,
+		expires_at: Option<u64>,
+		networks: Vec<String>,
+	) -> Result<(ApiKeyEntry, String), Error> {
+		for network in networks.iter() {
+			Network::parse(network)?;
+		}
+		let mut id = [0u8; 6];
+		let mut secret = [0u8; 32];
+		random
+			.fill(&mut id)
+			.and_then(|_| random.fill(&mut secret))
+			.map_err(|_| Error::CryptoRandomBytesError)?;
+		let id: String = id.iter().map(|b| format!("{:02x}", b)).collect();
+		let secret = base64::encode_config(secret, base64::URL_SAFE_NO_PAD);
+		let key = format!("{}_{}_{}", KEY_PREFIX, id, secret);
+
+		let entry = ApiKeyEntry {
+			id,
+			hash: hash_key(&key),
+			claims,
+			roles,
+			created_at: now(),
+			expires_at,
+			networks,
+		};
+		self.keys.push(entry.clone());
+		Ok((entry, key))
+	}
+	///
+	/// Revoke a key by its id
+	///
+	pub fn revoke(&mut self, id: &str) -> bool {
+		let count = self.keys.len();
+		self.keys.retain(|k| k.id != id);
+		count != self.keys.len()
+	}
+}
+
+impl ApiKeys {
+	///
+	/// Create using the key file in the settings
+	///
+	pub fn new(settings: &Settings) -> Result<Self, Error> {
+		let header = match settings.api_keys.header {
+			Some(ref header) => Some(
+				HeaderName::from_bytes(header.to_lowercase().as_bytes())
+					.map_err(|_| Error::SettingsError("Invalid api key header"))?,
+			),
+			None => None,
+		};
+		let api_keys = Self {
+			file: settings.api_keys.file.clone(),
+			header,
+			loaded: Mutex::new(LoadedKeys {
+				keys: Vec::new(),
+				modified: None,
+				checked_at: Instant::now(),
+			}),
+		};
+		if let Some(ref file) = api_keys.file {
+			let mut loaded = api_keys.loaded.lock().unwrap();
+			loaded.keys = Self::load(file)?;
+			loaded.modified = Self::get_modified(file);
+		}
+		Ok(api_keys)
+	}
+	///
+	/// Get the key sent in the request, using `Authorization: ApiKey KEY` or the header
+	///
+	pub fn get_request_key<'a>(&self, req: &'a HttpRequest) -> Option<&'a str> {
+		self.file.as_ref()?;
+		if let Some(ref header) = self.header {
+			if let Some(key) = req.headers().get(header).and_then(|h| h.to_str().ok()) {
+				return Some(key.trim());
+			}
+		}
+		let authorization = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+		if authorization.len() > 7 && authorization[..7].eq_ignore_ascii_case("apikey ") {
+			return Some(authorization[7..].trim());
+		}
+		None
+	}
+	///
+	/// Validate the key, and get the claims of the userinfo
+	///
+	/// The networks of the key are checked against the address of the client, read from the
+	/// x-forwarded-for header of the trusted `proxies`, so the clients can not choose it
+	///
+	pub fn validate(
+		&self,
+		req: &HttpRequest,
+		key: &str,
+		proxies: &TrustedProxies,
+	) -> Option<JsonValue> {
+		let file = self.file.as_ref()?;
+		let mut parts = key.splitn(3, '_');
+		let id = match (parts.next(), parts.next(), parts.next()) {
+			(Some(KEY_PREFIX), Some(id), Some(_)) => id,
+			_ => return None,
+		};
+
+		let mut loaded = self.loaded.lock().unwrap();
+		if loaded.checked_at.elapsed() >= RELOAD_INTERVAL {
+			loaded.checked_at = Instant::now();
+			let modified = Self::get_modified(file);
+			if modified != loaded.modified {
+				match Self::load(file) {
+					Ok(keys) => {
+						log::info!("Api keys reloaded");
+						loaded.keys = keys;
+						loaded.modified = modified;
+					}
+					Err(error) => log::error!("Error reloading the api keys: {:?}", error),
+				}
+			}
+		}
+
+		let (entry, networks) = loaded.keys.iter().find(|(k, _)| k.id == id)?;
+		let hash = hash_key(key);
+		if ring::constant_time::verify_slices_are_equal(hash.as_bytes(), entry.hash.as_bytes())
+			.is_err()
+		{
+			log::info!("Invalid api key {}", id);
+			return None;
+		}
+		if entry.expires_at.map(|e| e <= now()).unwrap_or(false) {
+			log::info!("Expired api key {}", id);
+			return None;
+		}
+		if !networks.is_empty() {
+			let ip = proxies.get_client_ip(req)?;
+			if !networks.iter().any(|n| n.contains(&ip)) {
+				log::info!("Api key {} used from {}, which is not allowed", id, ip);
+				return None;
+			}
+		}
+
+		let mut claims = match entry.claims {
+			JsonValue::Object(ref claims) => JsonValue::Object(claims.clone()),
+			_ => JsonValue::Object(serde_json::Map::new()),
+		};
+		if !entry.roles.is_empty() {
+			claims["roles"] = JsonValue::from(entry.roles.clone());
+		}
+		Some(claims)
+	}
+
+	fn load(file: &str) -> Result<Vec<(ApiKeyEntry, Vec<Network>)>, Error> {
+		let mut keys = Vec::new();
+		for entry in ApiKeyFile::load(file)?.keys {
+			let networks = entry
+				.networks
+				.iter()
+				.map(|n| Network::parse(n))
+				.collect::<Result<Vec<_>, _>>()?;
+			keys.push((entry, networks));
+		}
+		Ok(keys)
+	}
+
+	fn get_modified(file: &str) -> Option<SystemTime> {
+		std::fs::metadata(file).and_then(|m| m.modified()).ok()
+	}
+}
+
+///
+/// The keys have enough entropy, so a plain SHA-256 is used
+///
+fn hash_key(key: &str) -> String {
+	let digest = ring::digest::digest(&ring::digest::SHA256, key.as_bytes());
+	base64::encode_config(digest.as_ref(), base64::URL_SAFE_NO_PAD)
+}
+
+fn now() -> u64 {
+	SystemTime::now()
+		.duration_since(SystemTime::UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use actix_web::test::TestRequest;
+	#[test]
+	fn test_validate() {
+		let random = ring::rand::SystemRandom::new();
+		let mut file = ApiKeyFile::default();
+		let claims = serde_json::json!({ "sub": "ci" });
+		let (_, key) = file
+			.create(
+				&random,
+				claims,
+				vec![String::from("deploy")],
+				None,
+				Vec::new(),
+			)
+			.unwrap();
+		let (expired, expired_key) = file
+			.create(
+				&random,
+				serde_json::json!({}),
+				Vec::new(),
+				Some(1),
+				Vec::new(),
+			)
+			.unwrap();
+		let (_, restricted_key) = file
+			.create(
+				&random,
+				serde_json::json!({}),
+				Vec::new(),
+				None,
+				vec![String::from("10.0.0.0/8")],
+			)
+			.unwrap();
+
+		let path =
+			std::env::temp_dir().join(format!("auth-gatekeeper-keys-{}", std::process::id()));
+		let path = path.to_str().unwrap();
+		file.save(path).unwrap();
+		let api_keys = ApiKeys {
+			file: Some(path.to_owned()),
+			header: None,
+			loaded: Mutex::new(LoadedKeys {
+				keys: ApiKeys::load(path).unwrap(),
+				modified: None,
+				checked_at: Instant::now(),
+			}),
+		};
+		std::fs::remove_file(path).unwrap();
+		let proxies = TrustedProxies::parse(None).unwrap();
+
+		let req = TestRequest::default()
+			.header("authorization", format!("ApiKey {}", key))
+			.peer_addr("127.0.0.1:1000".parse().unwrap())
+			.to_http_request();
+		assert_eq!(Some(key.as_str()), api_keys.get_request_key(&req));
+		assert_eq!(
+			Some(serde_json::json!({ "sub": "ci", "roles": ["deploy"] })),
+			api_keys.validate(&req, &key, &proxies)
+		);
+		assert_eq!(
+			None,
+			api_keys.validate(&req, &format!("{}x", key), &proxies)
+		);
+		assert_eq!(None, api_keys.validate(&req, &expired_key, &proxies));
+		assert_eq!(None, api_keys.validate(&req, &restricted_key, &proxies));
+
+		// Behind a proxy, the address set by the client is not used, only the one of its peer
+		let proxies = TrustedProxies::parse(Some("127.0.0.1")).unwrap();
+		let req = TestRequest::default()
+			.header("x-forwarded-for", "10.0.0.1, 203.0.113.1")
+			.peer_addr("127.0.0.1:1000".parse().unwrap())
+			.to_http_request();
+		assert_eq!(None, api_keys.validate(&req, &restricted_key, &proxies));
+		let req = TestRequest::default()
+			.header("x-forwarded-for", "203.0.113.1, 10.0.0.1")
+			.peer_addr("127.0.0.1:1000".parse().unwrap())
+			.to_http_request();
+		assert!(api_keys.validate(&req, &restricted_key, &proxies).is_some());
+
+		assert!(file.revoke(&expired.id));
+		assert!(!file.revoke(&expired.id));
+	}
+}
@@ -0,0 +1,88 @@
+///
+/// Page to choose the provider to login, when there are named providers
+///
+/// The links keep the url to redirect after the login
+///
+pub fn render(names: &[&str], url: Option<&str>) -> String {
+	let mut links = String::new();
+	for name in names {
+		let mut query = url::form_urlencoded::Serializer::new(String::new());
+		query.append_pair("provider", name);
+		if let Some(url) = url {
+			query.append_pair("url", url);
+		}
+		links.push_str(&format!(
+			"<li><a href=\"?{}\">{}</a></li>\n",
+			escape(&query.finish()),
+			escape(name)
+		));
+	}
+	format!(
+		"<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Login</title>\n</head>\n<body>\n<h1>Login with</h1>\n<ul>\n{}</ul>\n</body>\n</html>\n",
+		links
+	)
+}
+
+///
+/// Page to login with a password, for the providers without an authorization server (Ex: ldap)
+///
+/// The form is posted to the login page, keeping the provider and the url to redirect after
+/// the login
+///
+pub fn render_password(provider: Option<&str>, url: Option<&str>, invalid: bool) -> String {
+	let mut query = url::form_urlencoded::Serializer::new(String::new());
+	if let Some(provider) = provider {
+		query.append_pair("provider", provider);
+	}
+	if let Some(url) = url {
+		query.append_pair("url", url);
+	}
+	let message = if invalid {
+		"<p>Invalid username or password</p>\n"
+	} else {
+		""
+	};
+	format!(
+		"<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Login</title>\n</head>\n<body>\n<h1>Login</h1>\n{}<form method=\"post\" action=\"?{}\">\n<input name=\"username\" placeholder=\"Username\" autocomplete=\"username\" required>\n<input name=\"password\" type=\"password\" placeholder=\"Password\" autocomplete=\"current-password\" required>\n<button type=\"submit\">Login</button>\n</form>\n</body>\n</html>\n",
+		message,
+		escape(&query.finish())
+	)
+}
+
+///
+/// Escape the text for the html
+///
+fn escape(text: &str) -> String {
+	let mut escaped = String::with_capacity(text.len());
+	for c in text.chars() {
+		match c {
+			'&' => escaped.push_str("&amp;"),
+			'<' => escaped.push_str("&lt;"),
+			'>' => escaped.push_str("&gt;"),
+			'"' => escaped.push_str("&quot;"),
+			'\'' => escaped.push_str("&#39;"),
+			_ => escaped.push(c),
+		}
+	}
+	escaped
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[test]
+	fn test_render() {
+		let page = render(&["employees", "contractors"], Some("/app?a=1&b=<2>"));
+		assert!(page.contains(
+			"<li><a href=\"?provider=employees&amp;url=%2Fapp%3Fa%3D1%26b%3D%3C2%3E\">employees</a></li>"
+		));
+		assert!(page.contains("<li><a href=\"?provider=contractors&amp;url="));
+		let page = render(&["employees"], None);
+		assert!(page.contains("<a href=\"?provider=employees\">"));
+
+		let page = render_password(Some("directory"), Some("/app?a=1"), false);
+		assert!(page.contains("action=\"?provider=directory&amp;url=%2Fapp%3Fa%3D1\""));
+		assert!(!page.contains("Invalid"));
+		assert!(render_password(None, None, true).contains("Invalid username or password"));
+	}
+}
@@ -0,0 +1,121 @@
+use crate::error::Error;
+use crate::settings::Settings;
+use crate::util::jwt::JsonValue;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Many proxies and servers limit each header to 8KB, warn before reaching it
+const HEADER_SIZE_WARNING: usize = 6 * 1024;
+
+///
+/// Filter and rename the claims sent in the x-auth-userinfo header
+///
+pub struct ClaimsFilter {
+	include: Option<Vec<String>>,
+	exclude: Vec<String>,
+	rename: Vec<(String, String)>,
+	size_warned: AtomicBool,
+}
+
+impl ClaimsFilter {
+	///
+	/// Create the filter using the lists in the settings
+	///
+	pub fn new(settings: &Settings) -> Result<Self, Error> {
+		let include = settings.claims_include.as_deref().map(Self::parse_list);
+		let exclude = settings
+			.claims_exclude
+			.as_deref()
+			.map(Self::parse_list)
+			.unwrap_or_default();
+		let mut rename = Vec::new();
+		if let Some(ref value) = settings.claims_rename {
+			for item in Self::parse_list(value) {
+				let mut item_split = item.splitn(2, '=');
+				let from = item_split.next().unwrap_or("").trim();
+				let to = item_split.next().unwrap_or("").trim();
+				if from.is_empty() || to.is_empty() {
+					return Err(Error::SettingsError("Invalid claim rename"));
+				}
+				rename.push((from.to_owned(), to.to_owned()));
+			}
+		}
+		Ok(Self {
+			include,
+			exclude,
+			rename,
+			size_warned: AtomicBool::new(false),
+		})
+	}
+	///
+	/// Apply the include list, then the exclude list and then the renames
+	///
+	pub fn apply(&self, claims: &JsonValue) -> JsonValue {
+		let object = match claims.as_object() {
+			Some(object) => object,
+			None => return claims.clone(),
+		};
+		let mut filtered = serde_json::Map::new();
+		for (name, value) in object {
+			if let Some(ref include) = self.include {
+				if !include.contains(name) {
+					continue;
+				}
+			}
+			if self.exclude.contains(name) {
+				continue;
+			}
+			filtered.insert(name.clone(), value.clone());
+		}
+		for (from, to) in self.rename.iter() {
+			if let Some(value) = filtered.remove(from) {
+				filtered.insert(to.clone(), value);
+			}
+		}
+		JsonValue::Object(filtered)
+	}
+	///
+	/// Log a warning, only once, when the header is close to the common size limits
+	///
+	pub fn check_size(&self, size: usize) {
+		if size >= HEADER_SIZE_WARNING && !self.size_warned.swap(true, Ordering::Relaxed) {
+			log::warn!(
+				"The x-auth-userinfo header has {} bytes and may be rejected by proxies. Use the claims settings to reduce it",
+				size
+			);
+		}
+	}
+
+	fn parse_list(value: &str) -> Vec<String> {
+		value
+			.split(',')
+			.map(|v| v.trim())
+			.filter(|v| !v.is_empty())
+			.map(|v| v.to_owned())
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+	#[test]
+	fn test_apply() {
+		let filter = ClaimsFilter {
+			include: Some(ClaimsFilter::parse_list("sub, email, realm_access")),
+			exclude: ClaimsFilter::parse_list("email"),
+			rename: vec![(String::from("realm_access"), String::from("roles"))],
+			size_warned: AtomicBool::new(false),
+		};
+		let claims = json!({
+			"sub": "user",
+			"email": "user@example.com",
+			"realm_access": { "roles": ["admin"] },
+			"session_state": "internal",
+		});
+		assert_eq!(
+			json!({ "sub": "user", "roles": { "roles": ["admin"] } }),
+			filter.apply(&claims)
+		);
+	}
+}
@@ -0,0 +1,271 @@
+use crate::error::Error;
+use crate::settings::Settings;
+use crate::util::jwt::JsonValue;
+use actix_web::{dev::Extensions, http::HeaderName, rt::net::TcpStream, HttpRequest};
+use openssl::nid::Nid;
+use openssl::x509::{X509NameRef, X509};
+use std::any::Any;
+
+///
+/// The client certificate verified by an https listener
+///
+struct PeerCertificate(X509);
+
+///
+/// Source of a claim in the certificate
+///
+enum ClaimSource {
+	Subject(Nid),
+	Issuer(Nid),
+	SanDns,
+	SanEmail,
+	SanUri,
+	SanIp,
+	Serial,
+	Fingerprint,
+}
+
+///
+/// Authentication using client certificates
+///
+pub struct ClientCertAuth {
+	claims: Vec<(String, ClaimSource)>,
+	header: Option<HeaderName>,
+}
+
+///
+/// Keep the client certificate of the https connections, so the requests can use it
+///
+pub fn on_connect(connection: &dyn Any, extensions: &mut Extensions) {
+	if let Some(stream) = connection.downcast_ref::<actix_tls::openssl::SslStream<TcpStream>>() {
+		if let Some(certificate) = stream.ssl().peer_certificate() {
+			extensions.insert(PeerCertificate(certificate));
+		}
+	}
+}
+
+impl ClaimSource {
+	///
+	/// Parse a source like `subject.CN`, `issuer.O`, `san.email`, `serial` or `fingerprint`
+	///
+	fn parse(value: &str) -> Result<Self, Error> {
+		let mut value_split = value.splitn(2, '.');
+		let kind = value_split.next().unwrap_or("");
+		let field = value_split.next().unwrap_or("");
+		let nid = || match field {
+			"CN" => Ok(Nid::COMMONNAME),
+			"O" => Ok(Nid::ORGANIZATIONNAME),
+			"OU" => Ok(Nid::ORGANIZATIONALUNITNAME),
+			"C" => Ok(Nid::COUNTRYNAME),
+			"ST" => Ok(Nid::STATEORPROVINCENAME),
+			"L" => Ok(Nid::LOCALITYNAME),
+			"emailAddress" => Ok(Nid::PKCS9_EMAILADDRESS),
+			_ => Err(Error::SettingsError(
+				"Invalid client certificate name field",
+			)),
+		};
+		match (kind, field) {
+			("subject", _) => Ok(ClaimSource::Subject(nid()?)),
+			("issuer", _) => Ok(ClaimSource::Issuer(nid()?)),
+			("san", "dns") => Ok(ClaimSource::SanDns),
+			("san", "email") => Ok(ClaimSource::SanEmail),
+			("san", "uri") => Ok(ClaimSource::SanUri),
+			("san", "ip") => Ok(ClaimSource::SanIp),
+			("serial", "") => Ok(ClaimSource::Serial),
+			("fingerprint", "") => Ok(ClaimSource::Fingerprint),
+			_ => Err(Error::SettingsError("Invalid client certificate claim")),
+		}
+	}
+	///
+	/// Get the values in the certificate
+	///
+	fn get_values(&self, certificate: &X509) -> Vec<String> {
+		let names = |name: &X509NameRef, nid: Nid| -> Vec<String> {
+			name.entries_by_nid(nid)
+				.filter_map(|e| e.data().as_utf8().ok().map(|v| v.to_string()))
+				.collect()
+		};
+		let alt_names = |get: fn(&openssl::x509::GeneralNameRef) -> Option<String>| -> Vec<String> {
+			certificate
+				.subject_alt_names()
+				.map(|names| names.iter().filter_map(get).collect())
+				.unwrap_or_default()
+		};
+		match self {
+			ClaimSource::Subject(nid) => names(certificate.subject_name(), *nid),
+			ClaimSource::Issuer(nid) => names(certificate.issuer_name(), *nid),
+			ClaimSource::SanDns => alt_names(|n| n.dnsname().map(|v| v.to_owned())),
+			ClaimSource::SanEmail => alt_names(|n| n.email().map(|v| v.to_owned())),
+			ClaimSource::SanUri => alt_names(|n| n.uri().map(|v| v.to_owned())),
+			ClaimSource::SanIp => alt_names(|n| {
+				n.ipaddress().and_then(|ip| match ip.len() {
+					4 => Some(std::net::Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]).to_string()),
+					16 => {
+						let mut octets = [0u8; 16];
+						octets.copy_from_slice(ip);
+						Some(std::net::Ipv6Addr::from(octets).to_string())
+					}
+					_ => None,
+				})
+			}),
+			ClaimSource::Serial => certificate
+				.serial_number()
+				.to_bn()
+				.and_then(|s| s.to_hex_str().map(|h| h.to_string()))
+				.map(|s| vec![s])
+				.unwrap_or_default(),
+			ClaimSource::Fingerprint => certificate
+				.digest(openssl::hash::MessageDigest::sha256())
+				.map(|d| vec![d.iter().map(|b| format!("{:02x}", b)).collect()])
+				.unwrap_or_default(),
+		}
+	}
+}
+
+impl ClientCertAuth {
+	///
+	/// Create using the claims and the header in the settings
+	///
+	pub fn new(settings: &Settings) -> Result<Self, Error> {
+		let mut claims = Vec::new();
+		for claim in settings.client_cert.claims.split(',') {
+			if claim.trim().is_empty() {
+				continue;
+			}
+			let mut claim_split = claim.splitn(2, '=');
+			let name = claim_split.next().unwrap_or("").trim();
+			let source = claim_split.next().unwrap_or("").trim();
+			if name.is_empty() {
+				return Err(Error::SettingsError("Invalid client certificate claim"));
+			}
+			claims.push((name.to_owned(), ClaimSource::parse(source)?));
+		}
+		// Anyone could send the header without a list of the proxies allowed to send it
+		if settings.client_cert.header.is_some() && settings.trusted_proxies.is_none() {
+			return Err(Error::SettingsError(
+				"The client certificate header requires the trusted proxies",
+			));
+		}
+		let header = match settings.client_cert.header {
+			Some(ref header) => Some(
+				HeaderName::from_bytes(header.to_lowercase().as_bytes())
+					.map_err(|_| Error::SettingsError("Invalid client certificate header"))?,
+			),
+			None => None,
+		};
+		Ok(Self { claims, header })
+	}
+	///
+	/// Get the claims of the client certificate
+	///
+	/// The certificate verified by the https listener is preferred. The header is only used
+	/// when the request comes from a `trusted` proxy.
+	///
+	pub fn get_claims(&self, req: &HttpRequest, trusted: bool) -> Option<JsonValue> {
+		if let Some(certificate) = req.extensions().get::<PeerCertificate>() {
+			return Some(self.map_claims(&certificate.0));
+		}
+		let header = self.header.as_ref().filter(|_| trusted)?;
+		let value = req.headers().get(header)?.to_str().ok()?;
+		let certificate = Self::parse_header(value)?;
+		Some(self.map_claims(&certificate))
+	}
+	///
+	/// Map the certificate fields to the claims. Fields with many values become arrays
+	///
+	fn map_claims(&self, certificate: &X509) -> JsonValue {
+		let mut claims = serde_json::Map::new();
+		for (name, source) in self.claims.iter() {
+			let mut values = source.get_values(certificate);
+			let value = match values.len() {
+				0 => continue,
+				1 => JsonValue::String(values.remove(0)),
+				_ => JsonValue::from(values),
+			};
+			claims.insert(name.clone(), value);
+		}
+		JsonValue::Object(claims)
+	}
+	///
+	/// Parse the certificate sent by the proxy
+	///
+	/// Accepts the envoy `x-forwarded-client-cert` format (using the `Cert` field), an url
+	/// encoded PEM (nginx `$ssl_client_escaped_cert`) or a base64 DER (traefik).
+	///
+	fn parse_header(value: &str) -> Option<X509> {
+		let value = value
+			.split(';')
+			.find_map(|field| field.trim().strip_prefix("Cert="))
+			.map(|cert| cert.trim_matches('"'))
+			.unwrap_or(value);
+		let decoded = percent_encoding::percent_decode_str(value)
+			.decode_utf8()
+			.ok()?;
+		if decoded.contains("-----BEGIN") {
+			return X509::from_pem(decoded.as_bytes()).ok();
+		}
+		let der = base64::decode(decoded.split_whitespace().collect::<String>()).ok()?;
+		X509::from_der(&der).ok()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[test]
+	fn test_claims() {
+		let mut name = openssl::x509::X509NameBuilder::new().unwrap();
+		name.append_entry_by_nid(Nid::COMMONNAME, "alice").unwrap();
+		name.append_entry_by_nid(Nid::ORGANIZATIONALUNITNAME, "admin")
+			.unwrap();
+		name.append_entry_by_nid(Nid::ORGANIZATIONALUNITNAME, "dev")
+			.unwrap();
+		let name = name.build();
+		let key =
+			openssl::pkey::PKey::from_rsa(openssl::rsa::Rsa::generate(2048).unwrap()).unwrap();
+		let mut builder = X509::builder().unwrap();
+		builder.set_subject_name(&name).unwrap();
+		builder.set_issuer_name(&name).unwrap();
+		builder.set_pubkey(&key).unwrap();
+		let not_before = openssl::asn1::Asn1Time::days_from_now(0).unwrap();
+		let not_after = openssl::asn1::Asn1Time::days_from_now(1).unwrap();
+		builder.set_not_before(&not_before).unwrap();
+		builder.set_not_after(&not_after).unwrap();
+		builder
+			.sign(&key, openssl::hash::MessageDigest::sha256())
+			.unwrap();
+		let certificate = builder.build();
+
+		let auth = ClientCertAuth {
+			claims: vec![
+				(
+					String::from("sub"),
+					ClaimSource::parse("subject.CN").unwrap(),
+				),
+				(
+					String::from("roles"),
+					ClaimSource::parse("subject.OU").unwrap(),
+				),
+				(
+					String::from("email"),
+					ClaimSource::parse("san.email").unwrap(),
+				),
+			],
+			header: None,
+		};
+		let expected = serde_json::json!({ "sub": "alice", "roles": ["admin", "dev"] });
+		assert_eq!(expected, auth.map_claims(&certificate));
+
+		let pem = String::from_utf8(certificate.to_pem().unwrap()).unwrap();
+		let escaped =
+			percent_encoding::utf8_percent_encode(&pem, percent_encoding::NON_ALPHANUMERIC)
+				.to_string();
+		let xfcc = format!("Hash=abc;Cert=\"{}\";Subject=\"CN=alice\"", escaped);
+		let der = base64::encode(certificate.to_der().unwrap());
+		for value in [escaped, xfcc, der].iter() {
+			let parsed = ClientCertAuth::parse_header(value).unwrap();
+			assert_eq!(expected, auth.map_claims(&parsed));
+		}
+		assert!(ClaimSource::parse("subject.XX").is_err());
+	}
+}
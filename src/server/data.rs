@@ -1,9 +1,20 @@
+use super::api_key::ApiKeys;
+use super::basic_auth::{BasicAuth, BasicAuthStatePtr};
+use super::claims::ClaimsFilter;
+use super::client_cert::ClientCertAuth;
+use super::exchange::TokenExchange;
+use super::profile::ProxyProfile;
+use super::proxy::Proxy;
+use super::redirect::AllowedRedirects;
+use super::stats::StatsPtr;
+use super::trust::TrustedProxies;
 use crate::api::Api;
 use crate::error::Error;
-use crate::provider::{create_provider, ProviderBox};
+use crate::provider::{create_providers, Providers};
 use crate::settings::Settings;
 use crate::util::crypto::{Crypto, RandomPtr};
-use crate::util::jwt::JWT;
+use crate::util::jwt::{JwtKeysPtr, JWT};
+use crate::util::replay::ReplayCachePtr;
 
 #[allow(dead_code)]
 pub struct Data {
@@ -12,22 +23,59 @@ pub struct Data {
 	pub crypto: Crypto,
 	pub jwt: JWT,
 	pub api: Api,
-	pub provider: ProviderBox,
+	pub providers: Providers,
+	pub profile: ProxyProfile,
+	pub proxy: Proxy,
+	pub exchange: TokenExchange,
+	pub claims: ClaimsFilter,
+	pub trusted_proxies: TrustedProxies,
+	pub client_cert: ClientCertAuth,
+	pub api_keys: ApiKeys,
+	pub basic_auth: BasicAuth,
+	pub redirects: AllowedRedirects,
+	pub stats: StatsPtr,
 }
 
 impl Data {
-	pub fn new(settings: Settings, random: RandomPtr) -> Result<Self, Error> {
+	pub fn new(
+		settings: Settings,
+		random: RandomPtr,
+		stats: StatsPtr,
+		replay: ReplayCachePtr,
+		basic_auth: BasicAuthStatePtr,
+		jwt_keys: Option<JwtKeysPtr>,
+	) -> Result<Self, Error> {
 		let crypto = Crypto::new(&settings.secret, random.clone());
-		let jwt = JWT::new(settings.jwt_secret.clone())?;
+		let jwt = JWT::from_settings(&settings, random.clone(), jwt_keys)?;
 		let api = Api::new(&settings)?;
-		let provider = create_provider(&settings)?;
+		let providers = create_providers(&settings, &replay)?;
+		let profile = ProxyProfile::from_name(&settings.proxy.profile)
+			.ok_or(Error::SettingsError("Invalid proxy profile"))?;
+		let proxy = Proxy::new(&settings)?;
+		let exchange = TokenExchange::new(&settings)?;
+		let claims = ClaimsFilter::new(&settings)?;
+		let trusted_proxies = TrustedProxies::new(&settings)?;
+		let client_cert = ClientCertAuth::new(&settings)?;
+		let api_keys = ApiKeys::new(&settings)?;
+		let basic_auth = BasicAuth::new(&settings, basic_auth)?;
+		let redirects = AllowedRedirects::new(&settings)?;
 		Ok(Self {
 			random: random,
 			settings: settings,
 			crypto: crypto,
 			jwt: jwt,
 			api: api,
-			provider: provider,
+			providers,
+			profile,
+			proxy,
+			exchange,
+			claims,
+			trusted_proxies,
+			client_cert,
+			api_keys,
+			basic_auth,
+			redirects,
+			stats,
 		})
 	}
 }
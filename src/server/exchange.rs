@@ -0,0 +1,169 @@
+use super::proxy::is_path_prefix;
+use crate::error::Error;
+use crate::provider::ProviderBox;
+use crate::settings::Settings;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Exchanged tokens are renewed a few seconds before they expire
+const EXPIRATION_MARGIN: Duration = Duration::from_secs(10);
+/// Used when the provider does not tell when the exchanged token expires
+const DEFAULT_EXPIRATION: Duration = Duration::from_secs(60);
+
+///
+/// A target audience for the token exchange
+///
+pub struct ExchangeTarget {
+	audience: String,
+	scope: Option<String>,
+	path: Option<String>,
+}
+
+///
+/// Token exchange (RFC 8693) targets, and the cache of the exchanged tokens
+///
+pub struct TokenExchange {
+	targets: Vec<ExchangeTarget>,
+	cache: Mutex<HashMap<Vec<u8>, (String, Instant)>>,
+}
+
+impl ExchangeTarget {
+	///
+	/// Parse a target in the format `AUDIENCE[;scope=SCOPE][;path=PREFIX]`
+	///
+	fn parse(value: &str) -> Result<Self, Error> {
+		let mut options = value.split(';');
+		let audience = options.next().unwrap_or("").trim();
+		if audience.is_empty() {
+			return Err(Error::SettingsError("Invalid token exchange audience"));
+		}
+		let mut target = Self {
+			audience: audience.to_owned(),
+			scope: None,
+			path: None,
+		};
+		for option in options {
+			let mut option_split = option.splitn(2, '=');
+			let name = option_split.next().unwrap_or("").trim();
+			let value = option_split.next().map(|v| v.trim().to_owned());
+			match name {
+				"scope" => target.scope = value,
+				"path" => target.path = value,
+				_ => return Err(Error::SettingsError("Invalid token exchange option")),
+			}
+		}
+		Ok(target)
+	}
+}
+
+impl TokenExchange {
+	///
+	/// Create the token exchange using the targets in the settings
+	///
+	pub fn new(settings: &Settings) -> Result<Self, Error> {
+		let mut targets = Vec::new();
+		if let Some(ref value) = settings.token_exchange {
+			for target in value.split(',').filter(|t| !t.trim().is_empty()) {
+				targets.push(ExchangeTarget::parse(target)?);
+			}
+		}
+		Ok(Self {
+			targets,
+			cache: Mutex::new(HashMap::new()),
+		})
+	}
+	///
+	/// Find the target by the audience, or by the longest path prefix of the uri, matching whole
+	/// segments
+	///
+	pub fn find(&self, audience: Option<&str>, uri: &str) -> Option<&ExchangeTarget> {
+		if let Some(audience) = audience {
+			return self.targets.iter().find(|t| t.audience == audience);
+		}
+		let path = uri.split(['?', '#']).next().unwrap_or("");
+		self.targets
+			.iter()
+			.filter(|t| {
+				t.path
+					.as_ref()
+					.map(|p| is_path_prefix(p, path))
+					.unwrap_or(false)
+			})
+			.max_by_key(|t| t.path.as_ref().map(|p| p.len()))
+	}
+	///
+	/// Exchange the access token, using the cached token while it is not expired
+	///
+	pub async fn exchange(
+		&self,
+		provider: &ProviderBox,
+		access_token: &str,
+		target: &ExchangeTarget,
+	) -> Result<Option<String>, Error> {
+		let key = Self::get_cache_key(access_token, target);
+		let now = Instant::now();
+		if let Some((token, expires_at)) = self.cache.lock().unwrap().get(&key) {
+			if now + EXPIRATION_MARGIN < *expires_at {
+				return Ok(Some(token.clone()));
+			}
+		}
+
+		let scope = target.scope.as_deref();
+		let exchanged = provider
+			.grant_token_exchange(access_token, &target.audience, scope)
+			.await?;
+		let exchanged = match exchanged {
+			Some(exchanged) => exchanged,
+			None => return Ok(None),
+		};
+		let expires_in = exchanged
+			.expires_in
+			.map(|e| Duration::from_secs(e.max(0) as u64))
+			.unwrap_or(DEFAULT_EXPIRATION);
+
+		let mut cache = self.cache.lock().unwrap();
+		cache.retain(|_, (_, expires_at)| now < *expires_at);
+		cache.insert(key, (exchanged.access_token.clone(), now + expires_in));
+		Ok(Some(exchanged.access_token))
+	}
+	///
+	/// The cache is keyed by a digest, so the access tokens are not kept in memory
+	///
+	fn get_cache_key(access_token: &str, target: &ExchangeTarget) -> Vec<u8> {
+		let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+		context.update(access_token.as_bytes());
+		context.update(b"\n");
+		context.update(target.audience.as_bytes());
+		context.update(b"\n");
+		context.update(target.scope.as_deref().unwrap_or("").as_bytes());
+		context.finish().as_ref().to_vec()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[test]
+	fn test_find() {
+		let exchange = TokenExchange {
+			targets: vec![
+				ExchangeTarget::parse("api;path=/api").unwrap(),
+				ExchangeTarget::parse("billing;scope=read write;path=/api/billing").unwrap(),
+				ExchangeTarget::parse("other").unwrap(),
+			],
+			cache: Mutex::new(HashMap::new()),
+		};
+		let find = |audience, uri| exchange.find(audience, uri).map(|t| t.audience.as_str());
+		assert_eq!(Some("api"), find(None, "/api/users"));
+		assert_eq!(Some("billing"), find(None, "/api/billing/1"));
+		assert_eq!(None, find(None, "/app"));
+		assert_eq!(Some("api"), find(None, "/api"));
+		assert_eq!(Some("api"), find(None, "/api?id=1"));
+		assert_eq!(None, find(None, "/apiary"));
+		assert_eq!(Some("api"), find(None, "/api/billingx"));
+		assert_eq!(Some("other"), find(Some("other"), "/api/users"));
+		assert_eq!(None, find(Some("unknown"), "/api/users"));
+		assert!(ExchangeTarget::parse("api;other=1").is_err());
+	}
+}
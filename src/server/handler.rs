@@ -1,16 +1,28 @@
+use super::basic_auth::BasicAuthStatePtr;
+use super::chooser;
 use super::data::Data;
-use super::state::State;
+use super::profile::ProxyProfile;
+use super::state::{DeviceCode, State};
+use super::stats::StatsPtr;
+use super::vhost::VirtualHost;
 use crate::error::Error;
+use crate::provider::DeviceGrant;
 use crate::session::{Session, SessionAuthMethod, SessionFlags};
 use crate::settings::Settings;
 use crate::util::crypto;
-use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use crate::util::jwt::JwtKeysPtr;
+use crate::util::replay::ReplayCachePtr;
+use actix_web::{guard, web, HttpRequest, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
 
+/// Bytes of the SAML responses posted to the callback
+const SAML_RESPONSE_LIMIT: usize = 256 * 1024;
+
 #[derive(Deserialize)]
 struct LoginQuery {
 	state: Option<String>,
 	url: Option<String>,
+	provider: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -19,9 +31,23 @@ struct CallbackQuery {
 	code: Option<String>,
 }
 
+#[derive(Deserialize)]
+struct CallbackForm {
+	#[serde(rename = "SAMLResponse")]
+	saml_response: Option<String>,
+	#[serde(rename = "RelayState")]
+	relay_state: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProviderQuery {
+	provider: Option<String>,
+}
+
 #[derive(Deserialize)]
 struct AuthLoginQuery {
 	url: Option<String>,
+	provider: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -30,9 +56,23 @@ struct AuthLoginForm {
 	password: String,
 }
 
+#[derive(Deserialize)]
+struct DeviceTokenForm {
+	device_code: String,
+}
+
+#[derive(Deserialize)]
+struct AuthValidateQuery {
+	roles: Option<String>,
+	exchange: Option<String>,
+}
+
 #[derive(Deserialize)]
 struct AuthForwardAuthQuery {
 	redirect: Option<String>,
+	roles: Option<String>,
+	profile: Option<String>,
+	exchange: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -40,19 +80,48 @@ struct AuthForwardLoginResponse {
 	authorization: Option<String>,
 }
 
+#[derive(Serialize)]
+struct DeviceErrorResponse {
+	error: String,
+}
+
 ///
 /// Redirect to the login url using authorization_code flow
 ///
+/// With many named providers, the provider is chosen using the query or the chooser page, and is
+/// recorded in the state for the callback. The providers without an authorization server show
+/// a page asking for the password
+///
 async fn route_login(
 	data: web::Data<Data>,
 	query: web::Query<LoginQuery>,
 ) -> Result<impl Responder, Error> {
-	let state_str = if let Some(ref state) = query.state {
-		state.clone()
+	let (state_str, name) = if let Some(ref state) = query.state {
+		let name = State::deserialize_state(&data.crypto, state)
+			.ok()
+			.and_then(|s| s.provider);
+		(state.clone(), name)
+	} else if query.provider.is_none() && data.providers.names().len() > 1 {
+		let page = chooser::render(&data.providers.names(), query.url.as_deref());
+		return Ok(HttpResponse::Ok().content_type("text/html").body(page));
 	} else {
-		State::serialize_state(&data.crypto, query.url.clone())?
+		let name = match data.providers.select(query.provider.as_deref()) {
+			Some((name, _)) => name,
+			None => return Ok(HttpResponse::BadRequest().finish()),
+		};
+		let state = State::serialize_state(&data.crypto, query.url.clone(), name.clone())?;
+		(state, name)
+	};
+	let provider = match data.providers.get(name.as_deref()) {
+		Some(provider) => provider,
+		None => return Ok(HttpResponse::BadRequest().finish()),
 	};
-	let url = data.provider.get_authorization_url(state_str);
+	// Providers without an authorization server ask for the password
+	if provider.is_password_only() {
+		let page = chooser::render_password(name.as_deref(), query.url.as_deref(), false);
+		return Ok(HttpResponse::Ok().content_type("text/html").body(page));
+	}
+	let url = provider.get_authorization_url(state_str);
 	let response = HttpResponse::Found().header("location", url).finish();
 	Ok(response)
 }
@@ -66,34 +135,43 @@ async fn route_post_login(
 	query: web::Query<AuthLoginQuery>,
 	form: web::Form<AuthLoginForm>,
 ) -> Result<impl Responder, Error> {
+	let (name, provider) = match data.providers.select(query.provider.as_deref()) {
+		Some(selected) => selected,
+		None => return Ok(HttpResponse::BadRequest().finish()),
+	};
+
 	// Perform the grant
-	let token_set = data
-		.provider
+	let token_set = provider
 		.grant_password(&form.username, &form.password)
 		.await?;
 	if token_set.is_none() {
+		if provider.is_password_only() {
+			let page = chooser::render_password(name.as_deref(), query.url.as_deref(), true);
+			return Ok(HttpResponse::Unauthorized()
+				.content_type("text/html")
+				.body(page));
+		}
 		return Ok(HttpResponse::Unauthorized().finish());
 	}
 
 	// Create the response and redirects
 	let mut builder = HttpResponse::Found();
-	let session = Session::new(data.clone(), token_set.unwrap());
+	let session = Session::new(data.clone(), name, token_set.unwrap());
 	session
 		.response(&req, &mut builder, SessionFlags::COOKIES)
 		.await?;
-	if let Some(ref url) = query.url {
-		builder.header("location", url.clone());
-	} else {
-		builder.header("location", "/");
-	}
+	builder.header("location", data.redirects.get_location(query.url.clone()));
 	Ok(builder.finish())
 }
 
 ///
-/// Logout
+/// Logout, using the provider of the session
 ///
 async fn route_logout(data: web::Data<Data>, req: HttpRequest) -> Result<impl Responder, Error> {
-	let url = data.provider.get_logout_url();
+	let url = Session::from_request(data.clone(), &req)
+		.get_provider()
+		.unwrap_or_else(|| data.providers.get_default())
+		.get_logout_url();
 	let session = Session::logout(data);
 	let mut builder = HttpResponse::Found();
 	builder.header("location", url);
@@ -111,40 +189,98 @@ async fn route_callback(
 	req: HttpRequest,
 	query: web::Query<CallbackQuery>,
 ) -> Result<impl Responder, Error> {
+	callback(
+		data,
+		req,
+		query.code.as_deref(),
+		query.state.as_deref(),
+		false,
+	)
+	.await
+}
+
+///
+/// Callback of the SAML responses, posted by the IdP (HTTP-POST binding)
+///
+async fn route_post_callback(
+	data: web::Data<Data>,
+	req: HttpRequest,
+	form: web::Form<CallbackForm>,
+) -> Result<impl Responder, Error> {
+	callback(
+		data,
+		req,
+		form.saml_response.as_deref(),
+		form.relay_state.as_deref(),
+		true,
+	)
+	.await
+}
+
+///
+/// Grant the code using the provider of the login, and create the session
+///
+/// The SAML responses are only accepted when posted, and the codes of the other providers only
+/// in the query
+///
+async fn callback(
+	data: web::Data<Data>,
+	req: HttpRequest,
+	code: Option<&str>,
+	state: Option<&str>,
+	posted: bool,
+) -> Result<HttpResponse, Error> {
 	// No query code, so unauthorized
-	if query.code.is_none() {
+	let code = match code {
+		Some(code) => code,
+		None => return Ok(HttpResponse::Unauthorized().finish()),
+	};
+
+	// The code is granted by the provider of the login
+	let state = state.and_then(|state| State::deserialize_state(&data.crypto, state).ok());
+	let provider_name = state.as_ref().and_then(|s| s.provider.as_deref());
+	let (name, provider) = match data.providers.select(provider_name) {
+		Some(selected) => selected,
+		None => return Ok(HttpResponse::Unauthorized().finish()),
+	};
+	if provider.is_saml() != posted {
 		return Ok(HttpResponse::Unauthorized().finish());
 	}
 
 	// Try to request an access token
-	let token_set = data
-		.provider
-		.grant_authorization_code(&query.code.as_ref().unwrap())
-		.await?;
+	let token_set = provider.grant_authorization_code(code).await?;
 	if token_set.is_none() {
 		return Ok(HttpResponse::Unauthorized().finish());
 	}
 	let mut builder = HttpResponse::Found();
-	let session = Session::new(data.clone(), token_set.unwrap());
+	let session = Session::new(data.clone(), name, token_set.unwrap());
 	session
 		.response(&req, &mut builder, SessionFlags::COOKIES)
 		.await?;
-	{
-		let mut location: String = String::from("/");
-		if query.state.is_some() {
-			let state = State::deserialize_state(&data.crypto, &query.state.as_ref().unwrap());
-			if state.is_ok() {
-				location = state.unwrap().url.unwrap_or(location);
-			}
-		}
-		if location.is_empty() {
-			location = String::from("/");
-		}
-		builder.header("location", location);
-	}
+	let location = data.redirects.get_location(state.and_then(|s| s.url));
+	builder.header("location", location);
 	Ok(builder.finish())
 }
 
+///
+/// Metadata of the SAML service provider, to register it in the IdP
+///
+async fn route_saml_metadata(
+	data: web::Data<Data>,
+	query: web::Query<ProviderQuery>,
+) -> Result<impl Responder, Error> {
+	let metadata = data
+		.providers
+		.select(query.provider.as_deref())
+		.and_then(|(_, provider)| provider.saml_metadata());
+	match metadata {
+		Some(metadata) => Ok(HttpResponse::Ok()
+			.content_type("application/samlmetadata+xml")
+			.body(metadata)),
+		None => Ok(HttpResponse::NotFound().finish()),
+	}
+}
+
 ///
 /// Validate the login
 ///
@@ -168,9 +304,19 @@ async fn route_refresh(data: web::Data<Data>, req: HttpRequest) -> Result<impl R
 ///
 /// Validate the login
 ///
-async fn route_validate(data: web::Data<Data>, req: HttpRequest) -> Result<impl Responder, Error> {
+async fn route_validate(
+	data: web::Data<Data>,
+	req: HttpRequest,
+	query: web::Query<AuthValidateQuery>,
+) -> Result<impl Responder, Error> {
 	let mut session = Session::from_request(data, &req);
 	session.validate(true).await?;
+	if let Some(ref roles) = query.roles {
+		session.require_roles(roles);
+	}
+	session
+		.exchange_token(&req, query.exchange.as_deref())
+		.await?;
 
 	let mut builder = HttpResponse::Ok();
 	session
@@ -180,15 +326,50 @@ async fn route_validate(data: web::Data<Data>, req: HttpRequest) -> Result<impl
 }
 
 ///
-/// Endpoint middleware for traefik
+/// Reject the forward auth requests from peers that are not trusted proxies
+///
+fn reject_untrusted(data: &Data, req: &HttpRequest) -> Option<HttpResponse> {
+	if data.trusted_proxies.is_trusted(req) {
+		return None;
+	}
+	log::warn!(
+		"Forward auth request from an untrusted peer {:?}",
+		req.peer_addr()
+	);
+	Some(HttpResponse::Forbidden().finish())
+}
+
+///
+/// Endpoint middleware for traefik and the other proxy profiles
 ///
 async fn route_forward_auth(
 	data: web::Data<Data>,
 	req: HttpRequest,
 	query: web::Query<AuthForwardAuthQuery>,
 ) -> Result<impl Responder, Error> {
+	if let Some(response) = reject_untrusted(&data, &req) {
+		return Ok(response);
+	}
+	let profile = if let Some(ref name) = query.profile {
+		match ProxyProfile::from_name(name) {
+			Some(profile) => Some(profile),
+			None => return Ok(HttpResponse::BadRequest().finish()),
+		}
+	} else {
+		None
+	};
+
 	let mut session = Session::from_request(data, &req);
+	if let Some(profile) = profile {
+		session.set_profile(profile);
+	}
 	session.validate(true).await?;
+	if let Some(ref roles) = query.roles {
+		session.require_roles(roles);
+	}
+	session
+		.exchange_token(&req, query.exchange.as_deref())
+		.await?;
 
 	let mut builder = HttpResponse::Ok();
 	let flags = if query.redirect.is_some() {
@@ -200,17 +381,141 @@ async fn route_forward_auth(
 	Ok(builder.finish())
 }
 
+///
+/// Endpoint for nginx auth_request
+///
+/// Only answers 2xx, 401 or 403. The login url is sent using the x-auth-login-url header
+///
+async fn route_auth_request(
+	data: web::Data<Data>,
+	req: HttpRequest,
+	query: web::Query<AuthValidateQuery>,
+) -> Result<impl Responder, Error> {
+	if let Some(response) = reject_untrusted(&data, &req) {
+		return Ok(response);
+	}
+	let mut session = Session::from_request(data, &req);
+	session.set_profile(ProxyProfile::Nginx);
+	session.validate(true).await?;
+	if let Some(ref roles) = query.roles {
+		session.require_roles(roles);
+	}
+	session
+		.exchange_token(&req, query.exchange.as_deref())
+		.await?;
+
+	let mut builder = HttpResponse::Ok();
+	session
+		.response(&req, &mut builder, SessionFlags::FORWARD_AUTH)
+		.await?;
+	Ok(builder.finish())
+}
+
+///
+/// Endpoint for the envoy ext_authz http service
+///
+/// Envoy appends the original path to the endpoint, so the redirect mode uses another prefix
+///
+async fn route_ext_authz(
+	data: web::Data<Data>,
+	req: HttpRequest,
+	redirect: bool,
+) -> Result<HttpResponse, Error> {
+	if let Some(response) = reject_untrusted(&data, &req) {
+		return Ok(response);
+	}
+	let query = get_ext_authz_query(&req);
+	let mut session = Session::from_request(data, &req);
+	session.set_profile(ProxyProfile::Envoy);
+	session.validate(true).await?;
+	if let Some(ref roles) = query.roles {
+		session.require_roles(roles);
+	}
+	session
+		.exchange_token(&req, query.exchange.as_deref())
+		.await?;
+
+	let mut builder = HttpResponse::Ok();
+	let flags = if redirect {
+		SessionFlags::FORWARD_AUTH | SessionFlags::FORWARD_AUTH_REDIRECT
+	} else {
+		SessionFlags::FORWARD_AUTH
+	};
+	session.response(&req, &mut builder, flags).await?;
+	Ok(builder.finish())
+}
+
+///
+/// Get the roles and the audience of the exchange for envoy
+///
+/// The query of the check request is the one of the original request, sent by the client, so
+/// they are read from the `x-auth-roles` and `x-auth-exchange` headers added by envoy instead
+///
+fn get_ext_authz_query(req: &HttpRequest) -> AuthValidateQuery {
+	let header = |name: &str| {
+		req.headers()
+			.get(name)
+			.and_then(|h| h.to_str().ok())
+			.map(|h| h.to_owned())
+	};
+	AuthValidateQuery {
+		roles: header("x-auth-roles"),
+		exchange: header("x-auth-exchange"),
+	}
+}
+
+///
+/// Built-in reverse proxy
+///
+/// Validates the session like the forward auth, and sends the request to the upstream
+///
+async fn route_proxy(
+	data: web::Data<Data>,
+	req: HttpRequest,
+	payload: web::Payload,
+) -> Result<HttpResponse, Error> {
+	let trusted = data.trusted_proxies.is_trusted(&req);
+	let upstream = match data.proxy.find(&req, trusted) {
+		Some(upstream) => upstream,
+		None => return Ok(HttpResponse::NotFound().finish()),
+	};
+	let mut session = Session::from_request(data.clone(), &req);
+	session.set_profile(ProxyProfile::Builtin);
+	session.validate(true).await?;
+	session.exchange_token(&req, None).await?;
+
+	let mut builder = HttpResponse::Ok();
+	let flags = if upstream.redirect {
+		SessionFlags::FORWARD_AUTH | SessionFlags::FORWARD_AUTH_REDIRECT
+	} else {
+		SessionFlags::FORWARD_AUTH
+	};
+	session.response(&req, &mut builder, flags).await?;
+	let auth_response = builder.finish();
+	if !auth_response.status().is_success() {
+		return Ok(auth_response);
+	}
+	data.proxy
+		.forward(&req, payload, upstream, auth_response.headers(), trusted)
+		.await
+}
+
 ///
 /// Json login
 ///
 async fn route_post_auth_login_json(
 	data: web::Data<Data>,
 	req: HttpRequest,
+	query: web::Query<AuthLoginQuery>,
 	form: web::Json<AuthLoginForm>,
 ) -> Result<impl Responder, Error> {
+	let (name, provider) = match data.providers.select(query.provider.as_deref()) {
+		Some(selected) => selected,
+		None => return Ok(HttpResponse::BadRequest().finish()),
+	};
+
 	// Perform the grant
-	let token_set = data
-		.provider
+	let token_set = provider
 		.grant_password(&form.username, &form.password)
 		.await?;
 	if token_set.is_none() {
@@ -219,7 +524,7 @@ async fn route_post_auth_login_json(
 
 	// Create the response and post t
 	let mut builder = HttpResponse::Ok();
-	let mut session = Session::new(data.clone(), token_set.unwrap());
+	let mut session = Session::new(data.clone(), name, token_set.unwrap());
 	session.validate(false).await?;
 	session
 		.response(&req, &mut builder, SessionFlags::COOKIES)
@@ -227,20 +532,103 @@ async fn route_post_auth_login_json(
 	session.response_json(&mut builder, Some(true))
 }
 
+///
+/// Start a device authorization at the provider, for the clients without a browser (Ex: CLIs)
+///
+/// The user opens the verification uri and types the user code, while the client polls
+/// `/auth/device/token` with the device code
+///
+async fn route_post_device(
+	data: web::Data<Data>,
+	query: web::Query<ProviderQuery>,
+) -> Result<impl Responder, Error> {
+	let (name, provider) = match data.providers.select(query.provider.as_deref()) {
+		Some(selected) => selected,
+		None => return Ok(HttpResponse::BadRequest().finish()),
+	};
+	let mut authorization = match provider.device_authorization().await? {
+		Some(authorization) => authorization,
+		None => return Ok(HttpResponse::BadRequest().finish()),
+	};
+	// The device code is polled using the same provider
+	authorization.device_code =
+		DeviceCode::serialize_code(&data.crypto, authorization.device_code, name)?;
+	Ok(HttpResponse::Ok().json(authorization))
+}
+
+///
+/// Poll the device code. Once the user approves the device, returns the authorization like
+/// `/auth/login/json`. Until then, returns 400 with the error of the provider (RFC 8628)
+///
+async fn route_post_device_token(
+	data: web::Data<Data>,
+	req: HttpRequest,
+	form: web::Form<DeviceTokenForm>,
+) -> Result<impl Responder, Error> {
+	let device_error = |error: &str| {
+		HttpResponse::BadRequest().json(DeviceErrorResponse {
+			error: error.to_owned(),
+		})
+	};
+	let device_code = match DeviceCode::deserialize_code(&data.crypto, &form.device_code) {
+		Ok(device_code) => device_code,
+		Err(_) => return Ok(device_error("invalid_grant")),
+	};
+	let (name, provider) = match data.providers.select(device_code.provider.as_deref()) {
+		Some(selected) => selected,
+		None => return Ok(device_error("invalid_grant")),
+	};
+	let token_set = match provider.grant_device_code(&device_code.code).await? {
+		DeviceGrant::Granted(token_set) => token_set,
+		DeviceGrant::Error(error) => return Ok(device_error(&error)),
+	};
+
+	// The client sends the authorization, so there are no cookies
+	let mut builder = HttpResponse::Ok();
+	let mut session = Session::new(data.clone(), name, token_set);
+	session.validate(false).await?;
+	session
+		.response(&req, &mut builder, SessionFlags::NONE)
+		.await?;
+	session.response_json(&mut builder, Some(true))
+}
+
+///
+/// Public keys to verify the x-auth-userinfo header
+///
+async fn route_jwks(data: web::Data<Data>) -> impl Responder {
+	HttpResponse::Ok().json(data.jwt.jwks())
+}
+
 ///
 /// Helper struct to create the routes and setup the service
 ///
 pub struct Handler {
 	random: crypto::RandomPtr,
+	stats: StatsPtr,
+	replay: ReplayCachePtr,
+	basic_auth: BasicAuthStatePtr,
+	jwt_keys: Option<JwtKeysPtr>,
 	settings: Settings,
 }
 impl Handler {
 	///
 	/// Create a new handler
 	///
-	pub fn new(random: crypto::RandomPtr, settings: Settings) -> Result<Handler, Error> {
+	pub fn new(
+		random: crypto::RandomPtr,
+		stats: StatsPtr,
+		replay: ReplayCachePtr,
+		basic_auth: BasicAuthStatePtr,
+		jwt_keys: Option<JwtKeysPtr>,
+		settings: Settings,
+	) -> Result<Handler, Error> {
 		Ok(Handler {
 			random: random,
+			stats,
+			replay,
+			basic_auth,
+			jwt_keys,
 			settings: settings,
 		})
 	}
@@ -248,21 +636,80 @@ impl Handler {
 	///
 	/// Configure the service
 	///
+	/// The virtual hosts use their own data, and the other hosts the one of the settings
+	///
 	pub fn config(&self, service_config: &mut web::ServiceConfig) -> Result<(), Error> {
-		let data = Data::new(self.settings.clone(), self.random.clone())?;
+		for vhost in VirtualHost::from_settings(&self.settings)? {
+			let data = Data::new(
+				vhost.settings.clone(),
+				self.random.clone(),
+				self.stats.clone(),
+				self.replay.clone(),
+				self.basic_auth.clone(),
+				self.jwt_keys.clone(),
+			)?;
+			let proxy_enabled = data.proxy.is_enabled();
+			service_config.service(
+				web::scope("")
+					.guard(guard::fn_guard(move |head| vhost.matches(head)))
+					.data(data)
+					.configure(|cfg| Self::routes(cfg, proxy_enabled)),
+			);
+		}
+		let data = Data::new(
+			self.settings.clone(),
+			self.random.clone(),
+			self.stats.clone(),
+			self.replay.clone(),
+			self.basic_auth.clone(),
+			self.jwt_keys.clone(),
+		)?;
+		let proxy_enabled = data.proxy.is_enabled();
+		service_config.data(data);
+		Self::routes(service_config, proxy_enabled);
+		Ok(())
+	}
+
+	///
+	/// Add the routes
+	///
+	fn routes(service_config: &mut web::ServiceConfig, proxy_enabled: bool) {
 		service_config
-			.data(data)
 			.route("/login", web::get().to(route_login))
 			.route("/login", web::post().to(route_post_login))
 			.route("/logout", web::get().to(route_logout))
-			.route("/auth/callback", web::get().to(route_callback))
+			.service(
+				web::resource("/auth/callback")
+					// The SAML responses may be larger than the default limit of the forms
+					.app_data(web::FormConfig::default().limit(SAML_RESPONSE_LIMIT))
+					.route(web::get().to(route_callback))
+					.route(web::post().to(route_post_callback)),
+			)
+			.route("/auth/saml/metadata", web::get().to(route_saml_metadata))
 			.route("/auth/refresh", web::get().to(route_refresh))
 			.route("/auth/validate", web::get().to(route_validate))
 			.route("/auth/forward-auth", web::get().to(route_forward_auth))
+			.route("/auth/nginx", web::route().to(route_auth_request))
+			.route(
+				"/auth/envoy/{path:.*}",
+				web::route().to(|data, req| route_ext_authz(data, req, false)),
+			)
+			.route(
+				"/auth/envoy-redirect/{path:.*}",
+				web::route().to(|data, req| route_ext_authz(data, req, true)),
+			)
 			.route(
 				"/auth/login/json",
 				web::post().to(route_post_auth_login_json),
-			);
-		Ok(())
+			)
+			.route("/auth/device", web::post().to(route_post_device))
+			.route(
+				"/auth/device/token",
+				web::post().to(route_post_device_token),
+			)
+			.route("/.well-known/jwks.json", web::get().to(route_jwks));
+		if proxy_enabled {
+			service_config.route("/{path:.*}", web::route().to(route_proxy));
+		}
 	}
 }
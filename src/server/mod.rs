@@ -1,3 +1,16 @@
+pub mod api_key;
+pub mod basic_auth;
+pub mod chooser;
+pub mod claims;
+pub mod client_cert;
 pub mod data;
+pub mod exchange;
 pub mod handler;
+pub mod profile;
+pub mod proxy;
+pub mod redirect;
 pub mod state;
+pub mod stats;
+pub mod tls;
+pub mod trust;
+pub mod vhost;
@@ -0,0 +1,254 @@
+use super::trust::get_scheme_host;
+use actix_web::HttpRequest;
+use url::Url;
+
+///
+/// The proxy in front of the gatekeeper calling the forward auth endpoints
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProxyProfile {
+	Traefik,
+	Caddy,
+	Nginx,
+	Haproxy,
+	Envoy,
+	Generic,
+	Builtin,
+}
+
+///
+/// The original request, as received by the proxy
+///
+pub struct OriginalRequest {
+	pub method: String,
+	pub origin: Option<String>,
+	pub uri: String,
+}
+
+impl ProxyProfile {
+	///
+	/// Get the profile by its name
+	///
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"traefik" => Some(ProxyProfile::Traefik),
+			"caddy" => Some(ProxyProfile::Caddy),
+			"nginx" => Some(ProxyProfile::Nginx),
+			"haproxy" | "haproxy-spoe-lite" => Some(ProxyProfile::Haproxy),
+			"envoy" => Some(ProxyProfile::Envoy),
+			"generic" => Some(ProxyProfile::Generic),
+			_ => None,
+		}
+	}
+	///
+	/// If the proxy sends the denied responses directly to the client
+	///
+	/// Only then the redirects and the Set-Cookie headers reach the user. Otherwise the
+	/// proxy only expects 2xx, 401 or 403 and the x-auth-* headers are used instead.
+	///
+	pub fn is_response_forwarded(self) -> bool {
+		matches!(
+			self,
+			ProxyProfile::Traefik
+				| ProxyProfile::Caddy
+				| ProxyProfile::Envoy
+				| ProxyProfile::Builtin
+		)
+	}
+	///
+	/// If the renewed cookies must use Set-Cookie when the request is allowed
+	///
+	pub fn is_success_set_cookie(self) -> bool {
+		matches!(self, ProxyProfile::Envoy | ProxyProfile::Builtin)
+	}
+	///
+	/// If the login url only sends the GET and HEAD requests back to the original uri
+	///
+	/// The proxies sending the redirects keep the earlier forward auth behaviour, which always
+	/// sends the original uri
+	///
+	pub fn is_login_get_only(self) -> bool {
+		!self.is_response_forwarded()
+	}
+	///
+	/// Get the original request using the headers sent by the proxy
+	///
+	/// The headers of an untrusted peer are ignored, and its request is used as it is
+	///
+	pub fn original_request(self, req: &HttpRequest, trusted: bool) -> OriginalRequest {
+		let header = |name: &str| req.headers().get(name).and_then(|h| h.to_str().ok());
+		let profile = if trusted { self } else { ProxyProfile::Builtin };
+		match profile {
+			ProxyProfile::Traefik | ProxyProfile::Caddy => OriginalRequest::from_parts(
+				header("x-forwarded-method"),
+				header("x-forwarded-proto"),
+				header("x-forwarded-host"),
+				header("x-forwarded-uri"),
+			),
+			ProxyProfile::Nginx | ProxyProfile::Haproxy => header("x-original-url")
+				.and_then(|url| OriginalRequest::from_url(header("x-original-method"), url))
+				.unwrap_or_else(|| {
+					OriginalRequest::from_parts(
+						header("x-original-method"),
+						header("x-forwarded-proto"),
+						header("x-forwarded-host").or_else(|| header("host")),
+						header("x-original-uri"),
+					)
+				}),
+			// Envoy appends the original path to the endpoint, and the redirect is
+			// sent to the same host so it can be relative
+			ProxyProfile::Envoy => {
+				let mut uri = format!("/{}", req.match_info().query("path"));
+				if !req.query_string().is_empty() {
+					uri.push('?');
+					uri.push_str(req.query_string());
+				}
+				OriginalRequest {
+					method: req.method().to_string(),
+					origin: None,
+					uri,
+				}
+			}
+			// The built-in proxy receives the original request
+			ProxyProfile::Builtin => {
+				let (scheme, host) = get_scheme_host(req, trusted);
+				let uri = req
+					.uri()
+					.path_and_query()
+					.map(|p| p.as_str())
+					.unwrap_or("/");
+				OriginalRequest::from_parts(
+					Some(req.method().as_str()),
+					Some(&scheme),
+					Some(&host),
+					Some(uri),
+				)
+			}
+			ProxyProfile::Generic => {
+				let method = header("x-original-method").or_else(|| header("x-forwarded-method"));
+				header("x-original-url")
+					.and_then(|url| OriginalRequest::from_url(method, url))
+					.unwrap_or_else(|| {
+						OriginalRequest::from_parts(
+							method,
+							header("x-forwarded-proto"),
+							header("x-forwarded-host").or_else(|| header("host")),
+							header("x-forwarded-uri").or_else(|| header("x-original-uri")),
+						)
+					})
+			}
+		}
+	}
+}
+
+impl OriginalRequest {
+	fn from_parts(
+		method: Option<&str>,
+		proto: Option<&str>,
+		host: Option<&str>,
+		uri: Option<&str>,
+	) -> Self {
+		Self {
+			method: method.unwrap_or("GET").to_owned(),
+			origin: host.map(|host| format!("{}://{}", proto.unwrap_or("http"), host)),
+			uri: uri.unwrap_or("/").to_owned(),
+		}
+	}
+
+	fn from_url(method: Option<&str>, url: &str) -> Option<Self> {
+		let url = Url::parse(url).ok()?;
+		let mut uri = url.path().to_owned();
+		if let Some(query) = url.query() {
+			uri.push('?');
+			uri.push_str(query);
+		}
+		Some(Self {
+			method: method.unwrap_or("GET").to_owned(),
+			origin: Some(url.origin().ascii_serialization()),
+			uri,
+		})
+	}
+	///
+	/// Get the login url, with the original uri url encoded in the `url` query
+	///
+	/// When `get_only`, only the GET and HEAD requests are sent back to the original uri
+	///
+	pub fn login_url(&self, get_only: bool) -> String {
+		let origin = self.origin.as_deref().unwrap_or("");
+		if get_only
+			&& !self.method.eq_ignore_ascii_case("GET")
+			&& !self.method.eq_ignore_ascii_case("HEAD")
+		{
+			return format!("{}/login", origin);
+		}
+		let query = url::form_urlencoded::Serializer::new(String::new())
+			.append_pair("url", &self.uri)
+			.finish();
+		format!("{}/login?{}", origin, query)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use actix_web::test::TestRequest;
+	#[test]
+	fn test_login_url() {
+		assert!(!ProxyProfile::Traefik.is_login_get_only());
+		assert!(!ProxyProfile::Envoy.is_login_get_only());
+		assert!(ProxyProfile::Nginx.is_login_get_only());
+		let req = TestRequest::default()
+			.header("x-forwarded-proto", "https")
+			.header("x-forwarded-host", "example.com")
+			.header("x-forwarded-uri", "/app/page?id=1&tab=2")
+			.header("x-forwarded-method", "POST")
+			.to_http_request();
+		assert_eq!(
+			"https://example.com/login?url=%2Fapp%2Fpage%3Fid%3D1%26tab%3D2",
+			ProxyProfile::Traefik
+				.original_request(&req, true)
+				.login_url(false)
+		);
+
+		let req = TestRequest::default()
+			.header("x-original-url", "https://example.com/app/page?id=1")
+			.to_http_request();
+		assert_eq!(
+			"https://example.com/login?url=%2Fapp%2Fpage%3Fid%3D1",
+			ProxyProfile::Nginx
+				.original_request(&req, true)
+				.login_url(true)
+		);
+
+		let req = TestRequest::default()
+			.header("host", "example.com")
+			.header("x-original-uri", "/app/page")
+			.header("x-original-method", "POST")
+			.to_http_request();
+		assert_eq!(
+			"http://example.com/login",
+			ProxyProfile::Haproxy
+				.original_request(&req, true)
+				.login_url(true)
+		);
+		assert_eq!(
+			"http://example.com/login?url=%2Fapp%2Fpage",
+			ProxyProfile::Haproxy
+				.original_request(&req, true)
+				.login_url(false)
+		);
+
+		let req = TestRequest::default()
+			.header("host", "gatekeeper:8088")
+			.header("x-forwarded-host", "example.com")
+			.header("x-forwarded-uri", "/app")
+			.uri("/auth/forward-auth")
+			.to_http_request();
+		assert_eq!(
+			"http://gatekeeper:8088/login?url=%2Fauth%2Fforward-auth",
+			ProxyProfile::Traefik
+				.original_request(&req, false)
+				.login_url(false)
+		);
+	}
+}
@@ -0,0 +1,500 @@
+use super::trust::{get_scheme_host, host_without_port};
+use crate::error::Error;
+use crate::settings::Settings;
+use actix_http::body::{Body, SizedStream};
+use actix_web::{
+	client::{Client, ClientBuilder},
+	http::{header, HeaderMap, HeaderName, Method},
+	web::{Bytes, Payload},
+	HttpRequest, HttpResponse,
+};
+use futures_util::stream::{self, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use url::Url;
+
+/// Headers that only make sense for a single connection
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+	"connection",
+	"expect",
+	"keep-alive",
+	"proxy-authenticate",
+	"proxy-authorization",
+	"te",
+	"trailer",
+	"transfer-encoding",
+	"upgrade",
+];
+
+///
+/// An upstream to proxy the requests to
+///
+pub struct Upstream {
+	host: Option<String>,
+	prefix: String,
+	url: Url,
+	pub redirect: bool,
+}
+
+///
+/// Built-in reverse proxy
+///
+pub struct Proxy {
+	client: Client,
+	upstreams: Vec<Upstream>,
+	relay_token: Option<HeaderName>,
+	client_cert_header: Option<HeaderName>,
+	api_key_header: Option<HeaderName>,
+	session_cookies: Vec<String>,
+}
+
+impl Upstream {
+	///
+	/// Parse an upstream in the format `[HOST]/PREFIX=URL[;redirect]`
+	///
+	fn parse(value: &str) -> Result<Self, Error> {
+		let mut options = value.split(';');
+		let route = options.next().unwrap_or("");
+		let mut route_split = route.splitn(2, '=');
+		let location = route_split.next().unwrap_or("").trim();
+		let url = route_split
+			.next()
+			.ok_or(Error::SettingsError("Invalid upstream"))?;
+		let (host, prefix) = match location.find('/') {
+			Some(index) => (&location[..index], &location[index..]),
+			None => return Err(Error::SettingsError("Invalid upstream")),
+		};
+
+		let mut redirect = false;
+		for option in options {
+			match option.trim() {
+				"redirect" => redirect = true,
+				_ => return Err(Error::SettingsError("Invalid upstream option")),
+			}
+		}
+		Ok(Self {
+			host: if host.is_empty() {
+				None
+			} else {
+				Some(host.to_lowercase())
+			},
+			prefix: prefix.to_owned(),
+			url: Url::parse(url.trim())?,
+			redirect,
+		})
+	}
+	///
+	/// Check if the request must go to this upstream
+	///
+	fn matches(&self, host: &str, path: &str) -> bool {
+		if let Some(ref upstream_host) = self.host {
+			if host_without_port(host).as_ref() != Some(upstream_host) {
+				return false;
+			}
+		}
+		is_path_prefix(&self.prefix, path)
+	}
+	///
+	/// Get the url of the upstream for the request
+	///
+	fn get_url(&self, req: &HttpRequest) -> String {
+		let mut url = self.url.clone();
+		let path = format!("{}{}", url.path().trim_end_matches('/'), req.path());
+		url.set_path(&path);
+		url.set_query(Some(req.query_string()).filter(|q| !q.is_empty()));
+		url.to_string()
+	}
+}
+
+impl Proxy {
+	///
+	/// Create the proxy using the upstreams in the settings
+	///
+	pub fn new(settings: &Settings) -> Result<Self, Error> {
+		let mut upstreams = Vec::new();
+		if let Some(ref value) = settings.proxy.upstreams {
+			for upstream in value.split(',').filter(|u| !u.trim().is_empty()) {
+				upstreams.push(Upstream::parse(upstream)?);
+			}
+		}
+		// Exchanged tokens are relayed using the authorization header by default
+		let relay_token = settings
+			.relay_token
+			.as_deref()
+			.or_else(|| settings.token_exchange.as_ref().map(|_| "authorization"));
+		let relay_token = match relay_token {
+			Some(header) => Some(
+				HeaderName::from_bytes(header.to_lowercase().as_bytes())
+					.map_err(|_| Error::SettingsError("Invalid relay token header"))?,
+			),
+			None => None,
+		};
+		let client_cert_header = settings
+			.client_cert
+			.header
+			.as_ref()
+			.and_then(|h| HeaderName::from_bytes(h.to_lowercase().as_bytes()).ok());
+		let api_key_header = settings
+			.api_keys
+			.header
+			.as_ref()
+			.and_then(|h| HeaderName::from_bytes(h.to_lowercase().as_bytes()).ok());
+		Ok(Self {
+			client: ClientBuilder::new().disable_timeout().finish(),
+			upstreams,
+			relay_token,
+			client_cert_header,
+			api_key_header,
+			session_cookies: vec![
+				settings.cookie.access_token_name.clone(),
+				settings.cookie.refresh_token_name.clone(),
+			],
+		})
+	}
+	///
+	/// If there is any upstream to proxy to
+	///
+	pub fn is_enabled(&self) -> bool {
+		!self.upstreams.is_empty()
+	}
+	///
+	/// Find the upstream for the request. Upstreams with host are preferred, then the longest prefix
+	///
+	pub fn find(&self, req: &HttpRequest, trusted: bool) -> Option<&Upstream> {
+		let (_, host) = get_scheme_host(req, trusted);
+		self.upstreams
+			.iter()
+			.filter(|u| u.matches(&host, req.path()))
+			.max_by_key(|u| (u.host.is_some(), u.prefix.len()))
+	}
+	///
+	/// Forward the request to the upstream
+	///
+	/// The `identity` headers (x-auth-*) replace any sent by the client, and the set-cookie
+	/// headers are added to the response. The forwarded headers of the client are only kept
+	/// when it is `trusted`.
+	///
+	pub async fn forward(
+		&self,
+		req: &HttpRequest,
+		payload: Payload,
+		upstream: &Upstream,
+		identity: &HeaderMap,
+		trusted: bool,
+	) -> Result<HttpResponse, Error> {
+		let url = upstream.get_url(req);
+		let is_websocket = req
+			.headers()
+			.get(header::UPGRADE)
+			.and_then(|h| h.to_str().ok())
+			.map(|h| h.eq_ignore_ascii_case("websocket"))
+			.unwrap_or(false);
+		let headers = self.get_upstream_headers(req, identity, trusted);
+
+		let mut response = if is_websocket {
+			self.forward_websocket(req, payload, &url, headers).await?
+		} else {
+			self.forward_http(req, payload, &url, headers).await?
+		};
+		for cookie in identity.get_all(header::SET_COOKIE) {
+			response
+				.headers_mut()
+				.append(header::SET_COOKIE, cookie.clone());
+		}
+		Ok(response)
+	}
+	///
+	/// Get the headers to send to the upstream
+	///
+	/// The credentials used by the gatekeeper (the authorization, the api key and the session
+	/// cookies) are not sent. The access token is only sent using the relay token header.
+	///
+	fn get_upstream_headers(
+		&self,
+		req: &HttpRequest,
+		identity: &HeaderMap,
+		trusted: bool,
+	) -> HeaderMap {
+		let mut headers = HeaderMap::new();
+		for (name, value) in req.headers() {
+			let name_str = name.as_str();
+			if HOP_BY_HOP_HEADERS.contains(&name_str)
+				|| name_str.starts_with("x-auth-")
+				|| name == header::AUTHORIZATION
+				|| Some(name) == self.relay_token.as_ref()
+				|| Some(name) == self.api_key_header.as_ref()
+				|| (!trusted
+					&& (name_str.starts_with("x-forwarded-")
+						|| name == header::FORWARDED
+						|| Some(name) == self.client_cert_header.as_ref()))
+			{
+				continue;
+			}
+			if name == header::COOKIE {
+				let cookies = value
+					.to_str()
+					.ok()
+					.and_then(|v| strip_cookies(v, &self.session_cookies));
+				if let Some(cookies) = cookies.and_then(|c| c.parse().ok()) {
+					headers.append(header::COOKIE, cookies);
+				}
+				continue;
+			}
+			headers.append(name.clone(), value.clone());
+		}
+		for (name, value) in identity {
+			if name.as_str().starts_with("x-auth-")
+				&& !name.as_str().starts_with("x-auth-set-cookie")
+			{
+				headers.append(name.clone(), value.clone());
+			}
+		}
+		if let Some(ref relay_token) = self.relay_token {
+			if let Some(value) = identity.get(relay_token) {
+				headers.insert(relay_token.clone(), value.clone());
+			}
+		}
+
+		// The peer is appended to the addresses sent by a trusted proxy
+		let (scheme, host) = get_scheme_host(req, trusted);
+		let mut forwarded_for = req
+			.peer_addr()
+			.map(|addr| addr.ip().to_string())
+			.unwrap_or_default();
+		if let Some(previous) = headers.get("x-forwarded-for").and_then(|h| h.to_str().ok()) {
+			forwarded_for = format!("{}, {}", previous, forwarded_for);
+		}
+		let forwarded = [
+			("x-forwarded-proto", scheme),
+			("x-forwarded-host", host),
+			("x-forwarded-for", forwarded_for),
+		];
+		for (name, value) in forwarded.iter() {
+			if let Ok(value) = value.parse() {
+				headers.insert(HeaderName::from_static(name), value);
+			}
+		}
+		headers
+	}
+	///
+	/// Forward a plain http request, streaming the bodies
+	///
+	async fn forward_http(
+		&self,
+		req: &HttpRequest,
+		payload: Payload,
+		url: &str,
+		headers: HeaderMap,
+	) -> Result<HttpResponse, Error> {
+		let mut request = self
+			.client
+			.request(req.method().clone(), url)
+			.no_decompress();
+		for (name, value) in headers.iter() {
+			if name != header::CONTENT_LENGTH {
+				request = request.header(name.clone(), value.clone());
+			}
+		}
+		let result = if let Some(size) = Self::get_content_length(req.headers()) {
+			let body = SizedStream::new(size, payload.map(|chunk| chunk.map_err(Into::into)));
+			request.send_body(Body::from_message(body)).await
+		} else if req.headers().contains_key(header::TRANSFER_ENCODING) {
+			request.send_stream(payload).await
+		} else {
+			request.send().await
+		};
+		let upstream_response = match result {
+			Ok(response) => response,
+			Err(error) => {
+				log::error!("Error proxying to {}: {}", url, error);
+				return Ok(HttpResponse::BadGateway().finish());
+			}
+		};
+
+		let mut builder = HttpResponse::build(upstream_response.status());
+		for (name, value) in upstream_response.headers() {
+			if HOP_BY_HOP_HEADERS.contains(&name.as_str()) || name == header::CONTENT_LENGTH {
+				continue;
+			}
+			builder.header(name.clone(), value.clone());
+		}
+		let size = Self::get_content_length(upstream_response.headers());
+		match size {
+			Some(size) if req.method() != Method::HEAD => {
+				let stream = upstream_response.map(|chunk| chunk.map_err(Into::into));
+				Ok(builder.body(Body::from_message(SizedStream::new(size, stream))))
+			}
+			_ => Ok(builder.streaming(upstream_response)),
+		}
+	}
+	///
+	/// Get the content-length header
+	///
+	fn get_content_length(headers: &HeaderMap) -> Option<u64> {
+		headers
+			.get(header::CONTENT_LENGTH)
+			.and_then(|h| h.to_str().ok())
+			.and_then(|h| h.parse().ok())
+	}
+	///
+	/// Forward a websocket connection
+	///
+	/// The handshake is made with the upstream, and then the raw bytes are relayed both ways
+	///
+	async fn forward_websocket(
+		&self,
+		req: &HttpRequest,
+		mut payload: Payload,
+		url: &str,
+		headers: HeaderMap,
+	) -> Result<HttpResponse, Error> {
+		let key = match req.headers().get(header::SEC_WEBSOCKET_KEY) {
+			Some(key) => key.as_bytes().to_vec(),
+			None => return Ok(HttpResponse::BadRequest().finish()),
+		};
+		let mut request = self.client.ws(url);
+		for (name, value) in headers.iter() {
+			if name == header::SEC_WEBSOCKET_KEY || name == header::SEC_WEBSOCKET_VERSION {
+				continue;
+			}
+			request = request.header(name.clone(), value.clone());
+		}
+		let (upstream_response, framed) = match request.connect().await {
+			Ok(result) => result,
+			Err(error) => {
+				log::error!("Error proxying websocket to {}: {}", url, error);
+				return Ok(HttpResponse::BadGateway().finish());
+			}
+		};
+
+		let mut builder = HttpResponse::SwitchingProtocols();
+		builder
+			.header(header::UPGRADE, "websocket")
+			.header(header::CONNECTION, "upgrade")
+			.header(header::SEC_WEBSOCKET_ACCEPT, actix_http::ws::hash_key(&key));
+		for (name, value) in upstream_response.headers() {
+			if name == header::SEC_WEBSOCKET_PROTOCOL || name == header::SEC_WEBSOCKET_EXTENSIONS {
+				builder.header(name.clone(), value.clone());
+			}
+		}
+
+		let parts = framed.into_parts();
+		let (reader, mut writer) = tokio::io::split(parts.io);
+		actix_rt::spawn(async move {
+			while let Some(Ok(chunk)) = payload.next().await {
+				if writer.write_all(&chunk).await.is_err() {
+					break;
+				}
+			}
+			let _ = writer.shutdown().await;
+		});
+
+		let pending = Some(parts.read_buf.freeze()).filter(|b| !b.is_empty());
+		let upstream_stream =
+			stream::unfold((reader, pending), |(mut reader, pending)| async move {
+				if let Some(pending) = pending {
+					return Some((Ok(pending), (reader, None)));
+				}
+				let mut buffer = vec![0u8; 8192];
+				match reader.read(&mut buffer).await {
+					Ok(0) => None,
+					Ok(size) => {
+						buffer.truncate(size);
+						Some((Ok(Bytes::from(buffer)), (reader, None)))
+					}
+					Err(error) => Some((Err::<Bytes, _>(error), (reader, None))),
+				}
+			});
+		Ok(builder.streaming(Box::pin(upstream_stream)))
+	}
+}
+
+///
+/// Remove the cookies of the session from a cookie header. None when no cookie is left
+///
+fn strip_cookies(value: &str, names: &[String]) -> Option<String> {
+	let cookies: Vec<&str> = value
+		.split(';')
+		.map(|c| c.trim())
+		.filter(|c| {
+			let name = c.split('=').next().unwrap_or("").trim();
+			!c.is_empty() && !names.iter().any(|n| n == name)
+		})
+		.collect();
+	if cookies.is_empty() {
+		None
+	} else {
+		Some(cookies.join("; "))
+	}
+}
+
+///
+/// Check if the prefix matches whole segments of the path, so `/api` does not match `/apiary`
+///
+pub fn is_path_prefix(prefix: &str, path: &str) -> bool {
+	match path.strip_prefix(prefix) {
+		Some(rest) => rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/'),
+		None => false,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use actix_web::test::TestRequest;
+	#[test]
+	fn test_upstream() {
+		let upstream = Upstream::parse("example.com/api=http://backend:3000/v1;redirect").unwrap();
+		assert!(upstream.redirect);
+		assert!(upstream.matches("example.com:8080", "/api/users"));
+		assert!(!upstream.matches("other.com", "/api/users"));
+		assert!(!upstream.matches("example.com", "/app"));
+		assert!(upstream.matches("example.com", "/api"));
+		assert!(!upstream.matches("example.com", "/apiary"));
+		let ipv6 = Upstream::parse("[::1]/api=http://backend").unwrap();
+		assert!(ipv6.matches("[::1]:8080", "/api"));
+		assert!(ipv6.matches("[::1]", "/api"));
+		assert!(!ipv6.matches("[::2]:8080", "/api"));
+		assert!(Upstream::parse("/=http://backend")
+			.unwrap()
+			.matches("", "/app"));
+		assert!(Upstream::parse("/api/=http://backend")
+			.unwrap()
+			.matches("", "/api/users"));
+
+		let req = TestRequest::with_uri("/api/users?id=1").to_http_request();
+		assert_eq!(
+			"http://backend:3000/v1/api/users?id=1",
+			upstream.get_url(&req)
+		);
+
+		assert!(Upstream::parse("http://backend").is_err());
+		assert!(Upstream::parse("/=http://backend;other").is_err());
+	}
+	#[actix_rt::test]
+	async fn test_upstream_headers() {
+		let proxy = Proxy {
+			client: Client::default(),
+			upstreams: Vec::new(),
+			relay_token: None,
+			client_cert_header: None,
+			api_key_header: Some(HeaderName::from_static("x-api-key")),
+			session_cookies: vec![String::from("sat"), String::from("srt")],
+		};
+		let req = TestRequest::default()
+			.header("authorization", "Basic dXNlcjpwYXNz")
+			.header("x-api-key", "agk_id_secret")
+			.header("cookie", "sat=access; theme=dark; srt=refresh")
+			.header("accept", "text/html")
+			.to_http_request();
+		let headers = proxy.get_upstream_headers(&req, &HeaderMap::new(), false);
+		assert!(headers.get("authorization").is_none());
+		assert!(headers.get("x-api-key").is_none());
+		assert_eq!("theme=dark", headers.get("cookie").unwrap());
+		assert_eq!("text/html", headers.get("accept").unwrap());
+
+		assert_eq!(
+			None,
+			strip_cookies("sat=access; srt=refresh", &proxy.session_cookies)
+		);
+	}
+}
@@ -0,0 +1,106 @@
+use crate::error::Error;
+use crate::settings::Settings;
+use url::Url;
+
+///
+/// The urls allowed as redirects after the login
+///
+/// Without the setting, every url is allowed. Otherwise, only the paths of the same host and the
+/// urls inside the allowed ones.
+///
+pub struct AllowedRedirects {
+	urls: Option<Vec<Url>>,
+}
+
+impl AllowedRedirects {
+	///
+	/// Create using the comma delimited list of urls in the settings
+	///
+	pub fn new(settings: &Settings) -> Result<Self, Error> {
+		let urls = match settings.allowed_redirects {
+			Some(ref value) => Some(
+				value
+					.split(',')
+					.filter(|u| !u.trim().is_empty())
+					.map(|u| Url::parse(u.trim()))
+					.collect::<Result<Vec<_>, _>>()?,
+			),
+			None => None,
+		};
+		Ok(Self { urls })
+	}
+	///
+	/// Check if the location is allowed
+	///
+	pub fn is_allowed(&self, location: &str) -> bool {
+		let urls = match self.urls {
+			Some(ref urls) => urls,
+			None => return true,
+		};
+		// The browsers remove the tabs and the newlines of the urls, so `/\t/example.com` would
+		// become `//example.com`
+		if location
+			.chars()
+			.any(|c| c.is_control() || c.is_whitespace())
+		{
+			return false;
+		}
+		// Paths of the same host, but not the urls without a scheme like `//example.com`
+		if let Some(path) = location.strip_prefix('/') {
+			return !path.starts_with('/') && !path.starts_with('\\');
+		}
+		let location = match Url::parse(location) {
+			Ok(location) => location,
+			Err(_) => return false,
+		};
+		urls.iter().any(|url| {
+			let path = url.path().trim_end_matches('/');
+			url.origin() == location.origin()
+				&& (location.path() == path || location.path().starts_with(&format!("{}/", path)))
+		})
+	}
+	///
+	/// Get the location if allowed, or the root of the host
+	///
+	pub fn get_location(&self, location: Option<String>) -> String {
+		match location {
+			Some(location) if !location.is_empty() && self.is_allowed(&location) => location,
+			_ => String::from("/"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[test]
+	fn test_allowed() {
+		let redirects = AllowedRedirects {
+			urls: Some(vec![
+				Url::parse("https://app.example.com").unwrap(),
+				Url::parse("https://example.com/app/").unwrap(),
+			]),
+		};
+		assert!(redirects.is_allowed("/page?id=1"));
+		assert!(!redirects.is_allowed("//evil.com/page"));
+		assert!(!redirects.is_allowed("/\\evil.com/page"));
+		assert!(!redirects.is_allowed("/\t/evil.com/page"));
+		assert!(!redirects.is_allowed("/\n/evil.com/page"));
+		assert!(!redirects.is_allowed("https://app.example.com/\tpage"));
+		assert!(redirects.is_allowed("https://app.example.com/page"));
+		assert!(!redirects.is_allowed("http://app.example.com/page"));
+		assert!(!redirects.is_allowed("https://app.example.com.evil.com/"));
+		assert!(redirects.is_allowed("https://example.com/app"));
+		assert!(redirects.is_allowed("https://example.com/app/page"));
+		assert!(!redirects.is_allowed("https://example.com/application"));
+		assert!(!redirects.is_allowed("javascript:alert(1)"));
+		assert_eq!(
+			"/",
+			redirects.get_location(Some(String::from("https://evil.com")))
+		);
+		assert_eq!("/", redirects.get_location(None));
+
+		let redirects = AllowedRedirects { urls: None };
+		assert!(redirects.is_allowed("https://other.com/"));
+	}
+}
@@ -5,11 +5,18 @@ use serde::{Deserialize, Serialize};
 #[derive(Serialize, Deserialize)]
 pub struct State {
 	pub url: Option<String>,
+	/// Name of the provider of the login, when there are named providers
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub provider: Option<String>,
 }
 
 impl State {
-	pub fn serialize_state(crypto: &Crypto, url: Option<String>) -> Result<String, Error> {
-		let state = Self { url: url };
+	pub fn serialize_state(
+		crypto: &Crypto,
+		url: Option<String>,
+		provider: Option<String>,
+	) -> Result<String, Error> {
+		let state = Self { url, provider };
 		let request_state_string = serde_json::to_string(&state)?;
 		let request_state_string_encrypted = crypto.encrypt(&request_state_string)?;
 		Ok(request_state_string_encrypted)
@@ -22,6 +29,36 @@ impl State {
 	}
 }
 
+///
+/// Device code of the provider, sent to the client to poll the tokens using the same provider
+///
+#[derive(Serialize, Deserialize)]
+pub struct DeviceCode {
+	pub code: String,
+	/// Name of the provider of the device authorization, when there are named providers
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub provider: Option<String>,
+}
+
+impl DeviceCode {
+	pub fn serialize_code(
+		crypto: &Crypto,
+		code: String,
+		provider: Option<String>,
+	) -> Result<String, Error> {
+		let device_code = Self { code, provider };
+		let device_code_string = serde_json::to_string(&device_code)?;
+		let device_code_string_encrypted = crypto.encrypt(&device_code_string)?;
+		Ok(device_code_string_encrypted)
+	}
+
+	pub fn deserialize_code(crypto: &Crypto, token: &str) -> Result<Self, Error> {
+		let token_decrypted = crypto.decrypt(token)?;
+		let device_code: Self = serde_json::from_str(&token_decrypted)?;
+		Ok(device_code)
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -31,8 +68,27 @@ mod tests {
 		let c = Crypto::new("Some key to test", random);
 
 		let data = "Some random data";
-		let state_token = State::serialize_state(&c, Some(String::from(data))).unwrap();
+		let state_token = State::serialize_state(&c, Some(String::from(data)), None).unwrap();
 		let state = State::deserialize_state(&c, &state_token).unwrap();
 		assert_eq!(data, state.url.unwrap());
+		assert!(state.provider.is_none());
+
+		let state_token =
+			State::serialize_state(&c, None, Some(String::from("employees"))).unwrap();
+		let state = State::deserialize_state(&c, &state_token).unwrap();
+		assert_eq!("employees", state.provider.unwrap());
+	}
+	#[test]
+	fn test_device_code() {
+		let random = Crypto::create_random();
+		let c = Crypto::new("Some key to test", random);
+
+		let token =
+			DeviceCode::serialize_code(&c, String::from("code"), Some(String::from("github")))
+				.unwrap();
+		let device_code = DeviceCode::deserialize_code(&c, &token).unwrap();
+		assert_eq!("code", device_code.code);
+		assert_eq!("github", device_code.provider.unwrap());
+		assert!(DeviceCode::deserialize_code(&c, "code").is_err());
 	}
 }
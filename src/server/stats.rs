@@ -0,0 +1,45 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub type StatsPtr = std::sync::Arc<Stats>;
+
+///
+/// Counters for the denied requests, shared between the workers
+///
+#[derive(Default)]
+pub struct Stats {
+	unauthorized: AtomicU64,
+	forbidden: AtomicU64,
+}
+
+impl Stats {
+	///
+	/// Create the shared counters
+	///
+	pub fn create() -> StatsPtr {
+		std::sync::Arc::new(Self::default())
+	}
+	///
+	/// Count a request without a valid session and return the total
+	///
+	pub fn add_unauthorized(&self) -> u64 {
+		self.unauthorized.fetch_add(1, Ordering::Relaxed) + 1
+	}
+	///
+	/// Count a request with a valid session but without permission and return the total
+	///
+	pub fn add_forbidden(&self) -> u64 {
+		self.forbidden.fetch_add(1, Ordering::Relaxed) + 1
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[test]
+	fn test_counters() {
+		let stats = Stats::create();
+		assert_eq!(1, stats.add_unauthorized());
+		assert_eq!(2, stats.add_unauthorized());
+		assert_eq!(1, stats.add_forbidden());
+	}
+}
@@ -0,0 +1,263 @@
+use crate::error::Error;
+use crate::settings::SettingsTls;
+use actix_rt::signal::unix::{signal, SignalKind};
+use openssl::ssl::{
+	AlpnError, ClientHelloResponse, SslAcceptor, SslAcceptorBuilder, SslContext, SslFiletype,
+	SslMethod, SslVerifyMode,
+};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// How often the certificate files are checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(30);
+
+///
+/// TLS certificates of the https listeners, reloaded on SIGHUP or when the files change
+///
+pub struct Tls {
+	settings: SettingsTls,
+	verify_mode: SslVerifyMode,
+	context: RwLock<SslContext>,
+	modified: Mutex<Vec<Option<SystemTime>>>,
+}
+
+pub type TlsPtr = Arc<Tls>;
+
+impl Tls {
+	///
+	/// Load the certificates in the settings
+	///
+	pub fn create(settings: &SettingsTls) -> Result<TlsPtr, Error> {
+		let verify_mode = match settings.client_auth.as_str() {
+			"none" => SslVerifyMode::NONE,
+			"optional" => SslVerifyMode::PEER,
+			"required" => SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+			_ => return Err(Error::SettingsError("Invalid TLS client auth")),
+		};
+		if verify_mode != SslVerifyMode::NONE && settings.client_ca.is_none() {
+			return Err(Error::SettingsError(
+				"TLS client auth requires the client CA",
+			));
+		}
+		let tls = Self {
+			settings: settings.clone(),
+			verify_mode,
+			context: RwLock::new(
+				Self::create_builder(settings, verify_mode)?
+					.build()
+					.into_context(),
+			),
+			modified: Mutex::new(Self::get_modified(settings)),
+		};
+		Ok(Arc::new(tls))
+	}
+	///
+	/// Create the acceptor for a listener
+	///
+	/// Every connection switches to the latest loaded context on the client hello, with or
+	/// without SNI, so the listeners do not need to be restarted when the certificates are
+	/// reloaded.
+	///
+	pub fn acceptor(tls: &TlsPtr) -> Result<SslAcceptorBuilder, Error> {
+		let mut builder = Self::create_builder(&tls.settings, tls.verify_mode)?;
+		let tls = tls.clone();
+		builder.set_client_hello_callback(move |ssl, _| {
+			let context = tls.context.read().unwrap();
+			ssl.set_ssl_context(&context)?;
+			Ok(ClientHelloResponse::SUCCESS)
+		});
+		Ok(builder)
+	}
+	///
+	/// Reload the certificates on SIGHUP, or when the files change
+	///
+	pub fn watch(tls: &TlsPtr) {
+		let signal_tls = tls.clone();
+		actix_rt::spawn(async move {
+			let mut hangup = match signal(SignalKind::hangup()) {
+				Ok(hangup) => hangup,
+				Err(error) => {
+					log::error!("Could not listen to SIGHUP: {}", error);
+					return;
+				}
+			};
+			while hangup.recv().await.is_some() {
+				log::info!("SIGHUP received, reloading the TLS certificates");
+				signal_tls.reload();
+			}
+		});
+		let watch_tls = tls.clone();
+		actix_rt::spawn(async move {
+			let mut interval = actix_rt::time::interval(WATCH_INTERVAL);
+			loop {
+				interval.tick().await;
+				let modified = Self::get_modified(&watch_tls.settings);
+				if *watch_tls.modified.lock().unwrap() != modified {
+					log::info!("TLS certificate files changed, reloading");
+					watch_tls.reload();
+				}
+			}
+		});
+	}
+	///
+	/// Reload the certificates. On errors the current ones are kept
+	///
+	fn reload(&self) {
+		let modified = Self::get_modified(&self.settings);
+		match Self::create_builder(&self.settings, self.verify_mode) {
+			Ok(builder) => {
+				*self.context.write().unwrap() = builder.build().into_context();
+				log::info!("TLS certificates reloaded");
+			}
+			Err(error) => log::error!("Error reloading the TLS certificates: {:?}", error),
+		}
+		*self.modified.lock().unwrap() = modified;
+	}
+	///
+	/// Create the acceptor using the files in the settings
+	///
+	fn create_builder(
+		settings: &SettingsTls,
+		verify_mode: SslVerifyMode,
+	) -> Result<SslAcceptorBuilder, Error> {
+		let cert = settings
+			.cert
+			.as_ref()
+			.ok_or(Error::SettingsError("TLS requires the certificate"))?;
+		let key = settings
+			.key
+			.as_ref()
+			.ok_or(Error::SettingsError("TLS requires the private key"))?;
+
+		let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())?;
+		builder.set_certificate_chain_file(cert)?;
+		builder.set_private_key_file(key, SslFiletype::PEM)?;
+		builder.check_private_key()?;
+		if let Some(ref client_ca) = settings.client_ca {
+			builder.set_ca_file(client_ca)?;
+			builder.set_client_ca_list(openssl::x509::X509Name::load_client_ca_file(client_ca)?);
+		}
+		builder.set_verify(verify_mode);
+
+		// The context replaces the one of the listener, so it needs the same protocols
+		builder.set_alpn_select_callback(|_, protocols| {
+			if protocols.windows(3).any(|window| window == b"\x02h2") {
+				Ok(b"h2")
+			} else if protocols.windows(9).any(|window| window == b"\x08http/1.1") {
+				Ok(b"http/1.1")
+			} else {
+				Err(AlpnError::NOACK)
+			}
+		});
+		Ok(builder)
+	}
+	///
+	/// Get the modification time of the files
+	///
+	fn get_modified(settings: &SettingsTls) -> Vec<Option<SystemTime>> {
+		[&settings.cert, &settings.key, &settings.client_ca]
+			.iter()
+			.map(|file| {
+				file.as_ref()
+					.and_then(|file| std::fs::metadata(file).ok())
+					.and_then(|metadata| metadata.modified().ok())
+			})
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use openssl::ssl::SslConnector;
+	use openssl::x509::X509;
+	use std::net::{TcpListener, TcpStream};
+
+	///
+	/// Write a self-signed certificate and its key
+	///
+	fn write_certificate(cert: &str, key: &str, name: &str) {
+		let pkey =
+			openssl::pkey::PKey::from_rsa(openssl::rsa::Rsa::generate(2048).unwrap()).unwrap();
+		let mut subject = openssl::x509::X509NameBuilder::new().unwrap();
+		subject
+			.append_entry_by_nid(openssl::nid::Nid::COMMONNAME, name)
+			.unwrap();
+		let subject = subject.build();
+		let mut builder = X509::builder().unwrap();
+		builder.set_subject_name(&subject).unwrap();
+		builder.set_issuer_name(&subject).unwrap();
+		builder.set_pubkey(&pkey).unwrap();
+		let not_before = openssl::asn1::Asn1Time::days_from_now(0).unwrap();
+		let not_after = openssl::asn1::Asn1Time::days_from_now(1).unwrap();
+		builder.set_not_before(&not_before).unwrap();
+		builder.set_not_after(&not_after).unwrap();
+		builder
+			.sign(&pkey, openssl::hash::MessageDigest::sha256())
+			.unwrap();
+		std::fs::write(cert, builder.build().to_pem().unwrap()).unwrap();
+		std::fs::write(key, pkey.private_key_to_pem_pkcs8().unwrap()).unwrap();
+	}
+
+	///
+	/// Connect without SNI, and get the common name of the server certificate
+	///
+	fn connect_without_sni(acceptor: &SslAcceptor) -> String {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let address = listener.local_addr().unwrap();
+		let acceptor = acceptor.clone();
+		let server = std::thread::spawn(move || {
+			let (stream, _) = listener.accept().unwrap();
+			let _ = acceptor.accept(stream);
+		});
+		let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+		connector.set_verify(SslVerifyMode::NONE);
+		let stream = connector
+			.build()
+			.configure()
+			.unwrap()
+			.use_server_name_indication(false)
+			.verify_hostname(false)
+			.connect("", TcpStream::connect(address).unwrap())
+			.unwrap();
+		let certificate = stream.ssl().peer_certificate().unwrap();
+		let name = certificate
+			.subject_name()
+			.entries_by_nid(openssl::nid::Nid::COMMONNAME)
+			.next()
+			.unwrap()
+			.data()
+			.as_utf8()
+			.unwrap()
+			.to_string();
+		drop(stream);
+		server.join().unwrap();
+		name
+	}
+
+	#[test]
+	fn test_reload() {
+		let dir = std::env::temp_dir();
+		let cert = dir.join(format!("auth-gatekeeper-tls-{}.crt", std::process::id()));
+		let key = dir.join(format!("auth-gatekeeper-tls-{}.key", std::process::id()));
+		let cert = cert.to_str().unwrap().to_owned();
+		let key = key.to_str().unwrap().to_owned();
+		write_certificate(&cert, &key, "first");
+		let tls = Tls::create(&SettingsTls {
+			cert: Some(cert.clone()),
+			key: Some(key.clone()),
+			client_ca: None,
+			client_auth: String::from("none"),
+		})
+		.unwrap();
+		let acceptor = Tls::acceptor(&tls).unwrap().build();
+		assert_eq!("first", connect_without_sni(&acceptor));
+
+		write_certificate(&cert, &key, "second");
+		tls.reload();
+		assert_eq!("second", connect_without_sni(&acceptor));
+
+		let _ = std::fs::remove_file(&cert);
+		let _ = std::fs::remove_file(&key);
+	}
+}
@@ -0,0 +1,264 @@
+use crate::error::Error;
+use crate::settings::Settings;
+use actix_web::{
+	http::{header, uri::Authority},
+	HttpRequest,
+};
+use std::net::{IpAddr, SocketAddr};
+
+///
+/// A network in the CIDR notation
+///
+pub struct Network {
+	address: IpAddr,
+	prefix: u32,
+}
+
+///
+/// The proxies allowed to send forwarded headers and call the forward auth endpoints
+///
+pub struct TrustedProxies {
+	networks: Option<Vec<Network>>,
+}
+
+impl Network {
+	///
+	/// Parse a network like `10.0.0.0/8` or a single address
+	///
+	pub fn parse(value: &str) -> Result<Self, Error> {
+		let mut value_split = value.trim().splitn(2, '/');
+		let address: IpAddr = value_split
+			.next()
+			.unwrap_or("")
+			.parse()
+			.map_err(|_| Error::SettingsError("Invalid network address"))?;
+		let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+		let prefix = match value_split.next() {
+			Some(prefix) => prefix
+				.parse()
+				.ok()
+				.filter(|p| *p <= max_prefix)
+				.ok_or(Error::SettingsError("Invalid network prefix"))?,
+			None => max_prefix,
+		};
+		Ok(Self { address, prefix })
+	}
+	///
+	/// Check if the address is inside the network
+	///
+	pub fn contains(&self, address: &IpAddr) -> bool {
+		let address = match address {
+			IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*address),
+			_ => *address,
+		};
+		match (self.address, address) {
+			(IpAddr::V4(network), IpAddr::V4(address)) => {
+				let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+				u32::from(network) & mask == u32::from(address) & mask
+			}
+			(IpAddr::V6(network), IpAddr::V6(address)) => {
+				let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+				u128::from(network) & mask == u128::from(address) & mask
+			}
+			_ => false,
+		}
+	}
+}
+
+impl TrustedProxies {
+	///
+	/// Create using the comma delimited list in the settings. Without it, every peer is trusted
+	///
+	pub fn new(settings: &Settings) -> Result<Self, Error> {
+		Self::parse(settings.trusted_proxies.as_deref())
+	}
+	///
+	/// Parse the comma delimited list of networks
+	///
+	pub fn parse(value: Option<&str>) -> Result<Self, Error> {
+		let networks = match value {
+			Some(value) => Some(
+				value
+					.split(',')
+					.filter(|n| !n.trim().is_empty())
+					.map(Network::parse)
+					.collect::<Result<Vec<_>, _>>()?,
+			),
+			None => None,
+		};
+		Ok(Self { networks })
+	}
+	///
+	/// Check if the peer of the request is a trusted proxy
+	///
+	/// Requests without a peer address come from the unix socket, which is trusted
+	///
+	pub fn is_trusted(&self, req: &HttpRequest) -> bool {
+		self.is_trusted_peer(req.peer_addr())
+	}
+	///
+	/// Check if the peer address is a trusted proxy
+	///
+	pub fn is_trusted_peer(&self, peer: Option<SocketAddr>) -> bool {
+		let networks = match self.networks {
+			Some(ref networks) => networks,
+			None => return true,
+		};
+		match peer {
+			Some(peer) => networks.iter().any(|n| n.contains(&peer.ip())),
+			None => true,
+		}
+	}
+	///
+	/// Get the address of the client
+	///
+	/// Each proxy appends the address of its peer to the x-forwarded-for header, so only the
+	/// last addresses are set by the proxies of the settings. The header is read from the right,
+	/// skipping them, and the first other address is the client. Without the setting, no peer is
+	/// a proxy and the address of the peer is used
+	///
+	pub fn get_client_ip(&self, req: &HttpRequest) -> Option<IpAddr> {
+		let peer = req.peer_addr().map(|addr| addr.ip());
+		let networks = match self.networks {
+			Some(ref networks) => networks,
+			None => return peer,
+		};
+		if !self.is_trusted(req) {
+			return peer;
+		}
+		let forwarded_for: Vec<&str> = req
+			.headers()
+			.get_all("x-forwarded-for")
+			.filter_map(|h| h.to_str().ok())
+			.flat_map(|h| h.split(','))
+			.map(|address| address.trim())
+			.filter(|address| !address.is_empty())
+			.collect();
+		let mut client = peer;
+		for address in forwarded_for.into_iter().rev() {
+			// An address that can not be parsed may hide the client, so there is none
+			let address: IpAddr = address.parse().ok()?;
+			client = Some(address);
+			if !networks.iter().any(|n| n.contains(&address)) {
+				break;
+			}
+		}
+		client
+	}
+}
+
+///
+/// Get the scheme and the host of the request
+///
+/// The forwarded headers are only used when the peer is trusted
+///
+pub fn get_scheme_host(req: &HttpRequest, trusted: bool) -> (String, String) {
+	if trusted {
+		let info = req.connection_info();
+		return (info.scheme().to_owned(), info.host().to_owned());
+	}
+	let config = req.app_config();
+	let scheme = if config.secure() { "https" } else { "http" };
+	let host = req
+		.headers()
+		.get(header::HOST)
+		.and_then(|h| h.to_str().ok())
+		.unwrap_or_else(|| config.host());
+	(scheme.to_owned(), host.to_owned())
+}
+
+///
+/// Get the host of the authority (Ex: `example.com:8080` or `[::1]:8080`) without the port,
+/// in lowercase
+///
+pub fn host_without_port(authority: &str) -> Option<String> {
+	let authority: Authority = authority.trim().parse().ok()?;
+	Some(authority.host().to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[test]
+	fn test_network() {
+		let network = Network::parse("10.1.0.0/16").unwrap();
+		assert!(network.contains(&"10.1.2.3".parse().unwrap()));
+		assert!(network.contains(&"::ffff:10.1.2.3".parse().unwrap()));
+		assert!(!network.contains(&"10.2.0.1".parse().unwrap()));
+
+		let network = Network::parse("fd00::/8").unwrap();
+		assert!(network.contains(&"fd12::1".parse().unwrap()));
+		assert!(!network.contains(&"::1".parse().unwrap()));
+
+		assert!(Network::parse("0.0.0.0/0")
+			.unwrap()
+			.contains(&"1.2.3.4".parse().unwrap()));
+		assert!(Network::parse("127.0.0.1")
+			.unwrap()
+			.contains(&"127.0.0.1".parse().unwrap()));
+		assert!(Network::parse("10.0.0.0/33").is_err());
+		assert!(Network::parse("proxy").is_err());
+	}
+	#[test]
+	fn test_host_without_port() {
+		assert_eq!(
+			Some(String::from("example.com")),
+			host_without_port("Example.com:8080")
+		);
+		assert_eq!(
+			Some(String::from("example.com")),
+			host_without_port("example.com")
+		);
+		assert_eq!(Some(String::from("[::1]")), host_without_port("[::1]:8080"));
+		assert_eq!(Some(String::from("[::1]")), host_without_port("[::1]"));
+		assert_eq!(None, host_without_port(""));
+	}
+	#[test]
+	fn test_client_ip() {
+		let request = |forwarded_for: &str, peer: &str| {
+			actix_web::test::TestRequest::default()
+				.header("x-forwarded-for", forwarded_for)
+				.peer_addr(peer.parse().unwrap())
+				.to_http_request()
+		};
+		let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+
+		// Without the setting, every peer is trusted but none sends the client address
+		let proxies = TrustedProxies::parse(None).unwrap();
+		let req = request("10.0.0.1, 127.0.0.1", "127.0.0.1:1000");
+		assert!(proxies.is_trusted(&req));
+		assert_eq!(ip("127.0.0.1"), proxies.get_client_ip(&req));
+
+		// The proxies are skipped from the right, so the addresses sent by the client are not used
+		let proxies = TrustedProxies::parse(Some("127.0.0.1,192.168.0.0/16")).unwrap();
+		assert_eq!(
+			ip("203.0.113.1"),
+			proxies.get_client_ip(&request("203.0.113.1", "127.0.0.1:1000"))
+		);
+		assert_eq!(
+			ip("203.0.113.1"),
+			proxies.get_client_ip(&request(
+				"10.0.0.1, 203.0.113.1, 192.168.0.2",
+				"127.0.0.1:1000"
+			))
+		);
+		assert_eq!(
+			ip("192.168.0.2"),
+			proxies.get_client_ip(&request("192.168.0.2", "127.0.0.1:1000"))
+		);
+		assert_eq!(
+			None,
+			proxies.get_client_ip(&request("10.0.0.1, unknown", "127.0.0.1:1000"))
+		);
+		assert_eq!(
+			ip("127.0.0.1"),
+			proxies.get_client_ip(&request("", "127.0.0.1:1000"))
+		);
+
+		// Other peers are not proxies
+		assert_eq!(
+			ip("203.0.113.1"),
+			proxies.get_client_ip(&request("10.0.0.1", "203.0.113.1:1000"))
+		);
+	}
+}
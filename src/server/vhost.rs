@@ -0,0 +1,150 @@
+use super::trust::{host_without_port, TrustedProxies};
+use crate::error::Error;
+use crate::settings::{Settings, SettingsHost};
+use actix_web::dev::RequestHead;
+
+///
+/// A virtual host, using its own settings for the matching hosts
+///
+pub struct VirtualHost {
+	hosts: Vec<String>,
+	trusted_proxies: TrustedProxies,
+	pub settings: Settings,
+}
+
+impl VirtualHost {
+	///
+	/// Create the named virtual hosts of the settings
+	///
+	pub fn from_settings(settings: &Settings) -> Result<Vec<Self>, Error> {
+		let mut vhosts = Vec::new();
+		for name in split_list(settings.hosts.as_deref().unwrap_or("")) {
+			let host = settings
+				.named_hosts
+				.get(&name)
+				.ok_or(Error::SettingsError("Missing the settings of a named host"))?;
+			vhosts.push(Self {
+				hosts: split_list(&host.host),
+				trusted_proxies: TrustedProxies::new(settings)?,
+				settings: Self::create_settings(settings, host)?,
+			});
+		}
+		Ok(vhosts)
+	}
+	///
+	/// Replace the settings of the gatekeeper with the ones of the host
+	///
+	fn create_settings(settings: &Settings, host: &SettingsHost) -> Result<Settings, Error> {
+		let mut settings = settings.clone();
+		if let Some(ref providers) = host.providers {
+			let names = split_list(providers);
+			if names.is_empty()
+				|| names
+					.iter()
+					.any(|n| !settings.named_providers.contains_key(n))
+			{
+				return Err(Error::SettingsError("Unknown provider of a named host"));
+			}
+			// The first provider of the host is its default one
+			settings.provider = settings.named_providers[&names[0]].clone();
+			settings.providers = Some(providers.clone());
+		}
+		if let Some(ref callback_url) = host.callback_url {
+			settings.provider.callback_url = callback_url.clone();
+			for provider in settings.named_providers.values_mut() {
+				provider.callback_url = callback_url.clone();
+			}
+		}
+		if let Some(ref prefix) = host.cookie_prefix {
+			let cookie = &mut settings.cookie;
+			cookie.access_token_name = format!("{}{}", prefix, cookie.access_token_name);
+			cookie.refresh_token_name = format!("{}{}", prefix, cookie.refresh_token_name);
+		}
+		if host.cookie_domain.is_some() {
+			settings.cookie.domain = host.cookie_domain.clone();
+		}
+		if host.allowed_redirects.is_some() {
+			settings.allowed_redirects = host.allowed_redirects.clone();
+		}
+		settings.hosts = None;
+		settings.named_hosts.clear();
+		Ok(settings)
+	}
+	///
+	/// Check if the request is for the virtual host. Names like `*.example.com` match the subdomains
+	///
+	/// The forwarded host is only used from the trusted proxies
+	///
+	pub fn matches(&self, head: &RequestHead) -> bool {
+		let trusted = self.trusted_proxies.is_trusted_peer(head.peer_addr);
+		match get_host(head, trusted) {
+			Some(host) => host_matches(&self.hosts, &host),
+			None => false,
+		}
+	}
+}
+
+///
+/// Get the host of the request without the port, in lowercase
+///
+fn get_host(head: &RequestHead, trusted: bool) -> Option<String> {
+	let header = |name| head.headers.get(name).and_then(|h| h.to_str().ok());
+	let forwarded_host = if trusted {
+		header("x-forwarded-host").and_then(|h| h.split(',').next())
+	} else {
+		None
+	};
+	forwarded_host
+		.or_else(|| header("host"))
+		.and_then(host_without_port)
+}
+
+///
+/// Check if the host matches any of the names
+///
+fn host_matches(names: &[String], host: &str) -> bool {
+	names.iter().any(|name| match name.strip_prefix("*.") {
+		Some(domain) => host
+			.strip_suffix(domain)
+			.is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+		None => name == host,
+	})
+}
+
+///
+/// Split the comma delimited list, in lowercase
+///
+fn split_list(value: &str) -> Vec<String> {
+	value
+		.split(',')
+		.map(|v| v.trim().to_lowercase())
+		.filter(|v| !v.is_empty())
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use actix_web::test::TestRequest;
+	#[test]
+	fn test_matches() {
+		let names = split_list("acme.example.com, *.globex.com");
+		assert!(host_matches(&names, "acme.example.com"));
+		assert!(host_matches(&names, "a.globex.com"));
+		assert!(!host_matches(&names, "globex.com"));
+		assert!(!host_matches(&names, "evilglobex.com"));
+		assert!(!host_matches(&names, "other.example.com"));
+
+		let req = TestRequest::default()
+			.header("host", "gatekeeper:8088")
+			.header("x-forwarded-host", "ACME.example.com:8443")
+			.to_http_request();
+		assert_eq!("acme.example.com", get_host(req.head(), true).unwrap());
+		assert_eq!("gatekeeper", get_host(req.head(), false).unwrap());
+		let req = TestRequest::default()
+			.header("host", "[::1]:8088")
+			.to_http_request();
+		assert_eq!("[::1]", get_host(req.head(), false).unwrap());
+		assert!(host_matches(&split_list("[::1]"), "[::1]"));
+	}
+}
@@ -1,17 +1,27 @@
 use super::error::Error;
-use super::provider::{TokenSet, Userinfo};
+use super::provider::{ProviderBox, TokenSet, Userinfo};
+use super::server::basic_auth::{is_browser, BasicCredentials};
 use super::server::data::Data;
+use super::server::profile::{OriginalRequest, ProxyProfile};
 use crate::util::jwt::JsonValue;
 use actix_web::{
-	cookie, dev::HttpResponseBuilder, http::header::AUTHORIZATION, http::StatusCode, web,
-	HttpMessage, HttpRequest, HttpResponse,
+	cookie,
+	dev::HttpResponseBuilder,
+	http::header::{AUTHORIZATION, WWW_AUTHENTICATE},
+	http::StatusCode,
+	web, HttpMessage, HttpRequest, HttpResponse,
 };
 use std::collections::HashMap;
 
+/// Separates the name of the provider from the token in the encrypted values
+const PROVIDER_SEPARATOR: char = '\n';
+
 #[derive(Clone)]
 struct SessionTokenSet {
 	access_token: Option<String>,
 	refresh_token: Option<String>,
+	/// Name of the provider that issued the tokens, when there are named providers
+	provider: Option<String>,
 }
 
 enum SessionStatus {
@@ -25,6 +35,9 @@ enum SessionStatus {
 pub enum SessionAuthMethod {
 	Cookie,
 	AuthorizationToken,
+	ClientCertificate,
+	ApiKey,
+	Basic,
 }
 
 bitflags! {
@@ -40,24 +53,35 @@ bitflags! {
 pub struct Session {
 	data: web::Data<Data>,
 	status: SessionStatus,
+	forbidden: bool,
+	profile: Option<ProxyProfile>,
 	has_session: bool,
 	auth_method: Option<SessionAuthMethod>,
 	token_set: Option<SessionTokenSet>,
+	exchanged_token: Option<String>,
 	id_token: Option<serde_json::Value>,
+	identity: Option<JsonValue>,
+	basic_credentials: Option<BasicCredentials>,
 }
 
 impl Session {
-	pub fn new(data: web::Data<Data>, token_set: TokenSet) -> Self {
+	pub fn new(data: web::Data<Data>, provider: Option<String>, token_set: TokenSet) -> Self {
 		Self {
 			data: data,
 			auth_method: None,
 			status: SessionStatus::New(None),
+			forbidden: false,
+			profile: None,
 			token_set: Some(SessionTokenSet {
 				access_token: Some(token_set.access_token),
-				refresh_token: Some(token_set.refresh_token),
+				refresh_token: token_set.refresh_token,
+				provider,
 			}),
 			has_session: false,
+			exchanged_token: None,
 			id_token: token_set.id_token,
+			identity: None,
+			basic_credentials: None,
 		}
 	}
 
@@ -66,9 +90,14 @@ impl Session {
 			data: data,
 			auth_method: None,
 			status: SessionStatus::Logout,
+			forbidden: false,
+			profile: None,
 			token_set: None,
 			has_session: true,
+			exchanged_token: None,
 			id_token: None,
+			identity: None,
+			basic_credentials: None,
 		}
 	}
 
@@ -82,15 +111,57 @@ impl Session {
 				Self::request_get_token_set_from_authorization(&data, &req)
 					.map(|v| (Some(v), Some(SessionAuthMethod::AuthorizationToken)))
 			});
-		let has_session = token_pair.is_some();
 		let (token_set, auth_method) = token_pair.unwrap_or((None, None));
+
+		// Api keys and client certificates are only used without a token
+		let (identity, auth_method) = match auth_method {
+			Some(auth_method) => (None, Some(auth_method)),
+			None => Self::request_get_identity(&data, req),
+		};
+		let has_session = auth_method.is_some();
+
+		// Basic credentials are checked on validate, and are not a session to clear
+		let (basic_credentials, auth_method) = match auth_method {
+			Some(auth_method) => (None, Some(auth_method)),
+			None => match data.basic_auth.get_credentials(req, &data.trusted_proxies) {
+				Some(credentials) => (Some(credentials), Some(SessionAuthMethod::Basic)),
+				None => (None, None),
+			},
+		};
 		Self {
 			data: data,
 			status: SessionStatus::Invalid,
+			forbidden: false,
+			profile: None,
 			token_set,
 			auth_method,
 			has_session: has_session,
+			exchanged_token: None,
 			id_token: None,
+			identity,
+			basic_credentials,
+		}
+	}
+
+	///
+	/// Get the claims of an api key or a client certificate
+	///
+	/// An invalid api key is not a session, so the request is only unauthorized
+	///
+	fn request_get_identity(
+		data: &web::Data<Data>,
+		req: &HttpRequest,
+	) -> (Option<JsonValue>, Option<SessionAuthMethod>) {
+		let trusted = data.trusted_proxies.is_trusted(req);
+		if let Some(key) = data.api_keys.get_request_key(req) {
+			return match data.api_keys.validate(req, key, &data.trusted_proxies) {
+				Some(claims) => (Some(claims), Some(SessionAuthMethod::ApiKey)),
+				None => (None, None),
+			};
+		}
+		match data.client_cert.get_claims(req, trusted) {
+			Some(claims) => (Some(claims), Some(SessionAuthMethod::ClientCertificate)),
+			None => (None, None),
 		}
 	}
 
@@ -120,20 +191,9 @@ impl Session {
 		if access_token.is_none() && refresh_token.is_none() {
 			return None;
 		}
-		let access_token = if let Some(access_token) = access_token {
-			data.crypto.decrypt(access_token).ok()
-		} else {
-			None
-		};
-		let refresh_token = if let Some(refresh_token) = refresh_token {
-			data.crypto.decrypt(refresh_token).ok()
-		} else {
-			None
-		};
-		return Some(SessionTokenSet {
-			access_token: access_token,
-			refresh_token: refresh_token,
-		});
+		let access_token = access_token.and_then(|t| Self::decrypt_token(data, t));
+		let refresh_token = refresh_token.and_then(|t| Self::decrypt_token(data, t));
+		Some(Self::create_token_set(access_token, refresh_token))
 	}
 
 	/// Get the token set from the request
@@ -164,15 +224,54 @@ impl Session {
 		if tokens.len() == 0 {
 			return None;
 		} else if tokens.len() == 1 {
-			return Some(SessionTokenSet {
-				access_token: data.crypto.decrypt(tokens[0]).ok(),
-				refresh_token: None,
-			});
+			return Some(Self::create_token_set(
+				Self::decrypt_token(data, tokens[0]),
+				None,
+			));
+		}
+		Some(Self::create_token_set(
+			Self::decrypt_token(data, tokens[0]),
+			Self::decrypt_token(data, tokens[1]),
+		))
+	}
+
+	///
+	/// Decrypt a token, and the name of the provider that issued it when recorded
+	///
+	fn decrypt_token(data: &web::Data<Data>, value: &str) -> Option<(Option<String>, String)> {
+		let value = data.crypto.decrypt(value).ok()?;
+		match value.split_once(PROVIDER_SEPARATOR) {
+			Some((provider, token)) => Some((Some(provider.to_owned()), token.to_owned())),
+			None => Some((None, value)),
+		}
+	}
+
+	///
+	/// Create the token set from the decrypted tokens
+	///
+	fn create_token_set(
+		access_token: Option<(Option<String>, String)>,
+		refresh_token: Option<(Option<String>, String)>,
+	) -> SessionTokenSet {
+		let (access_provider, access_token) = access_token.unzip();
+		let (refresh_provider, refresh_token) = refresh_token.unzip();
+		SessionTokenSet {
+			access_token,
+			refresh_token,
+			provider: access_provider
+				.flatten()
+				.or_else(|| refresh_provider.flatten()),
+		}
+	}
+
+	///
+	/// Add the name of the provider to the token before encrypting it
+	///
+	fn token_with_provider(provider: &Option<String>, token: &str) -> String {
+		match provider {
+			Some(provider) => format!("{}{}{}", provider, PROVIDER_SEPARATOR, token),
+			None => token.to_owned(),
 		}
-		return Some(SessionTokenSet {
-			access_token: data.crypto.decrypt(tokens[0]).ok(),
-			refresh_token: data.crypto.decrypt(tokens[1]).ok(),
-		});
 	}
 
 	///
@@ -222,21 +321,168 @@ impl Session {
 		}
 	}
 	///
+	/// Get the provider that issued the tokens of the session, or the default provider
+	///
+	/// Returns None when the provider is not configured anymore
+	///
+	pub fn get_provider(&self) -> Option<&ProviderBox> {
+		let name = self.token_set.as_ref().and_then(|t| t.provider.as_deref());
+		self.data.providers.get(name)
+	}
+	///
+	/// Set the proxy calling the forward auth endpoints (Defaults to the settings)
+	///
+	pub fn set_profile(&mut self, profile: ProxyProfile) {
+		self.profile = Some(profile);
+	}
+	///
+	/// Get the proxy calling the forward auth endpoints
+	///
+	fn get_profile(&self) -> ProxyProfile {
+		self.profile.unwrap_or(self.data.profile)
+	}
+	///
+	/// Get the original request, trusting the forwarded headers only from the trusted proxies
+	///
+	fn original_request(&self, req: &HttpRequest) -> OriginalRequest {
+		let trusted = self.data.trusted_proxies.is_trusted(req);
+		self.get_profile().original_request(req, trusted)
+	}
+	///
+	/// Mark the session as forbidden when the user has none of the comma delimited roles
+	///
+	pub fn require_roles(&mut self, roles: &str) {
+		let roles: Vec<&str> = roles
+			.split(',')
+			.map(|r| r.trim())
+			.filter(|r| !r.is_empty())
+			.collect();
+		if roles.is_empty() {
+			return;
+		}
+		if let Some(userinfo) = self.get_userinfo() {
+			if !userinfo.has_any_role(&roles) {
+				self.forbidden = true;
+			}
+		}
+	}
+	///
+	/// Exchange the access token for the target audience, relaying it instead of the access token
+	///
+	/// The target is selected using the audience, or the path of the original request. If the
+	/// exchange is denied, the session is marked as forbidden.
+	///
+	pub async fn exchange_token(
+		&mut self,
+		req: &HttpRequest,
+		audience: Option<&str>,
+	) -> Result<(), Error> {
+		if self.get_userinfo().is_none() || self.forbidden {
+			return Ok(());
+		}
+		let access_token = match self
+			.token_set
+			.as_ref()
+			.and_then(|t| t.access_token.as_ref())
+		{
+			Some(access_token) => access_token.clone(),
+			None => return Ok(()),
+		};
+		let data = self.data.clone();
+		let uri = self.original_request(req).uri;
+		let target = match data.exchange.find(audience, &uri) {
+			Some(target) => target,
+			None => return Ok(()),
+		};
+		let provider = match self.get_provider() {
+			Some(provider) => provider,
+			None => return Ok(()),
+		};
+		self.exchanged_token = data
+			.exchange
+			.exchange(provider, &access_token, target)
+			.await?;
+		if self.exchanged_token.is_none() {
+			log::info!("Token exchange denied for {}", uri);
+			self.forbidden = true;
+		}
+		Ok(())
+	}
+	///
 	/// Validate the information and try to refresh the session
 	///
+	/// Users of the provider may still be forbidden by it (Ex: not in the GitHub organizations)
+	///
 	pub async fn validate(&mut self, refresh: bool) -> Result<(), Error> {
+		self.validate_session(refresh).await?;
+		if self.identity.is_some() {
+			return Ok(());
+		}
+		if let (Some(userinfo), Some(provider)) = (self.get_userinfo(), self.get_provider()) {
+			if !provider.is_allowed(userinfo) {
+				self.forbidden = true;
+			}
+		}
+		Ok(())
+	}
+	///
+	/// Validate the tokens or the identity of the session
+	///
+	async fn validate_session(&mut self, refresh: bool) -> Result<(), Error> {
 		// Invalidates the session
 		self.status = SessionStatus::Invalid;
+		self.forbidden = false;
+
+		// The api key or the client certificate were already verified, their claims are the userinfo
+		if let Some(ref claims) = self.identity {
+			self.status = SessionStatus::Logged(Some(Userinfo {
+				data: claims.clone(),
+				expires_at: None,
+			}));
+			return Ok(());
+		}
+
+		// Basic credentials are valid when the password grant succeeds
+		if let Some(ref credentials) = self.basic_credentials {
+			let (name, provider) = match self.data.providers.select(None) {
+				Some(selected) => selected,
+				None => return Ok(()),
+			};
+			let result = self
+				.data
+				.basic_auth
+				.authenticate(name.as_deref(), provider, credentials)
+				.await?;
+			if let Some(result) = result {
+				self.token_set = Some(SessionTokenSet {
+					access_token: Some(result.access_token),
+					refresh_token: None,
+					provider: None,
+				});
+				self.status = SessionStatus::Logged(Some(Userinfo {
+					data: result.userinfo,
+					expires_at: None,
+				}));
+			}
+			return Ok(());
+		}
 
 		// If there is no token, then it is already invalid
 		if self.token_set.is_none() {
 			return Ok(());
 		}
 
-		// If there is a token set already, try to load the userinfo
+		// The tokens are sent to the provider that issued them
+		let data = self.data.clone();
 		let token_set = self.token_set.clone().unwrap();
+		let provider = match data.providers.get(token_set.provider.as_deref()) {
+			Some(provider) => provider,
+			None => return Ok(()),
+		};
+
+		// If there is a token set already, try to load the userinfo
 		if let Some(access_token) = token_set.access_token {
-			let userinfo = self.data.provider.userinfo(&access_token).await?;
+			let userinfo = provider.userinfo(&access_token).await?;
 			if userinfo.is_some() {
 				self.status = SessionStatus::Logged(userinfo);
 				return Ok(());
@@ -246,21 +492,15 @@ impl Session {
 		// Check if need to refresh
 		if refresh {
 			if let Some(refresh_token) = token_set.refresh_token {
-				let new_token_set_result = self
-					.data
-					.provider
-					.grant_refresh_token(&refresh_token)
-					.await?;
+				let new_token_set_result = provider.grant_refresh_token(&refresh_token).await?;
 				if let Some(new_token_set) = new_token_set_result {
-					let userinfo = self
-						.data
-						.provider
-						.userinfo(&new_token_set.access_token)
-						.await?;
+					let userinfo = provider.userinfo(&new_token_set.access_token).await?;
 					if userinfo.is_some() {
 						self.token_set = Some(SessionTokenSet {
 							access_token: Some(new_token_set.access_token),
-							refresh_token: Some(new_token_set.refresh_token),
+							// Providers that do not rotate the refresh token keep the current one
+							refresh_token: new_token_set.refresh_token.or(Some(refresh_token)),
+							provider: token_set.provider,
 						});
 						self.id_token = new_token_set.id_token;
 						self.status = SessionStatus::New(userinfo);
@@ -292,18 +532,30 @@ impl Session {
 		};
 		match self.status {
 			SessionStatus::Invalid => {
+				let count = self.data.stats.add_unauthorized();
 				if self.has_session {
+					log::info!("Invalid session ({} unauthorized requests)", count);
 					self.response_save_session(&mut cookies, None)?;
 					self.api_logout(&mut cookies).await?;
 				}
 				builder.status(StatusCode::UNAUTHORIZED);
+				// Clients that can not follow the login page are asked for the basic credentials
+				if self.data.basic_auth.is_enabled()
+					&& (self.basic_credentials.is_some() || !is_browser(req))
+				{
+					builder.header(WWW_AUTHENTICATE, self.data.basic_auth.get_challenge());
+					flags.remove(SessionFlags::FORWARD_AUTH_REDIRECT);
+				}
 				if flags.contains(SessionFlags::FORWARD_AUTH) {
-					if flags.contains(SessionFlags::FORWARD_AUTH_REDIRECT) {
-						let location = self.response_forward_auth_get_redirect(req);
-						builder.status(StatusCode::FOUND);
-						builder.header("location", location);
-					}
-					flags = flags | SessionFlags::COOKIES;
+					let original_request = self.original_request(req);
+					let get_only = self.get_profile().is_login_get_only();
+					let location = Some(original_request.login_url(get_only));
+					flags = self.response_forward_auth_deny(
+						builder,
+						flags,
+						"x-auth-login-url",
+						location,
+					);
 				}
 			}
 			SessionStatus::Logout => {
@@ -311,22 +563,36 @@ impl Session {
 				self.api_logout(&mut cookies).await?;
 			}
 			SessionStatus::New(ref userinfo) => {
-				if flags.contains(SessionFlags::FORWARD_AUTH) {
-					flags = flags | SessionFlags::X_AUTH_HEADERS;
-				}
 				self.response_save_session(&mut cookies, self.token_set.clone())?;
-				self.response_set_userinfo(builder, &userinfo, flags)?;
+				if self.forbidden {
+					flags = self.response_forbidden(builder, userinfo, flags);
+				} else {
+					if flags.contains(SessionFlags::FORWARD_AUTH) {
+						flags |= SessionFlags::X_AUTH_HEADERS;
+						if self.get_profile().is_success_set_cookie() {
+							flags |= SessionFlags::COOKIES;
+						}
+					}
+					self.response_set_userinfo(builder, userinfo, flags)?;
+				}
 				self.api_id_token(&mut cookies).await?;
 			}
 			SessionStatus::Logged(ref userinfo) => {
-				if flags.contains(SessionFlags::FORWARD_AUTH) {
-					flags = flags | SessionFlags::X_AUTH_HEADERS;
+				if self.forbidden {
+					flags = self.response_forbidden(builder, userinfo, flags);
+				} else {
+					if flags.contains(SessionFlags::FORWARD_AUTH) {
+						flags |= SessionFlags::X_AUTH_HEADERS;
+					}
+					self.response_set_userinfo(builder, userinfo, flags)?;
 				}
-				self.response_set_userinfo(builder, &userinfo, flags)?;
 			}
 		}
 		if let Some(cookies) = cookies {
-			if flags.contains(SessionFlags::X_AUTH_HEADERS) {
+			// Proxies setting the cookies directly on success do not need the x-auth-set-cookie
+			if flags.contains(SessionFlags::X_AUTH_HEADERS)
+				&& !self.get_profile().is_success_set_cookie()
+			{
 				let mut i = 1;
 				for cookie in &cookies {
 					builder.header(&format!("x-auth-set-cookie-{}", i), cookie.to_string());
@@ -364,12 +630,15 @@ impl Session {
 		if token_set.access_token.is_none() {
 			return Ok(None);
 		}
-		let access_token = self
-			.data
-			.crypto
-			.encrypt(token_set.access_token.as_ref().unwrap())?;
+		let access_token = self.data.crypto.encrypt(&Self::token_with_provider(
+			&token_set.provider,
+			token_set.access_token.as_ref().unwrap(),
+		))?;
 		if let Some(ref refresh_token) = token_set.refresh_token {
-			let refresh_token = self.data.crypto.encrypt(refresh_token)?;
+			let refresh_token = self.data.crypto.encrypt(&Self::token_with_provider(
+				&token_set.provider,
+				refresh_token,
+			))?;
 			return Ok(Some(format!("{}|{}", access_token, refresh_token)));
 		} else {
 			return Ok(Some(access_token));
@@ -384,7 +653,7 @@ impl Session {
 		builder: &mut HttpResponseBuilder,
 		need_authorization: Option<bool>,
 	) -> Result<HttpResponse, Error> {
-		let mut data: HashMap<&str, &JsonValue> = HashMap::with_capacity(5);
+		let mut data: HashMap<&str, &JsonValue> = HashMap::with_capacity(6);
 		if let Some(userinfo) = self.get_userinfo() {
 			if let Some(ref user_sub) = userinfo.data.get("sub") {
 				data.insert("sub", user_sub);
@@ -400,6 +669,9 @@ impl Session {
 					data.insert("roles", user_roles);
 				}
 			}
+			if let Some(user_groups) = userinfo.data.get("groups") {
+				data.insert("groups", user_groups);
+			}
 		}
 
 		let authorization_value: JsonValue;
@@ -413,27 +685,62 @@ impl Session {
 		Ok(builder.json(data))
 	}
 	///
-	/// GEt the redirect uri from forward auth
-	///
-	fn response_forward_auth_get_redirect(&self, req: &HttpRequest) -> String {
-		let proto = req
-			.headers()
-			.get("x-forwarded-proto")
-			.and_then(|h| h.to_str().ok());
-		let host = req
-			.headers()
-			.get("x-forwarded-host")
-			.and_then(|h| h.to_str().ok());
-		let location = req
-			.headers()
-			.get("x-forwarded-uri")
-			.and_then(|h| h.to_str().ok());
-		format!(
-			"{}://{}/login?url={}",
-			proto.unwrap_or("http"),
-			host.unwrap_or(""),
-			location.unwrap_or("/")
-		)
+	/// Deny a valid session without permission
+	///
+	/// Does not redirect to the login page, as the user is already logged in and would
+	/// loop between the provider and the application. Uses the forbidden_url when set.
+	///
+	fn response_forbidden(
+		&self,
+		builder: &mut HttpResponseBuilder,
+		userinfo: &Option<Userinfo>,
+		flags: SessionFlags,
+	) -> SessionFlags {
+		let count = self.data.stats.add_forbidden();
+		let sub = userinfo
+			.as_ref()
+			.and_then(|u| u.data.get("sub"))
+			.and_then(|s| s.as_str())
+			.unwrap_or("");
+		log::info!(
+			"Forbidden access for '{}' ({} forbidden requests)",
+			sub,
+			count
+		);
+		builder.status(StatusCode::FORBIDDEN);
+		if !flags.contains(SessionFlags::FORWARD_AUTH) {
+			return flags;
+		}
+		let location = self.data.settings.forbidden_url.clone();
+		self.response_forward_auth_deny(builder, flags, "x-auth-forbidden-url", location)
+	}
+	///
+	/// Deny the forward auth request according to the proxy profile
+	///
+	/// When the proxy sends the response to the client, it is possible to redirect and set the
+	/// cookies, like the earlier forward auth. Otherwise the location is sent on the `header`
+	/// and the cookies on x-auth-set-cookie
+	///
+	fn response_forward_auth_deny(
+		&self,
+		builder: &mut HttpResponseBuilder,
+		flags: SessionFlags,
+		header: &str,
+		location: Option<String>,
+	) -> SessionFlags {
+		if !self.get_profile().is_response_forwarded() {
+			if let Some(location) = location {
+				builder.header(header, location);
+			}
+			return flags | SessionFlags::X_AUTH_HEADERS;
+		}
+		if flags.contains(SessionFlags::FORWARD_AUTH_REDIRECT) {
+			if let Some(location) = location {
+				builder.status(StatusCode::FOUND);
+				builder.header("location", location);
+			}
+		}
+		flags | SessionFlags::COOKIES
 	}
 	///
 	/// Save the userinfo
@@ -446,16 +753,45 @@ impl Session {
 	) -> Result<(), Error> {
 		if let Some(ref userinfo) = userinfo {
 			if flags.contains(SessionFlags::X_AUTH_HEADERS) {
-				let userinfo_encoded = self.data.jwt.encode_str(&userinfo.data)?;
+				let claims = self.data.claims.apply(&userinfo.data);
+				let userinfo_encoded = self.data.jwt.encode_str(&claims)?;
+				self.data.claims.check_size(userinfo_encoded.len());
 				builder.header("x-auth-userinfo", userinfo_encoded);
 				if let Some(ref data) = self.data.settings.data {
 					builder.header("x-auth-data", data.clone());
 				};
+				self.response_relay_token(builder);
 			}
 		}
 		Ok(())
 	}
 	///
+	/// Relay the access token to the upstream when configured
+	///
+	/// Uses the token set of the session, so a token refreshed on this request is relayed.
+	/// An exchanged token is always relayed, using the authorization header by default.
+	///
+	fn response_relay_token(&self, builder: &mut HttpResponseBuilder) {
+		let relay_token = self.data.settings.relay_token.as_deref();
+		let (header, token) = if let Some(ref exchanged_token) = self.exchanged_token {
+			(relay_token.unwrap_or("authorization"), exchanged_token)
+		} else {
+			let access_token = self
+				.token_set
+				.as_ref()
+				.and_then(|t| t.access_token.as_ref());
+			match (relay_token, access_token) {
+				(Some(header), Some(access_token)) => (header, access_token),
+				_ => return,
+			}
+		};
+		if header.eq_ignore_ascii_case("authorization") {
+			builder.header(header, format!("Bearer {}", token));
+		} else {
+			builder.header(header, token.clone());
+		}
+	}
+	///
 	/// Save the session
 	///
 	/// When the session doesn't have a session token
@@ -482,12 +818,21 @@ impl Session {
 		}
 
 		let token_set = token_set.unwrap();
+		let provider = &token_set.provider;
 		let cookie_access_token_name = self.data.settings.cookie.access_token_name.clone();
-		let cookie_access_token =
-			self.create_cookie(cookie_access_token_name, token_set.access_token)?;
+		let cookie_access_token = self.create_cookie(
+			cookie_access_token_name,
+			token_set
+				.access_token
+				.map(|t| Self::token_with_provider(provider, &t)),
+		)?;
 		let cookie_refresh_token_name = self.data.settings.cookie.refresh_token_name.clone();
-		let cookie_refresh_token =
-			self.create_cookie(cookie_refresh_token_name, token_set.refresh_token)?;
+		let cookie_refresh_token = self.create_cookie(
+			cookie_refresh_token_name,
+			token_set
+				.refresh_token
+				.map(|t| Self::token_with_provider(provider, &t)),
+		)?;
 		cookies.push(cookie_access_token);
 		cookies.push(cookie_refresh_token);
 		Ok(())
@@ -509,6 +854,9 @@ impl Session {
 		let mut builder = cookie::Cookie::build(name, cookie_value)
 			.path("/")
 			.http_only(true);
+		if let Some(ref domain) = self.data.settings.cookie.domain {
+			builder = builder.domain(domain.clone());
+		}
 		if value.is_none() {
 			builder = builder.expires(time::OffsetDateTime::from_unix_timestamp(0));
 		}
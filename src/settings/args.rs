@@ -13,7 +13,7 @@ const OPTS: &[(&str, &str, &str, &str)] = &[
 	(
 		"listen",
 		"listen",
-		"Listen for the server on the given URLs (comma delimited for multiple)",
+		"Listen for the server on the given URLs (comma delimited for multiple). Ex: 'http://0.0.0.0:8088,https://0.0.0.0:8443'",
 		"URLS",
 	),
 	(
@@ -28,12 +28,96 @@ const OPTS: &[(&str, &str, &str, &str)] = &[
 		"Use SECRET to encode the JWT used by x-auth headers",
 		"SECRET",
 	),
+	(
+		"jwt-key",
+		"jwt_key",
+		"Comma separated PEM FILES to sign the JWT used by x-auth headers. The first must be the private key",
+		"FILES",
+	),
+	(
+		"jwt-issuer",
+		"jwt_issuer",
+		"ISSUER (iss claim) of the JWT used by x-auth headers",
+		"ISSUER",
+	),
+	(
+		"jwt-audience",
+		"jwt_audience",
+		"AUDIENCE (aud claim) of the JWT used by x-auth headers",
+		"AUDIENCE",
+	),
+	(
+		"jwt-expires-in",
+		"jwt_expires_in",
+		"SECONDS until the JWT used by x-auth headers expires (Default: 60)",
+		"SECONDS",
+	),
 	(
 		"data",
 		"data",
 		"Arbitrary DATA to pass directly to x-auth-data header",
 		"DATA",
 	),
+	(
+		"forbidden-url",
+		"forbidden_url",
+		"Url to send the logged user without permission when using forward-auth redirect",
+		"URL",
+	),
+	(
+		"relay-token",
+		"relay_token",
+		"Relay the access token to the upstream using the HEADER. Ex: 'authorization' sends 'Bearer <access_token>'",
+		"HEADER",
+	),
+	(
+		"token-exchange",
+		"token_exchange",
+		"Exchange the access token for the audiences (comma delimited) and relay it. Ex: 'api;scope=read;path=/api/'",
+		"TARGETS",
+	),
+	(
+		"trusted-proxies",
+		"trusted_proxies",
+		"Only trust the forwarded headers and the forward auth requests of these NETWORKS (comma delimited CIDR)",
+		"NETWORKS",
+	),
+	(
+		"claims-include",
+		"claims_include",
+		"Only send these CLAIMS (comma delimited) in the x-auth-userinfo header",
+		"CLAIMS",
+	),
+	(
+		"claims-exclude",
+		"claims_exclude",
+		"Remove these CLAIMS (comma delimited) from the x-auth-userinfo header",
+		"CLAIMS",
+	),
+	(
+		"claims-rename",
+		"claims_rename",
+		"Rename the claims of the x-auth-userinfo header. Ex: 'preferred_username=username'",
+		"RENAMES",
+	),
+	(
+		"basic-auth",
+		"basic_auth",
+		"Accept 'Authorization: Basic' on forward auth, using the password grant. Ex: 'true'",
+		"ENABLED",
+	),
+	(
+		"basic-auth-realm",
+		"basic_auth_realm",
+		"REALM of the WWW-Authenticate challenge (Default: auth-gatekeeper)",
+		"REALM",
+	),
+	(
+		"basic-auth-cache-ttl",
+		"basic_auth_cache_ttl",
+		"SECONDS to cache a successful basic authentication (Default: 30)",
+		"SECONDS",
+	),
 	(
 		"api-id-token-endpoint",
 		"api.id_token_endpoint",
@@ -52,12 +136,36 @@ const OPTS: &[(&str, &str, &str, &str)] = &[
 		"The name of the cookie used to store the refresh token",
 		"NAME",
 	),
+	(
+		"cookie-domain",
+		"cookie.domain",
+		"DOMAIN of the cookies, to share them with the subdomains. Ex: '.example.com'",
+		"DOMAIN",
+	),
 	(
 		"provider",
 		"provider.provider",
-		"The provider to use. 'oidc' or 'keycloak'",
+		"The provider to use. 'oidc', 'keycloak', 'fusionauth', 'oauth2', 'github', 'google', 'azure', 'ldap', 'static' or 'saml'",
 		"PROVIDER",
 	),
+	(
+		"providers",
+		"providers",
+		"Use the named PROVIDERS (comma delimited). The first one is the default. Ex: 'employees,contractors'",
+		"PROVIDERS",
+	),
+	(
+		"hosts",
+		"hosts",
+		"Use the named virtual HOSTS (comma delimited), with their own providers and cookies",
+		"HOSTS",
+	),
+	(
+		"allowed-redirects",
+		"allowed_redirects",
+		"Only redirect to the URLS after the login (comma delimited). Ex: 'https://app.example.com'",
+		"URLS",
+	),
 	(
 		"provider-client-id",
 		"provider.client_id",
@@ -70,6 +178,12 @@ const OPTS: &[(&str, &str, &str, &str)] = &[
 		"Client Secret of the provider",
 		"CLIENT_SECRET",
 	),
+	(
+		"provider-scope",
+		"provider.scope",
+		"SCOPE to request (Default: 'openid email profile offline_access', empty for oauth2)",
+		"SCOPE",
+	),
 	(
 		"provider-auth-url",
 		"provider.auth_url",
@@ -82,12 +196,120 @@ const OPTS: &[(&str, &str, &str, &str)] = &[
 		"Url of the token endpoint",
 		"URL",
 	),
+	(
+		"provider-device-auth-url",
+		"provider.device_auth_url",
+		"Url of the device authorization endpoint, for the CLIs. Defaults to the one of the github, google or azure provider",
+		"URL",
+	),
 	(
 		"provider-userinfo-url",
 		"provider.userinfo_url",
 		"Url to get the user info using the access token",
 		"URL",
 	),
+	(
+		"provider-userinfo-requests",
+		"provider.userinfo_requests",
+		"Other requests (comma delimited NAME=URL) added to the userinfo of the oauth2 provider. Ex: 'emails=https://api.github.com/user/emails'",
+		"REQUESTS",
+	),
+	(
+		"provider-claims",
+		"provider.claims",
+		"Map the userinfo of the oauth2 provider to the CLAIMS. Ex: 'sub=id,email=email|emails[primary=true].email,name=name|login'",
+		"CLAIMS",
+	),
+	(
+		"provider-api-url",
+		"provider.api_url",
+		"Url of the GitHub api (Default: https://api.github.com). Ex: 'https://github.example.com/api/v3'",
+		"URL",
+	),
+	(
+		"provider-allowed-orgs",
+		"provider.allowed_orgs",
+		"Only allow the members of the GitHub ORGS (comma delimited)",
+		"ORGS",
+	),
+	(
+		"provider-allowed-teams",
+		"provider.allowed_teams",
+		"Only allow the members of the GitHub TEAMS (comma delimited). Ex: 'org/team'",
+		"TEAMS",
+	),
+	(
+		"provider-allowed-domains",
+		"provider.allowed_domains",
+		"Only allow the users of the Google Workspace DOMAINS (comma delimited)",
+		"DOMAINS",
+	),
+	(
+		"provider-jwks-url",
+		"provider.jwks_url",
+		"Url of the keys to verify the id_tokens (Defaults to the provider)",
+		"URL",
+	),
+	(
+		"provider-issuer",
+		"provider.issuer",
+		"ISSUER of the id_tokens, or the entity id of the SAML IdP (Defaults to the provider)",
+		"ISSUER",
+	),
+	(
+		"provider-tenant",
+		"provider.tenant",
+		"TENANT of the Azure provider. Ex: the tenant id, 'example.onmicrosoft.com' or 'organizations'",
+		"TENANT",
+	),
+	(
+		"provider-ldap-url",
+		"provider.ldap_url",
+		"Url of the LDAP server. Ex: 'ldaps://ldap.example.com'",
+		"URL",
+	),
+	(
+		"provider-ldap-bind-dn",
+		"provider.ldap_bind_dn",
+		"DN to bind before searching the users (Default: anonymous)",
+		"DN",
+	),
+	(
+		"provider-ldap-bind-password",
+		"provider.ldap_bind_password",
+		"PASSWORD of the bind DN",
+		"PASSWORD",
+	),
+	(
+		"provider-ldap-base-dn",
+		"provider.ldap_base_dn",
+		"DN to search the users. Ex: 'ou=people,dc=example,dc=com'",
+		"DN",
+	),
+	(
+		"provider-ldap-user-filter",
+		"provider.ldap_user_filter",
+		"FILTER to search the user (Default: '(uid={username})'). Ex: '(sAMAccountName={username})'",
+		"FILTER",
+	),
+	(
+		"provider-users-file",
+		"provider.users_file",
+		"FILE with the users of the static provider, like htpasswd (bcrypt or argon2)",
+		"FILE",
+	),
+	(
+		"provider-saml-idp-certificate",
+		"provider.saml_idp_certificate",
+		"FILE with the PEM certificates signing the responses of the SAML IdP",
+		"FILE",
+	),
+	(
+		"provider-session-ttl",
+		"provider.session_ttl",
+		"SECONDS of the sessions issued by the gatekeeper, for the ldap, static and saml providers (Default: 28800)",
+		"SECONDS",
+	),
 	(
 		"provider-end-session-url",
 		"provider.end_session_url",
@@ -106,6 +328,66 @@ const OPTS: &[(&str, &str, &str, &str)] = &[
 		"Url to send the user back when auth is complete",
 		"URL",
 	),
+	(
+		"proxy-profile",
+		"proxy.profile",
+		"The proxy calling forward-auth. 'traefik', 'caddy', 'nginx', 'haproxy-spoe-lite', 'envoy' or 'generic'",
+		"PROFILE",
+	),
+	(
+		"proxy-upstreams",
+		"proxy.upstreams",
+		"Proxy the requests to the upstreams (comma delimited). Ex: 'example.com/app=http://app:8080;redirect,/api=http://api'",
+		"UPSTREAMS",
+	),
+	(
+		"tls-cert",
+		"tls.cert",
+		"Certificate chain FILE (PEM) for the https listeners",
+		"FILE",
+	),
+	(
+		"tls-key",
+		"tls.key",
+		"Private key FILE (PEM) for the https listeners",
+		"FILE",
+	),
+	(
+		"tls-client-ca",
+		"tls.client_ca",
+		"CA FILE (PEM) to verify the client certificates",
+		"FILE",
+	),
+	(
+		"tls-client-auth",
+		"tls.client_auth",
+		"Verify the client certificates: none (Default), optional or required",
+		"MODE",
+	),
+	(
+		"client-cert-claims",
+		"client_cert.claims",
+		"Map the client certificate fields to the userinfo CLAIMS. Default: 'sub=subject.CN'",
+		"CLAIMS",
+	),
+	(
+		"client-cert-header",
+		"client_cert.header",
+		"HEADER with the client certificate sent by a trusted proxy. Ex: 'x-forwarded-client-cert'",
+		"HEADER",
+	),
+	(
+		"api-keys-file",
+		"api_keys.file",
+		"FILE with the api keys. Managed with the api-key command",
+		"FILE",
+	),
+	(
+		"api-keys-header",
+		"api_keys.header",
+		"HEADER with the api key, instead of 'Authorization: ApiKey KEY'",
+		"HEADER",
+	),
 ];
 
 #[derive(Clone, Debug)]
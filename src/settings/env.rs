@@ -3,7 +3,7 @@ use std::collections::HashMap;
 
 pub struct EnvironmentConfigOptions<'a> {
 	prefix: &'a str,
-	submatchers: &'a [&'static str],
+	submatchers: &'a [&'a str],
 }
 
 #[derive(Clone, Debug)]
@@ -12,7 +12,7 @@ pub struct EnvironmentConfig {
 }
 
 impl EnvironmentConfig {
-	pub fn with_prefix(prefix: &str, submatchers: &[&'static str]) -> Self {
+	pub fn with_prefix(prefix: &str, submatchers: &[&str]) -> Self {
 		let options = EnvironmentConfigOptions {
 			prefix: prefix,
 			submatchers: submatchers,
@@ -5,6 +5,19 @@ use crate::error::Error;
 use args::ArgsConfig;
 use env::EnvironmentConfig;
 use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Prefixes of the env variables for the nested settings
+// api_keys must come before api, which is its prefix
+const ENV_SUBMATCHERS: &[&str] = &[
+	"cookie",
+	"provider",
+	"api_keys",
+	"api",
+	"proxy",
+	"tls",
+	"client_cert",
+];
 
 ///
 /// Settings
@@ -14,10 +27,36 @@ pub struct Settings {
 	pub listen: String,
 	pub secret: String,
 	pub jwt_secret: Option<String>,
+	pub jwt_key: Option<String>,
+	pub jwt_issuer: Option<String>,
+	pub jwt_audience: Option<String>,
+	pub jwt_expires_in: u64,
 	pub data: Option<String>,
+	pub forbidden_url: Option<String>,
+	pub relay_token: Option<String>,
+	pub token_exchange: Option<String>,
+	pub trusted_proxies: Option<String>,
+	pub claims_include: Option<String>,
+	pub claims_exclude: Option<String>,
+	pub claims_rename: Option<String>,
+	pub basic_auth: bool,
+	pub basic_auth_realm: String,
+	pub basic_auth_cache_ttl: u64,
 	pub api: SettingsApi,
 	pub cookie: SettingsCookie,
 	pub provider: SettingsProvider,
+	pub providers: Option<String>,
+	#[serde(default)]
+	pub named_providers: HashMap<String, SettingsProvider>,
+	pub hosts: Option<String>,
+	#[serde(default)]
+	pub named_hosts: HashMap<String, SettingsHost>,
+	pub allowed_redirects: Option<String>,
+	pub proxy: SettingsProxy,
+	pub tls: SettingsTls,
+	pub client_cert: SettingsClientCert,
+	#[serde(default)]
+	pub api_keys: SettingsApiKeys,
 }
 
 ///
@@ -36,6 +75,7 @@ pub struct SettingsApi {
 pub struct SettingsCookie {
 	pub access_token_name: String,
 	pub refresh_token_name: String,
+	pub domain: Option<String>,
 }
 
 ///
@@ -47,14 +87,83 @@ pub struct SettingsProvider {
 	pub client_id: String,
 	pub client_secret: String,
 	pub scope: Option<String>,
-	pub auth_url: String,
-	pub token_url: String,
-	pub userinfo_url: String,
+	pub auth_url: Option<String>,
+	pub token_url: Option<String>,
+	pub device_auth_url: Option<String>,
+	pub userinfo_url: Option<String>,
+	pub userinfo_requests: Option<String>,
+	pub claims: Option<String>,
+	pub api_url: Option<String>,
+	pub allowed_orgs: Option<String>,
+	pub allowed_teams: Option<String>,
+	pub allowed_domains: Option<String>,
+	pub jwks_url: Option<String>,
+	pub issuer: Option<String>,
+	pub tenant: Option<String>,
+	pub ldap_url: Option<String>,
+	pub ldap_bind_dn: Option<String>,
+	pub ldap_bind_password: Option<String>,
+	pub ldap_base_dn: Option<String>,
+	pub ldap_user_filter: Option<String>,
+	pub users_file: Option<String>,
+	pub saml_idp_certificate: Option<String>,
+	pub session_ttl: Option<u64>,
 	pub end_session_url: Option<String>,
 	pub callback_url: String,
 	pub logout_redirect_url: String,
 }
 
+///
+/// Settings of a virtual host, replacing the ones of the gatekeeper for its hosts
+///
+#[derive(Clone, Debug, Deserialize)]
+pub struct SettingsHost {
+	pub host: String,
+	pub providers: Option<String>,
+	pub callback_url: Option<String>,
+	pub cookie_prefix: Option<String>,
+	pub cookie_domain: Option<String>,
+	pub allowed_redirects: Option<String>,
+}
+
+///
+/// Settings for the proxy in front of the gatekeeper, or the built-in proxy
+///
+#[derive(Clone, Debug, Deserialize)]
+pub struct SettingsProxy {
+	pub profile: String,
+	pub upstreams: Option<String>,
+}
+
+///
+/// Certificates for the https listeners
+///
+#[derive(Clone, Debug, Deserialize)]
+pub struct SettingsTls {
+	pub cert: Option<String>,
+	pub key: Option<String>,
+	pub client_ca: Option<String>,
+	pub client_auth: String,
+}
+
+///
+/// Authentication using client certificates
+///
+#[derive(Clone, Debug, Deserialize)]
+pub struct SettingsClientCert {
+	pub claims: String,
+	pub header: Option<String>,
+}
+
+///
+/// Api keys for the service accounts
+///
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct SettingsApiKeys {
+	pub file: Option<String>,
+	pub header: Option<String>,
+}
+
 impl Settings {
 	pub fn new(rand: &dyn ring::rand::SecureRandom) -> Self {
 		match Self::new_impl(rand) {
@@ -73,9 +182,21 @@ impl Settings {
 	fn new_impl(rand: &dyn ring::rand::SecureRandom) -> Result<Self, Error> {
 		let mut s = config::Config::new();
 		s.set_default("listen", "http://127.0.0.1:8088")?;
+		s.set_default("jwt_expires_in", 60)?;
+		s.set_default("basic_auth", false)?;
+		s.set_default("basic_auth_realm", "auth-gatekeeper")?;
+		s.set_default("basic_auth_cache_ttl", 30)?;
+		s.set_default("proxy.profile", "traefik")?;
+		s.set_default("tls.client_auth", "none")?;
+		s.set_default("client_cert.claims", "sub=subject.CN")?;
 		s.set_default("cookie.access_token_name", "sat")?;
 		s.set_default("cookie.refresh_token_name", "srt")?;
 		s.set_default("provider.provider", "oidc")?;
+		// Providers without an authorization server (Ex: ldap) do not need a client
+		s.set_default("provider.client_id", "")?;
+		s.set_default("provider.client_secret", "")?;
+		s.set_default("provider.callback_url", "")?;
+		s.set_default("provider.logout_redirect_url", "")?;
 		s.set_default("api.id_token_endpoint", "")?;
 
 		// Use args
@@ -83,10 +204,47 @@ impl Settings {
 
 		// Check if needs to parse using the env
 		if let Ok(prefix) = s.get_str("config.env") {
-			s.merge(EnvironmentConfig::with_prefix(
-				&prefix,
-				&["cookie", "provider", "api"],
-			))?;
+			s.merge(EnvironmentConfig::with_prefix(&prefix, ENV_SUBMATCHERS))?;
+			// The settings of the named providers and hosts are prefixed by their names, once
+			// they are known
+			let prefixes: Vec<String> = Self::names(&s, "providers")?
+				.iter()
+				.map(|n| format!("providers_{}", n))
+				.chain(
+					Self::names(&s, "hosts")?
+						.iter()
+						.map(|n| format!("hosts_{}", n)),
+				)
+				.collect();
+			if !prefixes.is_empty() {
+				let mut submatchers: Vec<&str> = prefixes.iter().map(|p| p.as_str()).collect();
+				submatchers.extend(ENV_SUBMATCHERS);
+				s.merge(EnvironmentConfig::with_prefix(&prefix, &submatchers))?;
+			}
+		}
+
+		// The named providers use the provider settings, unless they are set for the name.
+		// The first one is the default provider
+		let names = Self::names(&s, "providers")?;
+		let base = s.get_table("provider")?;
+		for (index, name) in names.iter().enumerate() {
+			let mut table = base.clone();
+			table.extend(
+				s.get_table(&format!("providers_{}", name))
+					.unwrap_or_default(),
+			);
+			for (key, value) in table {
+				if index == 0 {
+					s.set(&format!("provider.{}", key), value.clone())?;
+				}
+				s.set(&format!("named_providers.{}.{}", name, key), value)?;
+			}
+		}
+		for name in Self::names(&s, "hosts")? {
+			let table = s.get_table(&format!("hosts_{}", name)).unwrap_or_default();
+			for (key, value) in table {
+				s.set(&format!("named_hosts.{}.{}", name, key), value)?;
+			}
 		}
 
 		// If no secret is provided, use a random one
@@ -96,6 +254,27 @@ impl Settings {
 		let settings: Self = s.try_into()?;
 		Ok(settings)
 	}
+
+	///
+	/// Get the comma delimited names of the providers or the hosts. They are part of the env
+	/// variables, so only letters and digits are allowed
+	///
+	fn names(s: &config::Config, key: &str) -> Result<Vec<String>, Error> {
+		let names: Vec<String> = s
+			.get_str(key)
+			.unwrap_or_default()
+			.split(',')
+			.map(|n| n.trim().to_lowercase())
+			.filter(|n| !n.is_empty())
+			.collect();
+		if names
+			.iter()
+			.any(|n| !n.chars().all(|c| c.is_ascii_alphanumeric()))
+		{
+			return Err(Error::SettingsError("Invalid name of a provider or a host"));
+		}
+		Ok(names)
+	}
 }
 
 ///
@@ -0,0 +1,148 @@
+use crate::error::Error;
+use crate::util::jwt::JsonValue;
+use jsonwebtoken::{Algorithm, EncodingKey};
+use openssl::bn::{BigNumContext, BigNumRef};
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey, Private, Public};
+use serde_json::json;
+
+///
+/// A key loaded from a PEM file, with its public part as a JWK (RFC 7517)
+///
+pub struct Jwk {
+	pub kid: String,
+	pub algorithm: Algorithm,
+	pub encoding_key: Option<EncodingKey>,
+	pub public: JsonValue,
+}
+
+impl Jwk {
+	///
+	/// Load a private or a public key from a PEM
+	///
+	/// Only private keys have the `encoding_key` to sign the tokens. The algorithm is
+	/// chosen by the key type: RS256 for RSA, ES256/ES384 for EC and EdDSA for Ed25519.
+	///
+	pub fn from_pem(pem: &[u8]) -> Result<Self, Error> {
+		match PKey::private_key_from_pem(pem) {
+			Ok(private_key) => {
+				let public_key = PKey::public_key_from_der(&private_key.public_key_to_der()?)?;
+				let mut jwk = Self::from_public_key(&public_key)?;
+				jwk.encoding_key = Some(Self::get_encoding_key(&private_key, jwk.algorithm)?);
+				Ok(jwk)
+			}
+			Err(_) => Self::from_public_key(&PKey::public_key_from_pem(pem)?),
+		}
+	}
+	///
+	/// Create the JWK using the public key
+	///
+	fn from_public_key(key: &PKey<Public>) -> Result<Self, Error> {
+		let (algorithm, params) = match key.id() {
+			Id::RSA => {
+				let rsa = key.rsa()?;
+				let params = json!({
+					"e": Self::encode_bn(rsa.e(), 0)?,
+					"kty": "RSA",
+					"n": Self::encode_bn(rsa.n(), 0)?,
+				});
+				(Algorithm::RS256, params)
+			}
+			Id::EC => {
+				let ec = key.ec_key()?;
+				let (algorithm, crv, size) = match ec.group().curve_name() {
+					Some(Nid::X9_62_PRIME256V1) => (Algorithm::ES256, "P-256", 32),
+					Some(Nid::SECP384R1) => (Algorithm::ES384, "P-384", 48),
+					_ => return Err(Error::SettingsError("Unsupported JWT key curve")),
+				};
+				let mut context = BigNumContext::new()?;
+				let mut x = openssl::bn::BigNum::new()?;
+				let mut y = openssl::bn::BigNum::new()?;
+				ec.public_key()
+					.affine_coordinates(ec.group(), &mut x, &mut y, &mut context)?;
+				let params = json!({
+					"crv": crv,
+					"kty": "EC",
+					"x": Self::encode_bn(&x, size)?,
+					"y": Self::encode_bn(&y, size)?,
+				});
+				(algorithm, params)
+			}
+			Id::ED25519 => {
+				let params = json!({
+					"crv": "Ed25519",
+					"kty": "OKP",
+					"x": base64::encode_config(key.raw_public_key()?, base64::URL_SAFE_NO_PAD),
+				});
+				(Algorithm::EdDSA, params)
+			}
+			_ => return Err(Error::SettingsError("Unsupported JWT key type")),
+		};
+		let kid = Self::thumbprint(&params)?;
+
+		let mut public = params;
+		public["kid"] = json!(kid);
+		public["use"] = json!("sig");
+		public["alg"] = serde_json::to_value(algorithm)?;
+		Ok(Self {
+			kid,
+			algorithm,
+			encoding_key: None,
+			public,
+		})
+	}
+	///
+	/// The private key is converted to PKCS#8, which is accepted for every key type
+	///
+	fn get_encoding_key(key: &PKey<Private>, algorithm: Algorithm) -> Result<EncodingKey, Error> {
+		let pem = key.private_key_to_pem_pkcs8()?;
+		let encoding_key = match algorithm {
+			Algorithm::RS256 => EncodingKey::from_rsa_pem(&pem)?,
+			Algorithm::EdDSA => EncodingKey::from_ed_pem(&pem)?,
+			_ => EncodingKey::from_ec_pem(&pem)?,
+		};
+		Ok(encoding_key)
+	}
+	///
+	/// Encode a big number, left padded to `size` bytes
+	///
+	fn encode_bn(value: &BigNumRef, size: i32) -> Result<String, Error> {
+		let bytes = if size > 0 {
+			value.to_vec_padded(size)?
+		} else {
+			value.to_vec()
+		};
+		Ok(base64::encode_config(bytes, base64::URL_SAFE_NO_PAD))
+	}
+	///
+	/// JWK thumbprint (RFC 7638), used as the key id
+	///
+	/// The required members are already sorted, and serde_json keeps them sorted
+	///
+	fn thumbprint(params: &JsonValue) -> Result<String, Error> {
+		let canonical = serde_json::to_string(params)?;
+		let digest = ring::digest::digest(&ring::digest::SHA256, canonical.as_bytes());
+		Ok(base64::encode_config(
+			digest.as_ref(),
+			base64::URL_SAFE_NO_PAD,
+		))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[test]
+	fn test_thumbprint() {
+		// Example from RFC 7638, section 3.1
+		let params = json!({
+			"e": "AQAB",
+			"kty": "RSA",
+			"n": "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw",
+		});
+		assert_eq!(
+			"NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs",
+			Jwk::thumbprint(&params).unwrap()
+		);
+	}
+}
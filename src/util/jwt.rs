@@ -1,12 +1,164 @@
 use crate::error::Error;
+use crate::settings::Settings;
+use crate::util::crypto::RandomPtr;
+use crate::util::jwk::Jwk;
+use actix_rt::signal::unix::{signal, SignalKind};
 use jsonwebtoken::{EncodingKey, Header};
 use serde::ser::Serialize;
+use serde_json::json;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
 
 pub type JsonValue = serde_json::Value;
 
+/// How often the key files are checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(30);
+
+///
+/// The signing key and the public keys of the PEM files
+///
+struct LoadedKeys {
+	header: Header,
+	encoding_key: EncodingKey,
+	jwks: JsonValue,
+}
+
+///
+/// The keys in the PEM files, reloaded on SIGHUP or when the files change
+///
+pub struct JwtKeys {
+	files: String,
+	loaded: RwLock<LoadedKeys>,
+	modified: Mutex<Vec<Option<SystemTime>>>,
+}
+
+pub type JwtKeysPtr = Arc<JwtKeys>;
+
+///
+/// Registered claims added to the signed tokens
+///
+#[derive(Clone)]
+struct StandardClaims {
+	issuer: Option<String>,
+	audience: Option<String>,
+	expires_in: u64,
+	random: RandomPtr,
+}
+
 #[derive(Clone)]
 pub struct JWT {
+	header: Header,
 	encoding_key: Option<EncodingKey>,
+	jwks: JsonValue,
+	keys: Option<JwtKeysPtr>,
+	claims: Option<StandardClaims>,
+}
+
+impl JwtKeys {
+	///
+	/// Load the keys in the comma delimited PEM files
+	///
+	pub fn create(files: &str) -> Result<JwtKeysPtr, Error> {
+		let keys = Self {
+			files: files.to_owned(),
+			loaded: RwLock::new(Self::load(files)?),
+			modified: Mutex::new(Self::get_modified(files)),
+		};
+		Ok(Arc::new(keys))
+	}
+	///
+	/// Reload the keys on SIGHUP, or when the files change
+	///
+	pub fn watch(keys: &JwtKeysPtr) {
+		let signal_keys = keys.clone();
+		actix_rt::spawn(async move {
+			let mut hangup = match signal(SignalKind::hangup()) {
+				Ok(hangup) => hangup,
+				Err(error) => {
+					log::error!("Could not listen to SIGHUP: {}", error);
+					return;
+				}
+			};
+			while hangup.recv().await.is_some() {
+				log::info!("SIGHUP received, reloading the JWT keys");
+				signal_keys.reload();
+			}
+		});
+		let watch_keys = keys.clone();
+		actix_rt::spawn(async move {
+			let mut interval = actix_rt::time::interval(WATCH_INTERVAL);
+			loop {
+				interval.tick().await;
+				let modified = Self::get_modified(&watch_keys.files);
+				if *watch_keys.modified.lock().unwrap() != modified {
+					log::info!("JWT key files changed, reloading");
+					watch_keys.reload();
+				}
+			}
+		});
+	}
+	///
+	/// Reload the keys. On errors the current ones are kept
+	///
+	fn reload(&self) {
+		let modified = Self::get_modified(&self.files);
+		match Self::load(&self.files) {
+			Ok(loaded) => {
+				*self.loaded.write().unwrap() = loaded;
+				log::info!("JWT keys reloaded");
+			}
+			Err(error) => log::error!("Error reloading the JWT keys: {:?}", error),
+		}
+		*self.modified.lock().unwrap() = modified;
+	}
+	///
+	/// Load the keys in the PEM files
+	///
+	/// The first file must have the private key used to sign. The other keys are only
+	/// published in the JWKS, so the backends still accept the tokens signed with them
+	/// while the keys are rotated.
+	///
+	fn load(files: &str) -> Result<LoadedKeys, Error> {
+		let mut keys = Vec::new();
+		for file in Self::split_files(files) {
+			let pem = std::fs::read(file).map_err(|error| {
+				log::error!("Could not read the JWT key {}: {}", file, error);
+				Error::SettingsError("Could not read the JWT key")
+			})?;
+			keys.push(Jwk::from_pem(&pem)?);
+		}
+		let signing_key = keys.first().ok_or(Error::SettingsError("No JWT key"))?;
+		let encoding_key = signing_key
+			.encoding_key
+			.clone()
+			.ok_or(Error::SettingsError(
+				"The first JWT key must be a private key",
+			))?;
+
+		let mut header = Header::new(signing_key.algorithm);
+		header.kid = Some(signing_key.kid.clone());
+		let public_keys: Vec<&JsonValue> = keys.iter().map(|k| &k.public).collect();
+		Ok(LoadedKeys {
+			header,
+			encoding_key,
+			jwks: json!({ "keys": public_keys }),
+		})
+	}
+	///
+	/// Get the modification time of the files
+	///
+	fn get_modified(files: &str) -> Vec<Option<SystemTime>> {
+		Self::split_files(files)
+			.map(|file| {
+				std::fs::metadata(file)
+					.ok()
+					.and_then(|metadata| metadata.modified().ok())
+			})
+			.collect()
+	}
+	fn split_files(files: &str) -> impl Iterator<Item = &str> {
+		files.split(',').map(|f| f.trim()).filter(|f| !f.is_empty())
+	}
 }
 
 ///
@@ -24,21 +176,67 @@ impl JWT {
 			encoding_key = Some(jsonwebtoken::EncodingKey::from_secret(&secret_str.as_ref()));
 		}
 		Ok(Self {
+			header: Header::default(),
 			encoding_key: encoding_key,
+			jwks: json!({ "keys": [] }),
+			keys: None,
+			claims: None,
 		})
 	}
 	///
+	/// Construct using the keys of the PEM files, shared with the other workers
+	///
+	pub fn from_keys(keys: JwtKeysPtr) -> Self {
+		Self {
+			header: Header::default(),
+			encoding_key: None,
+			jwks: json!({ "keys": [] }),
+			keys: Some(keys),
+			claims: None,
+		}
+	}
+	///
+	/// Construct using the keys of the `jwt_key` setting, or the secret
+	///
+	/// The signed tokens get the iss, aud, iat, exp and jti claims, so they can not be
+	/// replayed after they expire.
+	///
+	pub fn from_settings(
+		settings: &Settings,
+		random: RandomPtr,
+		keys: Option<JwtKeysPtr>,
+	) -> Result<Self, Error> {
+		let mut jwt = match keys {
+			Some(keys) => Self::from_keys(keys),
+			None => Self::new(settings.jwt_secret.clone())?,
+		};
+		jwt.claims = Some(StandardClaims {
+			issuer: settings.jwt_issuer.clone(),
+			audience: settings.jwt_audience.clone(),
+			expires_in: settings.jwt_expires_in,
+			random,
+		});
+		Ok(jwt)
+	}
+	///
+	/// The public keys, as a JWK Set
+	///
+	pub fn jwks(&self) -> JsonValue {
+		match self.keys {
+			Some(ref keys) => keys.loaded.read().unwrap().jwks.clone(),
+			None => self.jwks.clone(),
+		}
+	}
+	///
 	/// Encode a json value
 	///
 	pub fn encode_value<T>(&self, value: &T) -> Result<JsonValue, Error>
 	where
 		T: Serialize,
 	{
-		if let Some(ref encoding_key) = self.encoding_key {
-			let encoded = jsonwebtoken::encode(&Header::default(), value, &encoding_key)?;
-			Ok(serde_json::to_value(encoded)?)
-		} else {
-			Ok(serde_json::to_value(value.clone())?)
+		match self.sign(value)? {
+			Some(encoded) => Ok(serde_json::to_value(encoded)?),
+			None => Ok(serde_json::to_value(value)?),
 		}
 	}
 	///
@@ -48,13 +246,73 @@ impl JWT {
 	where
 		T: Serialize,
 	{
-		if let Some(ref encoding_key) = self.encoding_key {
-			let encoded = jsonwebtoken::encode(&Header::default(), value, &encoding_key)?;
-			Ok(encoded)
-		} else {
-			Ok(serde_json::to_string(value)?)
+		match self.sign(value)? {
+			Some(encoded) => Ok(encoded),
+			None => Ok(serde_json::to_string(value)?),
 		}
 	}
+	///
+	/// Sign the value with the claims, using the current key. None without a key or a secret
+	///
+	fn sign<T>(&self, value: &T) -> Result<Option<String>, Error>
+	where
+		T: Serialize,
+	{
+		if let Some(ref keys) = self.keys {
+			let claims = self.get_claims(value)?;
+			let loaded = keys.loaded.read().unwrap();
+			let encoded = jsonwebtoken::encode(&loaded.header, &claims, &loaded.encoding_key)?;
+			return Ok(Some(encoded));
+		}
+		match self.encoding_key {
+			Some(ref encoding_key) => {
+				let claims = self.get_claims(value)?;
+				Ok(Some(jsonwebtoken::encode(
+					&self.header,
+					&claims,
+					encoding_key,
+				)?))
+			}
+			None => Ok(None),
+		}
+	}
+	///
+	/// Wrap the value with the standard claims. The value is kept in `data`, so its own claims
+	/// (Ex: the `exp` or the `aud` of the provider) are not taken for the ones of the gatekeeper
+	///
+	fn get_claims<T>(&self, value: &T) -> Result<JsonValue, Error>
+	where
+		T: Serialize,
+	{
+		let value = serde_json::to_value(value)?;
+		let standard = match self.claims {
+			Some(ref standard) => standard,
+			None => return Ok(value),
+		};
+		let now = SystemTime::now()
+			.duration_since(SystemTime::UNIX_EPOCH)
+			.map_err(|_| Error::CryptoError)?
+			.as_secs();
+		let mut jti = [0u8; 16];
+		standard
+			.random
+			.fill(&mut jti)
+			.map_err(|_| Error::CryptoRandomBytesError)?;
+
+		let mut claims = json!({
+			"iat": now,
+			"exp": now + standard.expires_in,
+			"jti": base64::encode_config(jti, base64::URL_SAFE_NO_PAD),
+			"data": value,
+		});
+		if let Some(ref issuer) = standard.issuer {
+			claims["iss"] = json!(issuer);
+		}
+		if let Some(ref audience) = standard.audience {
+			claims["aud"] = json!(audience);
+		}
+		Ok(claims)
+	}
 }
 
 #[cfg(test)]
@@ -72,4 +330,84 @@ mod tests {
 		println!("{}", encoded);
 		// assert_eq!("eyJ0eXAiOiJKV1QiLCJhbGciOiJIUzI1NiJ9.InNvbWV0aGluZyI.9T-IJnr5l7oe5yWKhI8T95Iz1Ju8qPJEhAqOiIjab_w", encoded);
 	}
+	fn write_ec_key(file: &std::path::Path) {
+		let group =
+			openssl::ec::EcGroup::from_curve_name(openssl::nid::Nid::X9_62_PRIME256V1).unwrap();
+		let key = openssl::ec::EcKey::generate(&group).unwrap();
+		std::fs::write(file, key.private_key_to_pem().unwrap()).unwrap();
+	}
+	#[test]
+	fn test_pem_files() {
+		let file = std::env::temp_dir().join(format!("auth-gatekeeper-{}.pem", std::process::id()));
+		write_ec_key(&file);
+		let jwt = JWT::from_keys(JwtKeys::create(file.to_str().unwrap()).unwrap());
+		std::fs::remove_file(&file).unwrap();
+
+		let public = &jwt.jwks()["keys"][0];
+		assert_eq!("ES256", public["alg"]);
+		let encoded = jwt.encode_str(&json!({ "sub": "user" })).unwrap();
+		let header = jsonwebtoken::decode_header(&encoded).unwrap();
+		assert_eq!(public["kid"].as_str(), header.kid.as_deref());
+
+		let decoding_key = jsonwebtoken::DecodingKey::from_ec_components(
+			public["x"].as_str().unwrap(),
+			public["y"].as_str().unwrap(),
+		)
+		.unwrap();
+		let mut validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::ES256);
+		validation.required_spec_claims.clear();
+		let decoded = jsonwebtoken::decode::<JsonValue>(&encoded, &decoding_key, &validation);
+		assert_eq!("user", decoded.unwrap().claims["sub"]);
+	}
+	#[test]
+	fn test_reload_keys() {
+		let file =
+			std::env::temp_dir().join(format!("auth-gatekeeper-reload-{}.pem", std::process::id()));
+		write_ec_key(&file);
+		let keys = JwtKeys::create(file.to_str().unwrap()).unwrap();
+		let jwt = JWT::from_keys(keys.clone());
+		let kid = jwt.jwks()["keys"][0]["kid"].clone();
+
+		// A new key is used once reloaded
+		write_ec_key(&file);
+		keys.reload();
+		let public = jwt.jwks()["keys"][0].clone();
+		assert_ne!(kid, public["kid"]);
+		let encoded = jwt.encode_str(&json!({ "sub": "user" })).unwrap();
+		let header = jsonwebtoken::decode_header(&encoded).unwrap();
+		assert_eq!(public["kid"].as_str(), header.kid.as_deref());
+
+		// An invalid file keeps the current key
+		std::fs::write(&file, "invalid").unwrap();
+		keys.reload();
+		std::fs::remove_file(&file).unwrap();
+		assert_eq!(public, jwt.jwks()["keys"][0]);
+	}
+	#[test]
+	fn test_standard_claims() {
+		let mut jwt = JWT::new(Some("my secret")).unwrap();
+		jwt.claims = Some(StandardClaims {
+			issuer: Some(String::from("gatekeeper")),
+			audience: Some(String::from("backend")),
+			expires_in: 60,
+			random: crate::util::crypto::Crypto::create_random(),
+		});
+		let userinfo = json!({ "sub": "user", "iss": "idp", "aud": "app", "exp": 1, "jti": "1" });
+		let claims = jwt.get_claims(&userinfo).unwrap();
+		assert_eq!(userinfo, claims["data"]);
+		assert_eq!(JsonValue::Null, claims["sub"]);
+		assert_eq!("gatekeeper", claims["iss"]);
+		assert_eq!("backend", claims["aud"]);
+		assert_eq!(
+			60,
+			claims["exp"].as_u64().unwrap() - claims["iat"].as_u64().unwrap()
+		);
+		assert_ne!("1", claims["jti"]);
+		assert_ne!(claims["jti"], jwt.get_claims(&json!({})).unwrap()["jti"]);
+		assert_eq!("token", jwt.get_claims(&"token").unwrap()["data"]);
+
+		// Without an audience, the one of the provider is not used
+		jwt.claims.as_mut().unwrap().audience = None;
+		assert_eq!(JsonValue::Null, jwt.get_claims(&userinfo).unwrap()["aud"]);
+	}
 }
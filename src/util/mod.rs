@@ -1,2 +1,5 @@
 pub mod crypto;
+pub mod jwk;
 pub mod jwt;
+pub mod replay;
+pub mod xmldsig;
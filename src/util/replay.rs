@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+pub type ReplayCachePtr = std::sync::Arc<ReplayCache>;
+
+///
+/// The ids already used (Ex: of the SAML assertions) until they expire, shared between the workers
+///
+#[derive(Default)]
+pub struct ReplayCache {
+	used: Mutex<HashMap<String, i64>>,
+}
+
+impl ReplayCache {
+	///
+	/// Create the shared cache
+	///
+	pub fn create() -> ReplayCachePtr {
+		std::sync::Arc::new(Self::default())
+	}
+	///
+	/// Record the id until it expires, returning false if it was already used
+	///
+	/// The expired ids are removed, as they are not accepted anymore
+	///
+	pub fn record(&self, id: &str, expires_at: i64, now: i64) -> bool {
+		let mut used = self.used.lock().unwrap();
+		used.retain(|_, expires| *expires > now);
+		if used.contains_key(id) {
+			return false;
+		}
+		used.insert(id.to_owned(), expires_at);
+		true
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[test]
+	fn test_record() {
+		let cache = ReplayCache::create();
+		assert!(cache.record("a1", 100, 0));
+		assert!(!cache.record("a1", 100, 50));
+		assert!(cache.record("a2", 100, 50));
+		// Expired, so it is removed
+		assert!(cache.record("a1", 200, 100));
+		assert!(!cache.record("a1", 200, 150));
+	}
+}
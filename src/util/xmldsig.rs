@@ -0,0 +1,405 @@
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Public};
+use openssl::sign::Verifier;
+use roxmltree::{Node, NodeId};
+use std::collections::HashMap;
+
+/// Namespace of the XML signatures
+pub const DSIG_NS: &str = "http://www.w3.org/2000/09/xmldsig#";
+/// Exclusive canonicalization, without comments
+const EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+/// Transform removing the signature from the signed element
+const ENVELOPED_SIGNATURE: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+
+///
+/// Verify the enveloped signature of the element (XML-DSig), using the public keys
+///
+/// Only the profile used by SAML is supported: a single reference to the element itself, the
+/// enveloped signature and the exclusive canonicalization transforms, and RSA signatures.
+/// Returns the reason when the signature is not valid.
+///
+pub fn verify_enveloped(element: Node, keys: &[PKey<Public>]) -> Result<(), &'static str> {
+	let signature = child(element, DSIG_NS, "Signature").ok_or("Missing the signature")?;
+	let signed_info =
+		child(signature, DSIG_NS, "SignedInfo").ok_or("Missing the signature signed info")?;
+	let canonicalization = child(signed_info, DSIG_NS, "CanonicalizationMethod")
+		.filter(|method| method.attribute("Algorithm") == Some(EXC_C14N))
+		.ok_or("Unsupported signature canonicalization")?;
+	let signature_digest = match child(signed_info, DSIG_NS, "SignatureMethod")
+		.and_then(|method| method.attribute("Algorithm"))
+	{
+		Some("http://www.w3.org/2001/04/xmldsig-more#rsa-sha256") => MessageDigest::sha256(),
+		Some("http://www.w3.org/2001/04/xmldsig-more#rsa-sha512") => MessageDigest::sha512(),
+		Some("http://www.w3.org/2000/09/xmldsig#rsa-sha1") => MessageDigest::sha1(),
+		_ => return Err("Unsupported signature method"),
+	};
+
+	// The single reference must be the element, so other elements are not trusted
+	let mut references = signed_info
+		.children()
+		.filter(|node| node.has_tag_name((DSIG_NS, "Reference")));
+	let reference = match (references.next(), references.next()) {
+		(Some(reference), None) => reference,
+		_ => return Err("The signature must have a single reference"),
+	};
+	let id = element
+		.attribute("ID")
+		.filter(|id| !id.is_empty())
+		.ok_or("Missing the id of the signed element")?;
+	if reference
+		.attribute("URI")
+		.and_then(|uri| uri.strip_prefix('#'))
+		!= Some(id)
+	{
+		return Err("The signature does not reference the element");
+	}
+	let mut enveloped = false;
+	let mut inclusive = None;
+	if let Some(transforms) = child(reference, DSIG_NS, "Transforms") {
+		for transform in transforms.children().filter(|node| node.is_element()) {
+			match transform.attribute("Algorithm") {
+				Some(ENVELOPED_SIGNATURE) => enveloped = true,
+				Some(EXC_C14N) => inclusive = Some(inclusive_prefixes(transform)),
+				_ => return Err("Unsupported signature transform"),
+			}
+		}
+	}
+	let inclusive = match inclusive {
+		Some(inclusive) if enveloped => inclusive,
+		_ => return Err("Unsupported signature transforms"),
+	};
+	let digest = match child(reference, DSIG_NS, "DigestMethod")
+		.and_then(|method| method.attribute("Algorithm"))
+	{
+		Some("http://www.w3.org/2001/04/xmlenc#sha256") => MessageDigest::sha256(),
+		Some("http://www.w3.org/2001/04/xmlenc#sha512") => MessageDigest::sha512(),
+		Some("http://www.w3.org/2000/09/xmldsig#sha1") => MessageDigest::sha1(),
+		_ => return Err("Unsupported signature digest"),
+	};
+
+	// Digest of the element without the signature
+	let digest_value = decode_base64(child(reference, DSIG_NS, "DigestValue"))?;
+	let canonical = canonicalize(element, &inclusive, Some(signature.id()));
+	let computed = openssl::hash::hash(digest, canonical.as_bytes())
+		.map_err(|_| "Could not digest the signed element")?;
+	if computed.as_ref() != digest_value.as_slice() {
+		return Err("The digest of the signed element does not match");
+	}
+
+	// Signature of the signed info, which has the digest
+	let signature_value = decode_base64(child(signature, DSIG_NS, "SignatureValue"))?;
+	let canonical = canonicalize(signed_info, &inclusive_prefixes(canonicalization), None);
+	let valid = keys.iter().any(|key| {
+		verify(
+			key,
+			signature_digest,
+			canonical.as_bytes(),
+			&signature_value,
+		)
+	});
+	if !valid {
+		return Err("Invalid signature");
+	}
+	Ok(())
+}
+
+///
+/// Canonicalize the element using the exclusive canonicalization, without comments
+///
+/// The namespaces are declared where they are used, and the prefixes of the inclusive list
+/// (`#default` for the default namespace) wherever they are in scope. The excluded node, like
+/// an enveloped signature, is removed
+///
+pub fn canonicalize(element: Node, inclusive: &[&str], exclude: Option<NodeId>) -> String {
+	let mut output = String::new();
+	write_element(element, &HashMap::new(), inclusive, exclude, &mut output);
+	output
+}
+
+///
+/// Get the text of the node, joining the text of its descendants
+///
+pub fn text_of(node: Node) -> String {
+	node.descendants()
+		.filter(|node| node.is_text())
+		.filter_map(|node| node.text())
+		.collect()
+}
+
+///
+/// Get the first child element with the name
+///
+pub fn child<'a, 'input>(
+	node: Node<'a, 'input>,
+	namespace: &str,
+	name: &str,
+) -> Option<Node<'a, 'input>> {
+	node.children()
+		.find(|node| node.has_tag_name((namespace, name)))
+}
+
+///
+/// Write the canonical element. The rendered namespaces are the ones declared by the ancestors
+/// in the output
+///
+fn write_element(
+	element: Node,
+	rendered: &HashMap<String, String>,
+	inclusive: &[&str],
+	exclude: Option<NodeId>,
+	output: &mut String,
+) {
+	let input = element.document().input_text();
+	let name = qualified_name(&input[element.range().start + 1..]);
+
+	// The prefixes used by the element and its attributes, and the inclusive ones in scope
+	let mut prefixes = vec![prefix_of(name)];
+	let mut attributes = Vec::new();
+	for attribute in element.attributes() {
+		let qname = &input[attribute.range_qname()];
+		if qname.contains(':') {
+			prefixes.push(prefix_of(qname));
+		}
+		attributes.push((
+			attribute.namespace().unwrap_or(""),
+			attribute.name(),
+			qname,
+			attribute.value(),
+		));
+	}
+	for prefix in inclusive {
+		let prefix = if *prefix == "#default" { "" } else { prefix };
+		if element.lookup_namespace_uri(to_prefix(prefix)).is_some() {
+			prefixes.push(prefix);
+		}
+	}
+	let mut rendered = rendered.clone();
+	let mut declarations = Vec::new();
+	for prefix in prefixes {
+		if prefix == "xml" {
+			continue;
+		}
+		let uri = element
+			.lookup_namespace_uri(to_prefix(prefix))
+			.unwrap_or("");
+		let current = rendered.get(prefix).map(|uri| uri.as_str()).unwrap_or("");
+		if current != uri {
+			declarations.push((prefix, uri));
+			rendered.insert(prefix.to_owned(), uri.to_owned());
+		}
+	}
+	declarations.sort_unstable();
+	attributes.sort_unstable_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+
+	output.push('<');
+	output.push_str(name);
+	for (prefix, uri) in declarations {
+		if prefix.is_empty() {
+			output.push_str(" xmlns=\"");
+		} else {
+			output.push_str(" xmlns:");
+			output.push_str(prefix);
+			output.push_str("=\"");
+		}
+		escape_attribute(uri, output);
+		output.push('"');
+	}
+	for (_, _, qname, value) in attributes {
+		output.push(' ');
+		output.push_str(qname);
+		output.push_str("=\"");
+		escape_attribute(value, output);
+		output.push('"');
+	}
+	output.push('>');
+	for node in element.children() {
+		if Some(node.id()) == exclude {
+			continue;
+		}
+		if node.is_element() {
+			write_element(node, &rendered, inclusive, exclude, output);
+		} else if node.is_text() {
+			escape_text(node.text().unwrap_or(""), output);
+		} else if let Some(pi) = node.pi() {
+			output.push_str("<?");
+			output.push_str(pi.target);
+			if let Some(value) = pi.value {
+				output.push(' ');
+				output.push_str(value);
+			}
+			output.push_str("?>");
+		}
+	}
+	output.push_str("</");
+	output.push_str(name);
+	output.push('>');
+}
+
+///
+/// Get the prefixes of the inclusive namespaces of the canonicalization
+///
+fn inclusive_prefixes<'a>(method: Node<'a, '_>) -> Vec<&'a str> {
+	child(method, EXC_C14N, "InclusiveNamespaces")
+		.and_then(|node| node.attribute("PrefixList"))
+		.map(|list| list.split_whitespace().collect())
+		.unwrap_or_default()
+}
+
+///
+/// Get the qualified name at the start of the tag
+///
+fn qualified_name(tag: &str) -> &str {
+	let end = tag
+		.find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+		.unwrap_or(tag.len());
+	&tag[..end]
+}
+
+fn prefix_of(qname: &str) -> &str {
+	qname
+		.split_once(':')
+		.map(|(prefix, _)| prefix)
+		.unwrap_or("")
+}
+
+fn to_prefix(prefix: &str) -> Option<&str> {
+	if prefix.is_empty() {
+		None
+	} else {
+		Some(prefix)
+	}
+}
+
+fn escape_attribute(value: &str, output: &mut String) {
+	for c in value.chars() {
+		match c {
+			'&' => output.push_str("&amp;"),
+			'<' => output.push_str("&lt;"),
+			'"' => output.push_str("&quot;"),
+			'\t' => output.push_str("&#x9;"),
+			'\n' => output.push_str("&#xA;"),
+			'\r' => output.push_str("&#xD;"),
+			c => output.push(c),
+		}
+	}
+}
+
+fn escape_text(value: &str, output: &mut String) {
+	for c in value.chars() {
+		match c {
+			'&' => output.push_str("&amp;"),
+			'<' => output.push_str("&lt;"),
+			'>' => output.push_str("&gt;"),
+			'\r' => output.push_str("&#xD;"),
+			c => output.push(c),
+		}
+	}
+}
+
+fn decode_base64(node: Option<Node>) -> Result<Vec<u8>, &'static str> {
+	let value: String = node
+		.map(text_of)
+		.ok_or("Missing a signature value")?
+		.split_whitespace()
+		.collect();
+	base64::decode(value).map_err(|_| "Invalid signature value")
+}
+
+fn verify(key: &PKey<Public>, digest: MessageDigest, data: &[u8], signature: &[u8]) -> bool {
+	let mut verifier = match Verifier::new(digest, key) {
+		Ok(verifier) => verifier,
+		Err(_) => return false,
+	};
+	verifier
+		.update(data)
+		.and_then(|_| verifier.verify(signature))
+		.unwrap_or(false)
+}
+
+///
+/// Sign the xml, whose signature has the `DIGEST` and `SIGNATURE` placeholders, for the tests
+///
+#[cfg(test)]
+pub fn sign_enveloped(xml: &str, key: &PKey<openssl::pkey::Private>) -> String {
+	fn signed_info<'a, 'input>(document: &'a roxmltree::Document<'input>) -> Node<'a, 'input> {
+		document
+			.descendants()
+			.find(|node| node.has_tag_name((DSIG_NS, "SignedInfo")))
+			.unwrap()
+	}
+	let document = roxmltree::Document::parse(xml).unwrap();
+	let signature = signed_info(&document).parent().unwrap();
+	let element = signature.parent().unwrap();
+	let canonical = canonicalize(element, &[], Some(signature.id()));
+	let digest = openssl::hash::hash(MessageDigest::sha256(), canonical.as_bytes()).unwrap();
+	let xml = xml.replace("DIGEST", &base64::encode(digest));
+
+	let document = roxmltree::Document::parse(&xml).unwrap();
+	let canonical = canonicalize(signed_info(&document), &[], None);
+	let mut signer = openssl::sign::Signer::new(MessageDigest::sha256(), key).unwrap();
+	signer.update(canonical.as_bytes()).unwrap();
+	xml.replace("SIGNATURE", &base64::encode(signer.sign_to_vec().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use openssl::pkey::Private;
+	use openssl::rsa::Rsa;
+
+	const SIGNED: &str = r##"<p:Response xmlns:p="urn:p" xmlns:a="urn:a" ID="r1"><a:Assertion ID="a1"><a:Subject>alice</a:Subject><ds:Signature xmlns:ds="http://www.w3.org/2000/09/xmldsig#"><ds:SignedInfo><ds:CanonicalizationMethod Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"/><ds:SignatureMethod Algorithm="http://www.w3.org/2001/04/xmldsig-more#rsa-sha256"/><ds:Reference URI="#a1"><ds:Transforms><ds:Transform Algorithm="http://www.w3.org/2000/09/xmldsig#enveloped-signature"/><ds:Transform Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"/></ds:Transforms><ds:DigestMethod Algorithm="http://www.w3.org/2001/04/xmlenc#sha256"/><ds:DigestValue>DIGEST</ds:DigestValue></ds:Reference></ds:SignedInfo><ds:SignatureValue>SIGNATURE</ds:SignatureValue></ds:Signature></a:Assertion></p:Response>"##;
+
+	fn verify_assertion(xml: &str, key: &PKey<Private>) -> Result<(), &'static str> {
+		let public = PKey::public_key_from_der(&key.public_key_to_der().unwrap()).unwrap();
+		let document = roxmltree::Document::parse(xml).unwrap();
+		let assertion = document.root_element().first_element_child().unwrap();
+		verify_enveloped(assertion, &[public])
+	}
+
+	#[test]
+	fn test_canonicalize() {
+		let xml = "<?xml version=\"1.0\"?>\n<r:Root xmlns:r=\"urn:root\" xmlns=\"urn:default\" xmlns:unused=\"urn:unused\" xmlns:a=\"urn:a\">\n  <a:Item b=\"2\" a:x=\"1\" xml:lang=\"en\" c='q\"&amp;&#9;'>text &amp; &lt; &gt; \"quote\"<!-- comment --><Empty/><?pi data?></a:Item>\n  <Other xmlns=\"\" attr=\"v\"><Inner xmlns=\"urn:other\"/></Other>\n</r:Root>";
+		let document = roxmltree::Document::parse(xml).unwrap();
+		let root = document.root_element();
+		// Same as `xmllint --exc-c14n`, without the comment
+		assert_eq!(
+			"<r:Root xmlns:r=\"urn:root\">\n  <a:Item xmlns:a=\"urn:a\" b=\"2\" c=\"q&quot;&amp;&#x9;\" xml:lang=\"en\" a:x=\"1\">text &amp; &lt; &gt; \"quote\"<Empty xmlns=\"urn:default\"></Empty><?pi data?></a:Item>\n  <Other attr=\"v\"><Inner xmlns=\"urn:other\"></Inner></Other>\n</r:Root>",
+			canonicalize(root, &[], None)
+		);
+		let item = root.first_element_child().unwrap();
+		assert_eq!(
+			"<a:Item xmlns=\"urn:default\" xmlns:a=\"urn:a\" xmlns:r=\"urn:root\" b=\"2\" c=\"q&quot;&amp;&#x9;\" xml:lang=\"en\" a:x=\"1\">text &amp; &lt; &gt; \"quote\"<Empty></Empty><?pi data?></a:Item>",
+			canonicalize(item, &["#default", "r", "missing"], None)
+		);
+	}
+
+	#[test]
+	fn test_verify_enveloped() {
+		let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+		let signed = sign_enveloped(SIGNED, &key);
+		assert_eq!(Ok(()), verify_assertion(&signed, &key));
+
+		let other = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+		assert_eq!(Err("Invalid signature"), verify_assertion(&signed, &other));
+		assert_eq!(
+			Err("The digest of the signed element does not match"),
+			verify_assertion(&signed.replace(">alice<", ">admin<"), &key)
+		);
+		// A comment does not change the signed text
+		assert_eq!(
+			Ok(()),
+			verify_assertion(&signed.replace(">alice<", ">al<!-- x -->ice<"), &key)
+		);
+		assert_eq!(
+			Err("The signature does not reference the element"),
+			verify_assertion(&signed.replace("ID=\"a1\"", "ID=\"a2\""), &key)
+		);
+		let start = SIGNED.find("<ds:Signature").unwrap();
+		let end = SIGNED.find("</ds:Signature>").unwrap() + "</ds:Signature>".len();
+		let unsigned = format!("{}{}", &SIGNED[..start], &SIGNED[end..]);
+		assert_eq!(
+			Err("Missing the signature"),
+			verify_assertion(&unsigned, &key)
+		);
+	}
+}